@@ -0,0 +1,32 @@
+-- version 1 -> 2, applied in one transaction with db_version updated by
+-- the app, see dblayer::MIGRATIONS
+
+-- sync asks for a confirmation if more than the percentage of collection
+-- entries are missing, e.g. the media is not mounted
+INSERT OR IGNORE INTO settings(setting_key, setting_value)
+    VALUES('sync_delete_threshold', '25');
+
+-- hierarchical tags keep the full path, e.g. 'places|france|nice' with
+-- parent_id of 'places|france'
+ALTER TABLE subject_tags ADD COLUMN parent_id INTEGER;
+
+-- sha256 of the file contents, hex; fs_mod_time and fs_size of the file
+-- when it was hashed
+CREATE TABLE file_hashes(id INTEGER PRIMARY KEY, dir_entry_id INTEGER NOT NULL UNIQUE,
+    hash_value TEXT NOT NULL, fs_mod_time INTEGER NOT NULL, fs_size INTEGER NOT NULL);
+CREATE INDEX file_hashes_hash_value ON file_hashes(hash_value);
+
+-- capture time from EXIF DateTimeOriginal or the movie header, UTC, or
+-- the camera clock if EXIF has no offset
+CREATE TABLE capture_times(id INTEGER PRIMARY KEY, dir_entry_id INTEGER NOT NULL UNIQUE,
+    date_time INTEGER NOT NULL);
+
+-- dimensions and camera metadata from EXIF/XMP, NULL if unknown;
+-- focal_length in mm, exposure_time in seconds, GPS in degrees and meters
+CREATE TABLE media_info(id INTEGER PRIMARY KEY, dir_entry_id INTEGER NOT NULL UNIQUE,
+    width INTEGER, height INTEGER, orientation INTEGER,
+    camera_make TEXT, camera_model TEXT, lens TEXT,
+    focal_length REAL, exposure_time REAL, f_number REAL, iso INTEGER,
+    latitude REAL, longitude REAL, altitude REAL);
+CREATE INDEX media_info_lens ON media_info(lens);
+CREATE INDEX media_info_position ON media_info(latitude, longitude);
@@ -0,0 +1,5 @@
+-- version 2 -> 3, see migration2.sql
+
+-- glob_patterns.regexp is the pattern text of the kind:
+-- 'regexp' (PCRE), 'glob' (e.g. '**/*.jpg') or 'suffix' (e.g. '.jpg')
+ALTER TABLE glob_patterns ADD COLUMN kind TEXT NOT NULL DEFAULT 'regexp';
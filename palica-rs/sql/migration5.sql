@@ -0,0 +1,5 @@
+-- version 4 -> 5, see dblayer::MIGRATIONS
+
+-- kind of directory entries a filter rule applies to: 'both', 'files' or
+-- 'dirs', directories excluded by a rule aren't walked
+ALTER TABLE glob_filter_to_pattern ADD COLUMN target TEXT NOT NULL DEFAULT 'both';
@@ -18,6 +18,9 @@
 use clap::Parser;
 use palica::cli;
 use palica::dblayer;
+use palica::dupes::DupesFormat;
+use palica::glob_filter::{PatternKind, RuleTarget};
+use palica::sync_report::ReportFormat;
 
 #[derive(Parser, Debug)]
 #[command(version, about, author)]
@@ -32,8 +35,28 @@ enum Command {
     Tree(TreeCommand),
     #[command(about = "Remove collection.")]
     Remove(RemoveCommand),
-    #[command(about = "Display file path (glob) filters.")]
-    Filters(FiltersCommand),
+    #[command(about = "Synchronize collection with the filesystem.")]
+    Sync(SyncCommand),
+    #[command(about = "Report differences between collections and the filesystem.")]
+    Status(StatusCommand),
+    #[command(about = "Store sha256 of collection files.")]
+    Hash(HashCommand),
+    #[command(about = "Display or change a setting.")]
+    Setting(SettingCommand),
+    #[command(about = "Manage subject tags.")]
+    Tag(TagCommand),
+    #[command(about = "Manage file path (glob) filters.")]
+    Filter(FilterCommand),
+    #[command(about = "Manage the patterns of file path filters.")]
+    Pattern(PatternCommand),
+    #[command(about = "Print paths of files matching an expression.")]
+    Select(SelectCommand),
+    #[command(about = "List identical files across collections.")]
+    Dupes(DupesCommand),
+    #[command(about = "Check the database integrity, optionally repair it.")]
+    Fsck(FsckCommand),
+    #[command(about = "Build and query the thumbnail database.")]
+    Thumbs(ThumbsCommand),
     #[command(about = "Read tags from files and sidecars.")]
     ReadTags(ReadTagsCommand),
     #[command(about = "Write tags from db to sidecars.")]
@@ -85,13 +108,349 @@ struct RemoveCommand {
 }
 
 #[derive(clap::Args, Debug)]
-struct FiltersCommand {}
+struct SyncCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(long = "verbose", short = 'v', help = "Print every change.")]
+    pub verbose: bool,
+    #[arg(long = "yes", help = "Do not ask for confirmations.")]
+    pub yes: bool,
+    #[arg(long = "report", help = "Print a report: jsonl, toml.")]
+    pub report: Option<ReportFormat>,
+    #[arg(long = "all", help = "Report unchanged entries too.")]
+    pub all: bool,
+    #[arg(long = "verify", help = "Compare file contents with stored hashes.")]
+    pub verify: bool,
+    #[arg(help = "Collection name.")]
+    pub name: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct HashCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(long = "verbose", short = 'v', help = "Print every hash.")]
+    pub verbose: bool,
+    #[arg(long = "force", help = "Hash unchanged files too.")]
+    pub force: bool,
+    #[arg(help = "Collection name.")]
+    pub name: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatusCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(
+        long = "format",
+        default_value = "jsonl",
+        help = "Report format: jsonl, toml."
+    )]
+    pub format: ReportFormat,
+    #[arg(long = "all", help = "Report unchanged entries too.")]
+    pub all: bool,
+    #[arg(long = "verify", help = "Compare file contents with stored hashes.")]
+    pub verify: bool,
+    #[arg(help = "Collection names, all collections if omitted.")]
+    pub names: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SettingCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(help = "Setting key, e.g. sync_delete_threshold.")]
+    pub key: String,
+    #[arg(help = "New value.")]
+    pub value: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct TagCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[command(subcommand)]
+    pub action: TagAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum TagAction {
+    #[command(about = "Create new tags.")]
+    Add {
+        #[arg(required = true, help = "Tag values.")]
+        tags: Vec<String>,
+    },
+    #[command(about = "Delete a tag and unassign it from all files.")]
+    Remove {
+        #[arg(long = "yes", help = "Do not ask for confirmations.")]
+        yes: bool,
+        #[arg(help = "Tag value.")]
+        tag: String,
+    },
+    #[command(about = "Rename a tag.")]
+    Rename {
+        #[arg(help = "Tag value.")]
+        tag: String,
+        #[arg(help = "New tag value.")]
+        new_tag: String,
+    },
+    #[command(about = "List all tags, or tags of a file.")]
+    List {
+        #[arg(help = "File path, collection:/relative/path.")]
+        path: Option<String>,
+    },
+    #[command(about = "List files tagged with a tag or its subtags.")]
+    Files {
+        #[arg(help = "Tag value, e.g. 'places' or 'places|france'.")]
+        tag: String,
+    },
+    #[command(about = "Assign a tag to files.")]
+    Assign {
+        #[arg(help = "Tag value.")]
+        tag: String,
+        #[arg(required = true, help = "File paths, collection:/relative/path.")]
+        paths: Vec<String>,
+    },
+    #[command(about = "Unassign a tag from files.")]
+    Unassign {
+        #[arg(help = "Tag value.")]
+        tag: String,
+        #[arg(required = true, help = "File paths, collection:/relative/path.")]
+        paths: Vec<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct SelectCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(
+        required = true,
+        help = "Expression, e.g. 'tag:places|france and not tag:family and date:2023-05..2023-07 and size>1M'. \
+        Terms: tag:, tagged, untagged, col:, path:, name:, mime:, lens:, camera:, gps:, date, taken, size; and, or, not, ( )."
+    )]
+    pub expr: Vec<String>,
+    #[arg(
+        long = "sort",
+        default_value = "path",
+        help = "Order: path, date (modification), taken (capture date or modification)."
+    )]
+    pub sort: dblayer::SelectOrder,
+}
+
+#[derive(clap::Args, Debug)]
+struct DupesCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(long = "by-size", help = "Compare sizes only, no hashes needed.")]
+    pub by_size: bool,
+    #[arg(long = "prefer", help = "Collection with the canonical files.")]
+    pub prefer: Option<String>,
+    #[arg(
+        long = "format",
+        default_value = "groups",
+        help = "Output: groups, plain (non-canonical paths), toml (vienik rich list)."
+    )]
+    pub format: DupesFormat,
+}
+
+#[derive(clap::Args, Debug)]
+struct FsckCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(
+        long = "repair",
+        help = "Delete orphaned rows, entries outside of collections and collections without a root."
+    )]
+    pub repair: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ThumbsCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[command(subcommand)]
+    pub action: ThumbsAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ThumbsAction {
+    #[command(about = "Create a thumbnail database and register it as a profile.")]
+    Add {
+        #[arg(
+            long = "size",
+            default_value = "256x256",
+            help = "Maximum WIDTHxHEIGHT."
+        )]
+        size: String,
+        #[arg(
+            long = "mime",
+            default_value = "image/jpeg",
+            help = "Thumbnail format."
+        )]
+        mime: String,
+        #[arg(
+            long = "cmd-line",
+            help = "Command with {input}, {output}, {max_width}, {max_height}, produced in-process if omitted."
+        )]
+        cmd_line: Option<String>,
+        #[arg(help = "Profile name, e.g. small-256-jxl.")]
+        name: String,
+        #[arg(help = "Thumbnail database filename.")]
+        path: String,
+    },
+    #[command(about = "List thumbnail profiles.")]
+    List,
+    #[command(about = "Unregister a thumbnail profile, its database file is kept.")]
+    Remove {
+        #[arg(help = "Profile name.")]
+        name: String,
+    },
+    #[command(about = "Create thumbnails of new and changed images.")]
+    Build {
+        #[arg(long = "verbose", short = 'v', help = "Print every new thumbnail.")]
+        verbose: bool,
+        #[arg(help = "Profile name.")]
+        profile: String,
+        #[arg(help = "Collection names, all collections if omitted.")]
+        names: Vec<String>,
+    },
+    #[command(about = "Write a thumbnail to a file or stdout.")]
+    Get {
+        #[arg(long = "output", short = 'o', help = "Output filename.")]
+        output: Option<String>,
+        #[arg(help = "Profile name.")]
+        profile: String,
+        #[arg(help = "File path, collection:/relative/path.")]
+        path: String,
+    },
+    #[command(about = "Delete thumbnails of files missing from the database.")]
+    Prune {
+        #[arg(help = "Profile name, all profiles if omitted.")]
+        profile: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct FilterCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[command(subcommand)]
+    pub action: FilterAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum FilterAction {
+    #[command(about = "List filters.")]
+    List,
+    #[command(about = "Print the rules of a filter in the order of application.")]
+    Show {
+        #[arg(help = "Filter name or id.")]
+        filter: String,
+    },
+    #[command(about = "Create an empty filter, it excludes every file.")]
+    Create {
+        #[arg(help = "Filter name.")]
+        name: String,
+    },
+    #[command(about = "Delete a filter not used by collections.")]
+    Delete {
+        #[arg(help = "Filter name or id.")]
+        filter: String,
+    },
+    #[command(about = "Add a rule, the last matching rule includes or excludes a file.")]
+    AddRule {
+        #[arg(long = "exclude", help = "Exclude the matching files.")]
+        exclude: bool,
+        #[arg(
+            long = "target",
+            default_value = "both",
+            help = "Entries the rule applies to: both, files or dirs, excluded directories aren't walked."
+        )]
+        target: RuleTarget,
+        #[arg(long = "at", help = "Rule number, the rule is appended if omitted.")]
+        at: Option<usize>,
+        #[arg(help = "Filter name or id.")]
+        filter: String,
+        #[arg(help = "Pattern id.")]
+        pattern_id: i64,
+    },
+    #[command(about = "Print whether the filter includes paths and the deciding rule.")]
+    Test {
+        #[arg(help = "Filter name or id.")]
+        filter: String,
+        #[arg(
+            help = "Full file paths as scanned by add and sync, stdin lines if omitted, a trailing '/' marks a directory."
+        )]
+        paths: Vec<String>,
+    },
+    #[command(about = "Remove a rule.")]
+    RemoveRule {
+        #[arg(help = "Filter name or id.")]
+        filter: String,
+        #[arg(help = "Rule number.")]
+        rule: usize,
+    },
+    #[command(about = "Move a rule to another number.")]
+    MoveRule {
+        #[arg(help = "Filter name or id.")]
+        filter: String,
+        #[arg(help = "Rule number.")]
+        rule: usize,
+        #[arg(help = "New rule number.")]
+        to: usize,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct PatternCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[command(subcommand)]
+    pub action: PatternAction,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum PatternAction {
+    #[command(about = "List patterns.")]
+    List,
+    #[command(about = "Add a pattern, prints its id.")]
+    Add {
+        #[arg(
+            long = "kind",
+            default_value = "glob",
+            help = "Pattern kind: glob (e.g. '**/*.{jpg,JPG}'), regexp (e.g. '(?i)\\.jpe?g$'), suffix (e.g. '.jpg')."
+        )]
+        kind: PatternKind,
+        #[arg(help = "Pattern matched against the full file path.")]
+        text: String,
+    },
+    #[command(about = "Delete a pattern not used by filters.")]
+    Delete {
+        #[arg(help = "Pattern id.")]
+        pattern_id: i64,
+    },
+}
 
 #[derive(clap::Args, Debug)]
-struct ReadTagsCommand {}
+struct ReadTagsCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(long = "dry", help = "Only report differences, do not assign tags.")]
+    pub dry: bool,
+    #[arg(help = "Collection name.")]
+    pub name: String,
+}
 
 #[derive(clap::Args, Debug)]
-struct WriteTagsCommand {}
+struct WriteTagsCommand {
+    #[arg(long = "db", help = "Database filename.")]
+    pub db_file_name: String,
+    #[arg(long = "dry", help = "Only print sidecars to be written.")]
+    pub dry: bool,
+    #[arg(help = "Collection name.")]
+    pub name: String,
+}
 
 #[derive(clap::Args, Debug)]
 struct CreateDbCommand {
@@ -101,7 +460,7 @@ struct CreateDbCommand {
 
 fn main() -> anyhow::Result<()> {
     let parsed = Command::parse();
-    println!("{:?}", parsed);
+    eprintln!("{:?}", parsed);
     match parsed {
         Command::Add(c) => cli::collection_add(cli::CollectionAdd {
             db_file_name: c.db_file_name,
@@ -116,8 +475,109 @@ fn main() -> anyhow::Result<()> {
         Command::Tree(c) => cli::collection_tree(&c.db_file_name, &c.name)?,
         Command::CreateDb(c) => cli::create_db(&c.db_file_name)?,
         Command::Remove(c) => cli::collection_remove(&c.db_file_name, &c.name)?,
-        // TODO
-        _ => todo!(),
+        Command::Sync(c) => cli::collection_sync(cli::CollectionSync {
+            db_file_name: c.db_file_name,
+            verbose: c.verbose,
+            yes: c.yes,
+            name: c.name,
+            report: c.report,
+            report_all: c.all,
+            verify_content: c.verify,
+        })?,
+        Command::Tag(c) => {
+            let db = &c.db_file_name;
+            match c.action {
+                TagAction::Add { tags } => cli::tag_add(db, &tags)?,
+                TagAction::Remove { yes, tag } => cli::tag_remove(db, &tag, yes)?,
+                TagAction::Rename { tag, new_tag } => cli::tag_rename(db, &tag, &new_tag)?,
+                TagAction::List { path } => cli::tag_list(db, path.as_deref())?,
+                TagAction::Files { tag } => cli::tag_files(db, &tag)?,
+                TagAction::Assign { tag, paths } => cli::tag_assign(db, &tag, &paths)?,
+                TagAction::Unassign { tag, paths } => cli::tag_unassign(db, &tag, &paths)?,
+            }
+        }
+        Command::Setting(c) => cli::setting(&c.db_file_name, &c.key, c.value.as_deref())?,
+        Command::Status(c) => cli::collection_status(cli::CollectionStatusArgs {
+            db_file_name: c.db_file_name,
+            names: c.names,
+            format: c.format,
+            report_all: c.all,
+            verify_content: c.verify,
+        })?,
+        Command::Hash(c) => cli::collection_hash(&c.db_file_name, &c.name, c.force, c.verbose)?,
+        Command::Select(c) => cli::select(&c.db_file_name, &c.expr.join(" "), c.sort)?,
+        Command::Dupes(c) => cli::dupes(cli::DupesArgs {
+            db_file_name: c.db_file_name,
+            by_size: c.by_size,
+            prefer: c.prefer,
+            format: c.format,
+        })?,
+        Command::Fsck(c) => cli::fsck(&c.db_file_name, c.repair)?,
+        Command::Thumbs(c) => {
+            let db = &c.db_file_name;
+            match c.action {
+                ThumbsAction::Add {
+                    size,
+                    mime,
+                    cmd_line,
+                    name,
+                    path,
+                } => cli::thumbs_add(cli::ThumbsAddArgs {
+                    db_file_name: c.db_file_name.clone(),
+                    name,
+                    fs_path: path,
+                    size,
+                    mime,
+                    cmd_line,
+                })?,
+                ThumbsAction::List => cli::thumbs_list(db)?,
+                ThumbsAction::Remove { name } => cli::thumbs_remove(db, &name)?,
+                ThumbsAction::Build {
+                    verbose,
+                    profile,
+                    names,
+                } => cli::thumbs_build(db, &profile, &names, verbose)?,
+                ThumbsAction::Get {
+                    output,
+                    profile,
+                    path,
+                } => cli::thumbs_get(db, &profile, &path, output.as_deref())?,
+                ThumbsAction::Prune { profile } => cli::thumbs_prune(db, profile.as_deref())?,
+            }
+        }
+        Command::Filter(c) => {
+            let db = &c.db_file_name;
+            match c.action {
+                FilterAction::List => cli::filter_list(db)?,
+                FilterAction::Show { filter } => cli::filter_show(db, &filter)?,
+                FilterAction::Create { name } => cli::filter_create(db, &name)?,
+                FilterAction::Delete { filter } => cli::filter_delete(db, &filter)?,
+                FilterAction::AddRule {
+                    exclude,
+                    target,
+                    at,
+                    filter,
+                    pattern_id,
+                } => cli::filter_add_rule(db, &filter, pattern_id, !exclude, target, at)?,
+                FilterAction::Test { filter, paths } => cli::filter_test(db, &filter, &paths)?,
+                FilterAction::RemoveRule { filter, rule } => {
+                    cli::filter_remove_rule(db, &filter, rule)?
+                }
+                FilterAction::MoveRule { filter, rule, to } => {
+                    cli::filter_move_rule(db, &filter, rule, to)?
+                }
+            }
+        }
+        Command::Pattern(c) => {
+            let db = &c.db_file_name;
+            match c.action {
+                PatternAction::List => cli::pattern_list(db)?,
+                PatternAction::Add { kind, text } => cli::pattern_add(db, kind, &text)?,
+                PatternAction::Delete { pattern_id } => cli::pattern_delete(db, pattern_id)?,
+            }
+        }
+        Command::ReadTags(c) => cli::read_tags(&c.db_file_name, &c.name, c.dry)?,
+        Command::WriteTags(c) => cli::write_tags(&c.db_file_name, &c.name, c.dry)?,
     };
     Ok(())
 }
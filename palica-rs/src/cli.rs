@@ -19,9 +19,24 @@ use anyhow::Error;
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 use crate::coll_builder;
+use crate::dblayer;
 use crate::dblayer::read;
 use crate::dblayer::write;
+use crate::dblayer::Collection;
 use crate::dblayer::DbId;
+use crate::dblayer::DirEntry;
+use crate::dblayer::GlobFilter;
+use crate::dblayer::SubjectTag;
+use crate::dupes::{self, DupesFormat};
+use crate::fsck;
+use crate::glob_filter::{PatternKind, RuleTarget};
+use crate::hashing;
+use crate::query;
+use crate::sync;
+use crate::sync_report::{CollectionStatus, EntryStatus, ReportFormat, ReportWriter};
+use crate::tags;
+use crate::thumbnails;
+use crate::vpath;
 use std::path::Path;
 
 enum YesNo {
@@ -31,7 +46,7 @@ enum YesNo {
 
 fn ask_confirmation(msg: &str) -> Result<YesNo, std::io::Error> {
     loop {
-        println!("{msg}(y/n)");
+        eprintln!("{msg}(y/n)");
         let mut answer = String::new();
         std::io::stdin().read_line(&mut answer)?;
         match answer.as_str() {
@@ -87,14 +102,14 @@ pub fn collection_add(args: CollectionAdd) -> anyhow::Result<()> {
     let norm_path = crate::fslayer::read::normalized_abspath(&args.path);
     check_with_existing_paths(&rdb, &norm_path)?;
 
-    let mut filter = rdb.glob_filter_by_id(args.filter_id)?;
+    let filter = rdb.glob_filter_by_id(args.filter_id)?;
     let mut wdb = write::Db::new(&conn)?;
     coll_builder::new_collection(
         &mut wdb,
         &args.name,
         &Path::new(&norm_path),
         args.filter_id,
-        &mut filter,
+        &filter,
         &|e| {
             println!("new entry {:?}", &e);
         },
@@ -102,6 +117,228 @@ pub fn collection_add(args: CollectionAdd) -> anyhow::Result<()> {
     Ok(())
 }
 
+pub struct CollectionSync {
+    pub db_file_name: String,
+    pub verbose: bool,
+    /// do not ask to confirm mass deletions
+    pub yes: bool,
+    pub name: String,
+    pub report: Option<ReportFormat>,
+    /// report unchanged entries too
+    pub report_all: bool,
+    /// compare file contents with stored hashes
+    pub verify_content: bool,
+}
+
+fn sync_with_report(
+    conn: &sqlite::Connection,
+    col: &Collection,
+    options: &sync::SyncOptions,
+    report: Option<&ReportWriter>,
+    verbose: bool,
+) -> anyhow::Result<sync::SyncStats> {
+    let rdb = read::Db::new(conn)?;
+    let available = Path::new(&col.fs_path).is_dir();
+    if let Some(report) = report {
+        report.collection(CollectionStatus {
+            collection: col.coll_name.clone(),
+            fs_path: col.fs_path.clone(),
+            available,
+        });
+    }
+    if !available {
+        return Err(anyhow::Error::msg(format!(
+            "Collection '{}' is not available at '{}'!",
+            col.coll_name, col.fs_path
+        )));
+    }
+
+    let filter = rdb.glob_filter_by_id(col.glob_filter_id)?;
+    let mut wdb = write::Db::new(conn)?;
+    let stats = sync::sync_collection(&mut wdb, col, &filter, options, &|path, change| {
+        if verbose {
+            let action = match change {
+                sync::SyncChange::Added(_) => Some("added"),
+                sync::SyncChange::Compared { result, .. } => {
+                    if *result == sync::DbFsCompareResult::Same {
+                        None
+                    } else {
+                        Some("updated")
+                    }
+                }
+                sync::SyncChange::Deleted(_) => Some("deleted"),
+                sync::SyncChange::Moved { .. } => Some("moved"),
+            };
+            if let Some(action) = action {
+                eprintln!("{action} {}", path.to_string_lossy());
+            }
+        }
+        if let Some(report) = report {
+            report.entry(EntryStatus::from_change(col, path, change));
+        }
+    })?;
+    Ok(stats)
+}
+
+pub fn collection_sync(args: CollectionSync) -> anyhow::Result<()> {
+    let conn = read::open_existing(&args.db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let col = rdb.collection_by_name(&args.name)?;
+    if col.is_none() {
+        return Err(anyhow::Error::msg(format!(
+            "No such collection: '{}'!",
+            args.name
+        )));
+    }
+    let col = col.unwrap();
+
+    let threshold = match rdb.setting_value(dblayer::SYNC_DELETE_THRESHOLD_KEY)? {
+        Some(value) => value.parse::<u32>().with_context(|| {
+            format!(
+                "Bad '{}' setting value '{value}'.",
+                dblayer::SYNC_DELETE_THRESHOLD_KEY
+            )
+        })?,
+        None => sync::DEFAULT_DELETE_THRESHOLD_PERCENT,
+    };
+    let mut options = sync::SyncOptions {
+        dry: false,
+        delete_threshold_percent: if args.yes { None } else { Some(threshold) },
+        verify_content: args.verify_content,
+    };
+
+    // a rolled back attempt is not reported, see ReportWriter
+    let new_report = || {
+        args.report
+            .map(|format| ReportWriter::new(format, args.report_all))
+    };
+    let mut report = new_report();
+    let stats = match sync_with_report(&conn, &col, &options, report.as_ref(), args.verbose) {
+        Ok(stats) => stats,
+        Err(e) => {
+            let too_many = match e.downcast_ref::<sync::SyncError>() {
+                Some(sync::SyncError::TooManyDeletions { deleted, total }) => {
+                    Some((*deleted, *total))
+                }
+                _ => None,
+            };
+            let Some((deleted, total)) = too_many else {
+                return Err(e);
+            };
+            let msg = format!(
+                "{deleted} of {total} entries of collection '{}' are missing at '{}'. \
+                Is the right media mounted? Delete them from the db?",
+                col.coll_name, col.fs_path
+            );
+            match ask_confirmation(&msg)? {
+                YesNo::Yes => (),
+                YesNo::No => return Err(Error::msg("Canceled.")),
+            }
+            options.delete_threshold_percent = None;
+            report = new_report();
+            sync_with_report(&conn, &col, &options, report.as_ref(), args.verbose)?
+        }
+    };
+    match report {
+        Some(report) => report.finish()?,
+        None => println!(
+            "Synced collection '{}': {} added, {} updated, {} deleted, {} moved.",
+            col.coll_name, stats.added, stats.updated, stats.deleted, stats.moved
+        ),
+    }
+    if stats.deleted > 0 || stats.moved > 0 {
+        thumbnails::prune_profiles(&conn)?;
+    }
+    Ok(())
+}
+
+/// Stores sha256 of the collection files changed since the last hashing,
+/// or of all files if `force`.
+pub fn collection_hash(
+    db_file_name: &str,
+    col_name: &str,
+    force: bool,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let col = rdb
+        .collection_by_name(col_name)?
+        .ok_or_else(|| Error::msg(format!("No such collection: '{col_name}'!")))?;
+    let stats = hashing::hash_collection(&conn, &col, force, &|path, hash_value| {
+        if verbose {
+            eprintln!("{hash_value} {}", path.to_string_lossy());
+        }
+    })?;
+    println!(
+        "Hashed collection '{}': {} hashed, {} unchanged, {} failed.",
+        col.coll_name, stats.hashed, stats.skipped, stats.failed
+    );
+    Ok(())
+}
+
+pub struct CollectionStatusArgs {
+    pub db_file_name: String,
+    /// all collections if empty
+    pub names: Vec<String>,
+    pub format: ReportFormat,
+    pub report_all: bool,
+    pub verify_content: bool,
+}
+
+/// Dry run of sync for the collections, prints the report only.
+pub fn collection_status(args: CollectionStatusArgs) -> anyhow::Result<()> {
+    let conn = read::open_existing(&args.db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let cols = if args.names.is_empty() {
+        rdb.enum_collections()?
+    } else {
+        let mut cols = Vec::new();
+        for name in &args.names {
+            match rdb.collection_by_name(name)? {
+                Some(col) => cols.push(col),
+                None => return Err(anyhow::Error::msg(format!("No such collection: '{name}'!"))),
+            }
+        }
+        cols
+    };
+
+    let report = ReportWriter::new(args.format, args.report_all);
+    let options = sync::SyncOptions {
+        dry: true,
+        verify_content: args.verify_content,
+        ..Default::default()
+    };
+    for col in cols {
+        // unavailable collections are already marked in the report
+        if let Err(e) = sync_with_report(&conn, &col, &options, Some(&report), false) {
+            eprintln!("{e}");
+        }
+    }
+    report.finish()
+}
+
+/// Prints the value if `value` is None, otherwise sets it.
+pub fn setting(db_file_name: &str, key: &str, value: Option<&str>) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    match value {
+        Some(value) => {
+            let wdb = write::Db::new(&conn)?;
+            let mut tx = dblayer::Transaction::new(&conn);
+            wdb.set_setting_value(key, value)?;
+            tx.commit();
+        }
+        None => {
+            let rdb = read::Db::new(&conn)?;
+            match rdb.setting_value(key)? {
+                Some(value) => println!("{value}"),
+                None => return Err(Error::msg(format!("No such setting: '{key}'!"))),
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn collection_list(db_file_name: &str) -> anyhow::Result<()> {
     let conn = read::open_existing(db_file_name)?;
     let rdb = read::Db::new(&conn)?;
@@ -199,8 +436,11 @@ pub fn collection_remove(db_file_name: &str, col_name: &str) -> anyhow::Result<(
     match ask_confirmation(&format!("Delete collection {col_name}?")) {
         Ok(YesNo::Yes) => {
             let wdb = write::Db::new(&conn)?;
+            let mut tx = dblayer::Transaction::new(&conn);
             wdb.delete_collection(col)?;
+            tx.commit();
             println!("Deleted collection '{col_name}'.");
+            thumbnails::prune_profiles(&conn)?;
             return Ok(());
         }
         Err(e) => return Err(e.into()),
@@ -208,3 +448,684 @@ pub fn collection_remove(db_file_name: &str, col_name: &str) -> anyhow::Result<(
     };
     Err(Error::msg("Canceled."))
 }
+
+fn entry_by_vpath(rdb: &read::Db, v_path: &str) -> anyhow::Result<DirEntry> {
+    let (col_name, rel_path) = vpath::parse(v_path).ok_or_else(|| {
+        Error::msg(format!(
+            "Bad path '{v_path}', expected 'collection:/relative/path'!"
+        ))
+    })?;
+    let col = rdb
+        .collection_by_name(col_name)?
+        .ok_or_else(|| Error::msg(format!("No such collection: '{col_name}'!")))?;
+    rdb.dir_entry_by_path(col.root_id, rel_path)?
+        .ok_or_else(|| Error::msg(format!("No such entry: '{v_path}'!")))
+}
+
+fn tag_by_value(rdb: &read::Db, tag_value: &str) -> anyhow::Result<SubjectTag> {
+    rdb.subject_tag_by_value(tag_value)?
+        .ok_or_else(|| Error::msg(format!("No such tag: '{tag_value}'!")))
+}
+
+fn check_tag_value(tag_value: &str) -> anyhow::Result<()> {
+    if SubjectTag::is_valid_value(tag_value) {
+        Ok(())
+    } else {
+        Err(Error::msg(format!(
+            "Bad tag '{tag_value}', expected non-empty levels, e.g. 'places|france|nice'!"
+        )))
+    }
+}
+
+/// Rewrites sidecars of the entries if the 'update_xmp' setting is on.
+fn update_sidecars(conn: &sqlite::Connection, entry_ids: &[DbId]) -> anyhow::Result<()> {
+    if tags::update_xmp_enabled(&read::Db::new(conn)?)? {
+        tags::write_entries_sidecars(conn, entry_ids, &|sidecar| {
+            eprintln!("Updated '{}'.", sidecar.to_string_lossy());
+        })?;
+    }
+    Ok(())
+}
+
+pub fn tag_add(db_file_name: &str, tag_values: &[String]) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let wdb = write::Db::new(&conn)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    for tag_value in tag_values {
+        check_tag_value(tag_value)?;
+        if rdb.subject_tag_by_value(tag_value)?.is_some() {
+            eprintln!("Tag '{tag_value}' already exists.");
+            continue;
+        }
+        wdb.create_subject_tag(tag_value)?;
+    }
+    tx.commit();
+    Ok(())
+}
+
+pub fn tag_remove(db_file_name: &str, tag_value: &str, yes: bool) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let tag = tag_by_value(&rdb, tag_value)?;
+    let entry_ids = rdb.entries_with_tag_subtree(tag.id)?;
+    let count = entry_ids.len();
+    if count > 0 && !yes {
+        let msg =
+            format!("Tag '{tag_value}' and its subtags are assigned to {count} entries, delete?");
+        if let YesNo::No = ask_confirmation(&msg)? {
+            return Err(Error::msg("Canceled."));
+        }
+    }
+    let wdb = write::Db::new(&conn)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    wdb.delete_subject_tag(tag.id)?;
+    tx.commit();
+    update_sidecars(&conn, &entry_ids)
+}
+
+pub fn tag_rename(db_file_name: &str, tag_value: &str, new_value: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let tag = tag_by_value(&rdb, tag_value)?;
+    if rdb.subject_tag_by_value(new_value)?.is_some() {
+        return Err(Error::msg(format!("Tag '{new_value}' already exists!")));
+    }
+    check_tag_value(new_value)?;
+    if new_value.starts_with(&format!("{tag_value}{}", dblayer::TAG_PATH_SEPARATOR)) {
+        return Err(Error::msg(format!(
+            "Can't move '{tag_value}' below itself!"
+        )));
+    }
+    let wdb = write::Db::new(&conn)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    wdb.rename_subject_tag(tag.id, new_value)?;
+    tx.commit();
+    update_sidecars(&conn, &rdb.entries_with_tag_subtree(tag.id)?)
+}
+
+/// Lists all tags with usage counts, or tags of the entry.
+pub fn tag_list(db_file_name: &str, v_path: Option<&str>) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    match v_path {
+        Some(v_path) => {
+            let entry = entry_by_vpath(&rdb, v_path)?;
+            for tag in rdb.tags_of_dir_entry(entry.id)? {
+                println!("{}", tag.tag_value);
+            }
+        }
+        None => {
+            for tag in rdb.enum_subject_tags()? {
+                println!("{}\t{}", tag.tag_value, rdb.tag_usage_count(tag.id)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints paths of the entries tagged with the tag or its subtags.
+pub fn tag_files(db_file_name: &str, tag_value: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let tag = tag_by_value(&rdb, tag_value)?;
+    for entry_id in rdb.entries_with_tag_subtree(tag.id)? {
+        if let Some((col, rel_path)) = rdb.entry_location(entry_id)? {
+            println!("{}", vpath::from_rel_path(&col.coll_name, &rel_path));
+        }
+    }
+    Ok(())
+}
+
+pub fn tag_assign(db_file_name: &str, tag_value: &str, v_paths: &[String]) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let tag = tag_by_value(&rdb, tag_value)?;
+    let wdb = write::Db::new(&conn)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    let mut entry_ids = Vec::new();
+    for v_path in v_paths {
+        let entry = entry_by_vpath(&rdb, v_path)?;
+        wdb.assign_tag(tag.id, entry.id)?;
+        entry_ids.push(entry.id);
+    }
+    tx.commit();
+    update_sidecars(&conn, &entry_ids)
+}
+
+pub fn tag_unassign(db_file_name: &str, tag_value: &str, v_paths: &[String]) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let tag = tag_by_value(&rdb, tag_value)?;
+    let wdb = write::Db::new(&conn)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    let mut entry_ids = Vec::new();
+    for v_path in v_paths {
+        let entry = entry_by_vpath(&rdb, v_path)?;
+        wdb.unassign_tag(tag.id, entry.id)?;
+        entry_ids.push(entry.id);
+    }
+    tx.commit();
+    update_sidecars(&conn, &entry_ids)
+}
+
+/// Merges tags from the collection sidecars into the db, prints tags found
+/// on one side only.
+pub fn read_tags(db_file_name: &str, col_name: &str, dry: bool) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let col = rdb
+        .collection_by_name(col_name)?
+        .ok_or_else(|| Error::msg(format!("No such collection: '{col_name}'!")))?;
+    let assigned = tags::read_sidecar_tags(&conn, &col, dry, &|diff| {
+        for tag in &diff.xmp_only {
+            println!("xmp-only\t{}\t{tag}", diff.v_path);
+        }
+        for tag in &diff.db_only {
+            println!("db-only\t{}\t{tag}", diff.v_path);
+        }
+    })?;
+    eprintln!("Assigned {assigned} tags.");
+    Ok(())
+}
+
+/// Creates or updates sidecars of the collection files from the db tags,
+/// prints the written sidecar paths.
+pub fn write_tags(db_file_name: &str, col_name: &str, dry: bool) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let col = rdb
+        .collection_by_name(col_name)?
+        .ok_or_else(|| Error::msg(format!("No such collection: '{col_name}'!")))?;
+    let written = tags::write_sidecar_tags(&conn, &col, dry, &|sidecar| {
+        println!("{}", sidecar.to_string_lossy());
+    })?;
+    eprintln!("Written {written} sidecars.");
+    Ok(())
+}
+
+/// By name, or by id if there's no such name.
+fn filter_by_name(rdb: &read::Db, filter: &str) -> anyhow::Result<GlobFilter> {
+    if let Some(found) = rdb.glob_filter_by_name(filter)? {
+        return Ok(found);
+    }
+    let found = match filter.parse::<DbId>() {
+        Ok(id) => rdb.glob_filter(id)?,
+        Err(_) => None,
+    };
+    found.ok_or_else(|| Error::msg(format!("No such filter: '{filter}'!")))
+}
+
+/// (pattern id, include, target) in the order of application.
+fn filter_rules(rdb: &read::Db, filter_id: DbId) -> anyhow::Result<Vec<(DbId, bool, RuleTarget)>> {
+    Ok(rdb
+        .filter_patterns(filter_id)?
+        .iter()
+        .map(|r| (r.glob_pattern_id, r.include, r.target))
+        .collect())
+}
+
+/// Rule numbers start at 1, as printed by filter_show.
+fn rule_index<T>(rules: &[T], rule: usize) -> anyhow::Result<usize> {
+    if rule == 0 || rule > rules.len() {
+        return Err(Error::msg(format!(
+            "No such rule: {rule}, the filter has {} rules!",
+            rules.len()
+        )));
+    }
+    Ok(rule - 1)
+}
+
+pub fn filter_list(db_file_name: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    for filter in rdb.enum_glob_filters()? {
+        println!("{},{}", filter.id, filter.name);
+    }
+    Ok(())
+}
+
+/// Prints the rules as 'number include|exclude both|files|dirs pattern_id
+/// kind text', tab separated, and the collections using the filter to stderr.
+pub fn filter_show(db_file_name: &str, filter: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let filter = filter_by_name(&rdb, filter)?;
+    for (i, (pattern_id, include, target)) in filter_rules(&rdb, filter.id)?.iter().enumerate() {
+        let (kind, text) = match rdb.glob_pattern(*pattern_id)? {
+            Some(p) => (p.kind.as_str(), p.regexp),
+            None => ("-", String::new()),
+        };
+        let action = if *include { "include" } else { "exclude" };
+        println!(
+            "{}\t{action}\t{}\t{pattern_id}\t{kind}\t{text}",
+            i + 1,
+            target.as_str()
+        );
+    }
+    for col in rdb.collections_by_filter(filter.id)? {
+        eprintln!("Used by collection '{}'.", col.coll_name);
+    }
+    Ok(())
+}
+
+pub fn filter_create(db_file_name: &str, name: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    if rdb.glob_filter_by_name(name)?.is_some() {
+        return Err(Error::msg(format!("Filter '{name}' already exists!")));
+    }
+    let mut tx = dblayer::Transaction::new(&conn);
+    let filter = write::Db::new(&conn)?.create_glob_filter(name)?;
+    tx.commit();
+    println!("{}", filter.id);
+    Ok(())
+}
+
+/// Fails if the filter is used by collections, see write::Db::delete_glob_filter.
+pub fn filter_delete(db_file_name: &str, filter: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let filter = filter_by_name(&rdb, filter)?;
+    write::Db::new(&conn)?.delete_glob_filter(&filter)?;
+    Ok(())
+}
+
+/// Inserts the rule before the rule number `at`, or appends it.
+pub fn filter_add_rule(
+    db_file_name: &str,
+    filter: &str,
+    pattern_id: DbId,
+    include: bool,
+    target: RuleTarget,
+    at: Option<usize>,
+) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let filter = filter_by_name(&rdb, filter)?;
+    if rdb.glob_pattern(pattern_id)?.is_none() {
+        return Err(Error::msg(format!("No such pattern: {pattern_id}!")));
+    }
+    let mut rules = filter_rules(&rdb, filter.id)?;
+    let index = match at {
+        Some(at) if at == rules.len() + 1 => rules.len(),
+        Some(at) => rule_index(&rules, at)?,
+        None => rules.len(),
+    };
+    rules.insert(index, (pattern_id, include, target));
+    write::Db::new(&conn)?.set_filter_rules(filter.id, &rules)?;
+    Ok(())
+}
+
+pub fn filter_remove_rule(db_file_name: &str, filter: &str, rule: usize) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let filter = filter_by_name(&rdb, filter)?;
+    let mut rules = filter_rules(&rdb, filter.id)?;
+    let index = rule_index(&rules, rule)?;
+    rules.remove(index);
+    write::Db::new(&conn)?.set_filter_rules(filter.id, &rules)?;
+    Ok(())
+}
+
+/// Moves the rule number `rule` to the number `to`, the rules between
+/// shift by one.
+pub fn filter_move_rule(
+    db_file_name: &str,
+    filter: &str,
+    rule: usize,
+    to: usize,
+) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let filter = filter_by_name(&rdb, filter)?;
+    let mut rules = filter_rules(&rdb, filter.id)?;
+    let from = rule_index(&rules, rule)?;
+    let to = rule_index(&rules, to)?;
+    let moved = rules.remove(from);
+    rules.insert(to, moved);
+    write::Db::new(&conn)?.set_filter_rules(filter.id, &rules)?;
+    Ok(())
+}
+
+/// Prints 'include|exclude rule pattern path', tab separated, for every path,
+/// or every line of stdin if there are no paths. The rule is the last
+/// matching one as numbered by filter_show, '-' if none matched. Existing
+/// directories and paths ending with '/' are tested as directories.
+pub fn filter_test(db_file_name: &str, filter: &str, paths: &[String]) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let filter = filter_by_name(&rdb, filter)?;
+    let mut regexps = Vec::new();
+    for (pattern_id, _, _) in filter_rules(&rdb, filter.id)? {
+        regexps.push(
+            rdb.glob_pattern(pattern_id)?
+                .map(|p| p.regexp)
+                .unwrap_or_default(),
+        );
+    }
+    let compiled = rdb.glob_filter_by_id(filter.id)?;
+    let test = |path: &str| {
+        let is_dir = path.ends_with('/') || std::path::Path::new(path).is_dir();
+        let text = match path.strip_suffix('/') {
+            Some(text) if !text.is_empty() => text,
+            _ => path,
+        };
+        match compiled.explain(text, is_dir).last() {
+            Some(m) => {
+                let action = if m.include { "include" } else { "exclude" };
+                let rule = m.item_index + 1;
+                println!("{action}\t{rule}\t{}\t{path}", regexps[m.item_index]);
+            }
+            None => println!("exclude\t-\t-\t{path}"),
+        };
+    };
+    if paths.is_empty() {
+        for line in std::io::stdin().lines() {
+            test(&line?);
+        }
+    } else {
+        for path in paths {
+            test(path);
+        }
+    }
+    Ok(())
+}
+
+/// Prints 'id kind text', tab separated.
+pub fn pattern_list(db_file_name: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    for pattern in rdb.enum_glob_patterns()? {
+        println!(
+            "{}\t{}\t{}",
+            pattern.id,
+            pattern.kind.as_str(),
+            pattern.regexp
+        );
+    }
+    Ok(())
+}
+
+/// Prints the id of the new pattern.
+pub fn pattern_add(db_file_name: &str, kind: PatternKind, text: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    let pattern = write::Db::new(&conn)?.create_glob_pattern(kind, text)?;
+    tx.commit();
+    println!("{}", pattern.id);
+    Ok(())
+}
+
+/// Fails if a filter uses the pattern, see write::Db::delete_glob_pattern.
+pub fn pattern_delete(db_file_name: &str, pattern_id: DbId) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    if rdb.glob_pattern(pattern_id)?.is_none() {
+        return Err(Error::msg(format!("No such pattern: {pattern_id}!")));
+    }
+    let mut tx = dblayer::Transaction::new(&conn);
+    write::Db::new(&conn)?.delete_glob_pattern(pattern_id)?;
+    tx.commit();
+    Ok(())
+}
+
+/// Prints absolute paths of the files matching the expression, see query.rs.
+pub fn select(db_file_name: &str, expr: &str, order: dblayer::SelectOrder) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let cond = query::compile(&query::parse(expr)?);
+    for file in rdb.select_files(&cond.sql, &cond.params, order)? {
+        println!(
+            "{}",
+            std::path::Path::new(&file.fs_path)
+                .join(&file.rel_path)
+                .to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+pub struct DupesArgs {
+    pub db_file_name: String,
+    /// group by size only, for files not hashed yet
+    pub by_size: bool,
+    /// collection to keep the files from
+    pub prefer: Option<String>,
+    pub format: DupesFormat,
+}
+
+/// Prints groups of identical files across all collections, or the files
+/// other than the canonical one of each group.
+pub fn dupes(args: DupesArgs) -> anyhow::Result<()> {
+    let conn = read::open_existing(&args.db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    if let Some(prefer) = &args.prefer {
+        if rdb.collection_by_name(prefer)?.is_none() {
+            return Err(Error::msg(format!("No such collection: '{prefer}'!")));
+        }
+    }
+    if !args.by_size {
+        let unhashed = rdb.unhashed_file_count()?;
+        if unhashed > 0 {
+            eprintln!(
+                "{unhashed} files have no up to date hash, run 'palica hash' or use --by-size."
+            );
+        }
+    }
+
+    let groups = dupes::find_duplicates(&rdb, args.by_size, args.prefer.as_deref())?;
+    match args.format {
+        DupesFormat::Groups => {
+            for group in &groups {
+                println!("{}", group.key);
+                for file in &group.files {
+                    println!(
+                        "\t{}",
+                        vpath::from_rel_path(&file.coll_name, &file.rel_path)
+                    );
+                }
+            }
+        }
+        DupesFormat::Plain => {
+            for file in groups.iter().flat_map(|g| g.others()) {
+                println!("{}", dupes::fs_path(file).to_string_lossy());
+            }
+        }
+        DupesFormat::Toml => {
+            print!("{}", toml::to_string(&dupes::rich_list(&rdb, &groups)?)?);
+        }
+    }
+    eprintln!("Found {} groups of duplicates.", groups.len());
+    Ok(())
+}
+
+/// Prints the integrity problems, fixes them if `repair`, see fsck::repair.
+pub fn fsck(db_file_name: &str, repair: bool) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let problems = if repair {
+        fsck::repair(&conn)?
+    } else {
+        fsck::check(&conn)?
+    };
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if problems.is_empty() {
+        eprintln!("No problems found.");
+    } else if repair {
+        let remaining = fsck::check(&conn)?;
+        eprintln!("Repaired {} problems.", problems.len() - remaining.len());
+        if !remaining.is_empty() {
+            return Err(Error::msg(format!(
+                "{} problems need a manual fix, e.g. 'palica pattern delete'.",
+                remaining.len()
+            )));
+        }
+    } else {
+        return Err(Error::msg(format!(
+            "Found {} problems, run with --repair to fix them.",
+            problems.len()
+        )));
+    }
+    Ok(())
+}
+
+pub struct ThumbsAddArgs {
+    pub db_file_name: String,
+    pub name: String,
+    /// thumbnail db filename
+    pub fs_path: String,
+    /// WIDTHxHEIGHT
+    pub size: String,
+    pub mime: String,
+    /// produced in-process if None
+    pub cmd_line: Option<String>,
+}
+
+/// Creates a thumbnail db and registers it as a profile.
+pub fn thumbs_add(args: ThumbsAddArgs) -> anyhow::Result<()> {
+    let (max_width, max_height) = args
+        .size
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+        .ok_or_else(|| Error::msg(format!("Bad size '{}', expected WIDTHxHEIGHT!", args.size)))?;
+    if Path::new(&args.fs_path).exists() {
+        return Err(Error::msg(format!(
+            "File '{}' already exists!",
+            args.fs_path
+        )));
+    }
+    let info = thumbnails::ThumbsInfo {
+        mime: args.mime,
+        max_width,
+        max_height,
+        produced_by: match args.cmd_line {
+            Some(_) => thumbnails::PRODUCED_BY_CMD_LINE,
+            None => thumbnails::PRODUCED_BY_IMAGE,
+        }
+        .to_owned(),
+        cmd_line: args.cmd_line.unwrap_or_default(),
+        hash_type: "sha256".to_owned(),
+    };
+    let conn = read::open_existing(&args.db_file_name)?;
+    let wdb = write::Db::new(&conn)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    let profile = thumbnails::create_profile(&wdb, &args.name, Path::new(&args.fs_path), &info)?;
+    tx.commit();
+    println!(
+        "Added thumbnail profile '{}' at '{}'.",
+        profile.name, profile.fs_path
+    );
+    Ok(())
+}
+
+pub fn thumbs_list(db_file_name: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    for profile in thumbnails::enum_profiles(&rdb)? {
+        if !Path::new(&profile.fs_path).exists() {
+            println!("{} {} (missing)", profile.name, profile.fs_path);
+            continue;
+        }
+        let thumbs_conn = thumbnails::open_profile(&profile)?;
+        let info = thumbnails::Db::new(&thumbs_conn).info()?;
+        println!(
+            "{} {} {}x{} {} {}",
+            profile.name,
+            profile.fs_path,
+            info.max_width,
+            info.max_height,
+            info.mime,
+            info.produced_by
+        );
+    }
+    Ok(())
+}
+
+/// Unregisters the profile, its db file is kept.
+pub fn thumbs_remove(db_file_name: &str, name: &str) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let wdb = write::Db::new(&conn)?;
+    let mut tx = dblayer::Transaction::new(&conn);
+    let profile = thumbnails::remove_profile(&wdb, name)?;
+    tx.commit();
+    println!(
+        "Removed thumbnail profile '{name}', its file '{}' is kept.",
+        profile.fs_path
+    );
+    Ok(())
+}
+
+/// Adds thumbnails of the collection images to the profile db,
+/// of all collections if `names` is empty.
+pub fn thumbs_build(
+    db_file_name: &str,
+    profile_name: &str,
+    names: &[String],
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let rdb = read::Db::new(&conn)?;
+    let cols = if names.is_empty() {
+        rdb.enum_collections()?
+    } else {
+        let mut cols = Vec::new();
+        for name in names {
+            match rdb.collection_by_name(name)? {
+                Some(col) => cols.push(col),
+                None => return Err(Error::msg(format!("No such collection: '{name}'!"))),
+            }
+        }
+        cols
+    };
+    let profile = thumbnails::profile_by_name(&rdb, profile_name)?;
+    let thumbs_conn = thumbnails::open_profile(&profile)?;
+    for col in cols {
+        let stats = thumbnails::build_collection(&conn, &thumbs_conn, &col, &|path| {
+            if verbose {
+                eprintln!("{}", path.to_string_lossy());
+            }
+        })?;
+        println!(
+            "Thumbnails of collection '{}': {} built, {} reused, {} unchanged, {} failed.",
+            col.coll_name, stats.built, stats.reused, stats.skipped, stats.failed
+        );
+    }
+    Ok(())
+}
+
+/// Writes the thumbnail of the file to `output`, or to stdout.
+pub fn thumbs_get(
+    db_file_name: &str,
+    profile_name: &str,
+    v_path: &str,
+    output: Option<&str>,
+) -> anyhow::Result<()> {
+    use std::io::Write;
+    let conn = read::open_existing(db_file_name)?;
+    let profile = thumbnails::profile_by_name(&read::Db::new(&conn)?, profile_name)?;
+    let thumbs_conn = thumbnails::open_profile(&profile)?;
+    let data = thumbnails::Db::new(&thumbs_conn)
+        .thumbnail_by_v_path(v_path)?
+        .ok_or_else(|| Error::msg(format!("No thumbnail of '{v_path}'!")))?;
+    match output {
+        Some(output) => std::fs::write(output, data)?,
+        None => std::io::stdout().write_all(&data)?,
+    }
+    Ok(())
+}
+
+/// Prunes the profile db, all profile dbs if `profile_name` is None.
+pub fn thumbs_prune(db_file_name: &str, profile_name: Option<&str>) -> anyhow::Result<()> {
+    let conn = read::open_existing(db_file_name)?;
+    let deleted = match profile_name {
+        Some(name) => {
+            let profile = thumbnails::profile_by_name(&read::Db::new(&conn)?, name)?;
+            thumbnails::prune(&conn, &thumbnails::open_profile(&profile)?)?
+        }
+        None => thumbnails::prune_profiles(&conn)?,
+    };
+    println!("Deleted thumbnails of {deleted} files.");
+    Ok(())
+}
@@ -15,11 +15,11 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
-use crate::dblayer::{Collection, DbId, DirEntry};
+use crate::dblayer::{Collection, DbId, DirEntry, MediaInfo};
 use crate::fsdbtime::dbtime_from_sys;
 use crate::fslayer::{read, FsDirEntry};
 use crate::glob_filter::Filter;
-use crate::{dblayer, fslayer};
+use crate::{dblayer, fslayer, mime};
 
 use std::collections::VecDeque;
 use std::path::Path;
@@ -28,7 +28,7 @@ use std::time::SystemTime;
 pub type CollResult<T> = anyhow::Result<T>;
 pub type OnNewDirEntry = dyn Fn(&DirEntry);
 
-fn new_entry_from_fs(fs_entry: &FsDirEntry, new_id: DbId, sync_time: SystemTime) -> DirEntry {
+pub fn new_entry_from_fs(fs_entry: &FsDirEntry, new_id: DbId, sync_time: SystemTime) -> DirEntry {
     dblayer::DirEntry {
         id: new_id,
         fs_name: fs_entry.name.clone(),
@@ -39,12 +39,83 @@ fn new_entry_from_fs(fs_entry: &FsDirEntry, new_id: DbId, sync_time: SystemTime)
     }
 }
 
+fn media_info(meta: vienik_rs::Metadata) -> MediaInfo {
+    MediaInfo {
+        width: meta.width.map(i64::from),
+        height: meta.height.map(i64::from),
+        orientation: meta.orientation.map(i64::from),
+        camera_make: meta.camera_make,
+        camera_model: meta.camera_model,
+        lens: meta.lens,
+        focal_length: meta.focal_length,
+        exposure_time: meta.exposure_time,
+        f_number: meta.f_number,
+        iso: meta.iso.map(i64::from),
+        latitude: meta.gps.as_ref().map(|gps| gps.latitude),
+        longitude: meta.gps.as_ref().map(|gps| gps.longitude),
+        altitude: meta.gps.and_then(|gps| gps.altitude),
+    }
+}
+
+/// Detects and stores the mime type of a file entry, and the capture time
+/// and the media info of photos and videos. Failures are reported and
+/// skipped.
+pub fn store_file_metadata(
+    write_db: &dblayer::write::Db,
+    entry: &DirEntry,
+    path: &Path,
+) -> dblayer::DbResult<()> {
+    if entry.is_dir {
+        return Ok(());
+    }
+    let mime_value = match mime::detect(path) {
+        Ok(mime_value) => mime_value,
+        Err(e) => {
+            eprintln!(
+                "Failed to detect mime type of '{}': {e}",
+                path.to_string_lossy()
+            );
+            return Ok(());
+        }
+    };
+    write_db.set_mime_type(entry.id, mime_value)?;
+    let is_image = mime_value.starts_with("image/");
+    let meta = if is_image || mime_value.starts_with("video/") {
+        match vienik_rs::read_metadata(path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                eprintln!(
+                    "Failed to read metadata of '{}': {e}",
+                    path.to_string_lossy()
+                );
+                vienik_rs::Metadata::default()
+            }
+        }
+    } else {
+        vienik_rs::Metadata::default()
+    };
+    let capture_time = meta
+        .date_taken
+        .and_then(|t| t.and_utc().timestamp_nanos_opt());
+    write_db.set_capture_time(entry.id, capture_time)?;
+
+    let mut info = media_info(meta);
+    if is_image && info.width.is_none() {
+        // formats without EXIF, e.g. PNG
+        if let Ok((width, height)) = image::image_dimensions(path) {
+            info.width = Some(i64::from(width));
+            info.height = Some(i64::from(height));
+        }
+    }
+    write_db.set_media_info(entry.id, Some(&info))
+}
+
 pub fn new_collection(
     write_db: &mut dblayer::write::Db,
     name: &str,
     src_path: &std::path::Path,
     filter_id: DbId,
-    filter: &mut Filter,
+    filter: &Filter,
     on_new_direntry: &OnNewDirEntry,
 ) -> CollResult<Collection> {
     let src_path = src_path.canonicalize()?;
@@ -65,7 +136,8 @@ pub fn new_collection(
     let mut subdirs = VecDeque::<(DbId, std::path::PathBuf)>::new();
     subdirs.push_back((root_entry.id, src_path.to_owned()));
 
-    let mut filter_fn = |item: &Path| filter.include(&item.to_string_lossy());
+    // excluded directories are never read, so their subtrees aren't walked
+    let mut filter_fn = |item: &Path, is_dir| filter.include(&item.to_string_lossy(), is_dir);
 
     while let Some((root_id, root_path)) = subdirs.pop_front() {
         if let Ok(entries) = fslayer::read::dir_entries(&root_path, &mut filter_fn) {
@@ -74,9 +146,10 @@ pub fn new_collection(
                 eprintln!("db_item = {:?}", &db_item);
                 write_db.create_dir_entry(&db_item)?;
                 write_db.map_dir_entry_to_parent_dir(db_item.id, root_id)?;
+                let p = root_path.join(&item.name);
+                store_file_metadata(write_db, &db_item, &p)?;
                 on_new_direntry(&db_item);
                 if item.is_dir {
-                    let p = root_path.join(item.name);
                     subdirs.push_back((db_item.id, p));
                 }
             }
@@ -91,6 +164,7 @@ pub fn new_collection(
 mod tests {
     use super::*;
     use crate::glob_filter::Filter;
+    use crate::test_util::TmpDir;
     use dblayer::write;
     #[test]
     fn new_col() {
@@ -101,7 +175,7 @@ mod tests {
             "testcol",
             &std::path::Path::new("./"),
             1,
-            &mut Filter::new(),
+            &Filter::new(),
             &|e| {
                 eprintln!("new entry {:?}", &e);
             },
@@ -109,4 +183,136 @@ mod tests {
         eprintln!("{:?}", col);
         assert!(col.is_ok());
     }
+
+    #[test]
+    fn new_col_metadata() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let col = new_collection(
+            &mut db,
+            "sample",
+            Path::new("../sample-data"),
+            1,
+            &Filter::new(),
+            &|_| {},
+        )
+        .unwrap();
+        let mut rdb = dblayer::read::Db::new(&conn).unwrap();
+        let mut types = Vec::new();
+        for (rel_path, entry) in rdb.enum_subtree(col.root_id).unwrap() {
+            let mime_value = rdb.mime_type_of_dir_entry(entry.id).unwrap();
+            assert_eq!(mime_value.is_none(), entry.is_dir);
+            if let Some(mime_value) = mime_value {
+                types.push((rel_path, mime_value));
+            }
+        }
+        types.sort();
+        assert!(types.contains(&("img1.jpg".to_owned(), "image/jpeg".to_owned())));
+        assert!(types.contains(&("lossless/img1.png".to_owned(), "image/png".to_owned())));
+        assert!(types.contains(&("img1.xcf.xz".to_owned(), "application/x-xz".to_owned())));
+
+        let exif_jpeg = rdb
+            .dir_entry_by_path(col.root_id, "exif/img2.jpg")
+            .unwrap()
+            .unwrap();
+        // 2023-05-03 14:30:00 +02:00
+        assert_eq!(
+            rdb.capture_time(exif_jpeg.id).unwrap(),
+            Some(
+                crate::fsdbtime::dbtime_from_date(2023, 5, 3).unwrap()
+                    + (12 * 60 + 30) * 60 * 1_000_000_000
+            )
+        );
+        let info = rdb.media_info(exif_jpeg.id).unwrap().unwrap();
+        assert_eq!(info.lens.as_deref(), Some("OLYMPUS M.12-40mm F2.8"));
+        assert_eq!((info.width, info.height), (Some(64), Some(48)));
+        assert_eq!((info.focal_length, info.iso), (Some(25.0), Some(200)));
+        assert!((info.latitude.unwrap() - 43.705).abs() < 1e-9);
+        assert_eq!(
+            rdb.entries_with_lens("OLYMPUS *").unwrap(),
+            vec![exif_jpeg.id]
+        );
+
+        // no EXIF, the dimensions of the image itself
+        let png = rdb
+            .dir_entry_by_path(col.root_id, "lossless/img1.png")
+            .unwrap()
+            .unwrap();
+        let info = rdb.media_info(png.id).unwrap().unwrap();
+        assert_eq!((info.width, info.height), (Some(256), Some(128)));
+        assert_eq!(info.camera_make, None);
+        let xz = rdb
+            .dir_entry_by_path(col.root_id, "img1.xcf.xz")
+            .unwrap()
+            .unwrap();
+        assert_eq!(rdb.media_info(xz.id).unwrap(), None);
+    }
+
+    #[test]
+    fn excluded_dirs_not_walked() {
+        use crate::glob_filter::{FilterItem, Pattern, PatternKind, RuleTarget};
+        use std::fs;
+        let tmp = TmpDir::new("coll-excluded-dirs");
+        let tmp_dir = tmp.path();
+        fs::create_dir_all(tmp_dir.join(".git/objects")).unwrap();
+        fs::create_dir_all(tmp_dir.join(".thumbnails/normal")).unwrap();
+        fs::create_dir_all(tmp_dir.join("dcim/Thumbs.db")).unwrap();
+        fs::write(tmp_dir.join(".git/a.txt"), "a").unwrap();
+        fs::write(tmp_dir.join(".git/objects/b"), "b").unwrap();
+        fs::write(tmp_dir.join(".thumbnails/normal/c.png"), "c").unwrap();
+        fs::write(tmp_dir.join("dcim/d.jpg"), "d").unwrap();
+        fs::write(tmp_dir.join("dcim/Thumbs.db/e.jpg"), "e").unwrap();
+        fs::write(tmp_dir.join("Thumbs.db"), "f").unwrap();
+
+        // the files inside .git and .thumbnails are included by the rules,
+        // they'd be in the collection if the directories were walked
+        let glob = |text| Pattern::parse(PatternKind::Glob, text).unwrap();
+        let item = |pattern_index, include, target| FilterItem {
+            pattern_index,
+            include,
+            target,
+        };
+        let filter = Filter {
+            patterns: vec![
+                Pattern::new(".+"),
+                glob(".git"),
+                glob(".thumbnails"),
+                glob("Thumbs.db"),
+            ],
+            items: vec![
+                item(0, true, RuleTarget::Both),
+                item(1, false, RuleTarget::Dirs),
+                item(2, false, RuleTarget::Dirs),
+                item(3, false, RuleTarget::Files),
+            ],
+        };
+
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut db, "testcol", tmp_dir, 1, &filter, &|_| {}).unwrap();
+        let mut rdb = dblayer::read::Db::new(&conn).unwrap();
+        let mut paths: Vec<String> = rdb
+            .enum_subtree(col.root_id)
+            .unwrap()
+            .into_iter()
+            .map(|(rel_path, _)| rel_path)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "dcim",
+                "dcim/Thumbs.db",
+                "dcim/Thumbs.db/e.jpg",
+                "dcim/d.jpg"
+            ]
+        );
+
+        // sync prunes the same directories
+        fs::write(tmp_dir.join(".git/objects/g"), "g").unwrap();
+        let stats =
+            crate::sync::sync_collection(&mut db, &col, &filter, &Default::default(), &|_, _| {})
+                .unwrap();
+        assert_eq!(stats, Default::default());
+    }
 }
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::glob_filter::{pcre_compat, Pattern, PatternKind, RuleTarget};
+
 /*
     palica media catalogue program
     Copyright (C) 2023 Yury Benesh
@@ -20,33 +22,66 @@ use thiserror::Error;
 /// 'default' filter from schema1.sql
 pub const DEFAULT_FILTER_ID: DbId = 1;
 
+/// settings table key, percentage of collection entries sync may delete
+/// without a confirmation
+pub const SYNC_DELETE_THRESHOLD_KEY: &str = "sync_delete_threshold";
+
+/// settings table key, id of the filter for new collections, it can't be
+/// deleted
+pub const DEFAULT_FILTER_KEY: &str = "default_filter";
+
+/// settings table key, '1' to rewrite sidecar .xmp files on tag edits
+pub const UPDATE_XMP_KEY: &str = "update_xmp";
+
+/// settings table key prefix of thumbnail profiles, e.g.
+/// 'thumbs_profile:small-256-jxl', the value is the thumbnail db path
+pub const THUMBS_PROFILE_KEY_PREFIX: &str = "thumbs_profile:";
+
 pub type DbId = i64;
 
+/// Implemented via savepoints, so transactions can be nested, e.g. sync
+/// deletes entries with write::Db::delete_dir_entry inside its own transaction.
 pub struct Transaction<'a> {
     conn: &'a sqlite::Connection,
     finished: bool,
+    /// total_change_count when the transaction began
+    changes: usize,
 }
 
 impl Transaction<'_> {
     pub fn new<'a>(conn: &'a sqlite::Connection) -> Transaction<'a> {
         eprintln!("starting tx...");
-        conn.execute("BEGIN").expect("Failed to begin tx.");
+        conn.execute("SAVEPOINT palica_tx")
+            .expect("Failed to begin tx.");
         Transaction {
             conn,
             finished: false,
+            changes: conn.total_change_count(),
         }
     }
 
+    /// Marks the database as written by this version of palica if rows
+    /// were changed by the transaction.
     pub fn commit(&mut self) {
         eprintln!("commiting tx...");
-        self.conn.execute("END").expect("Failed to commit tx.");
+        if self.conn.total_change_count() != self.changes {
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "UPDATE app_info SET info_value = ?1 WHERE info_key = 'app_version'",
+                crate::PALICA_VERSION,
+            )
+            .expect("Failed to update app_version.");
+        }
+        self.conn
+            .execute("RELEASE palica_tx")
+            .expect("Failed to commit tx.");
         self.finished = true;
     }
 
     pub fn rollback(&mut self) {
         eprintln!("rollback tx...");
         self.conn
-            .execute("ROLLBACK")
+            .execute("ROLLBACK TO palica_tx; RELEASE palica_tx")
             .expect("Failed to rollback tx.");
         self.finished = true;
     }
@@ -87,7 +122,7 @@ impl Collection {
 
 pub type DbTime = i64;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DirEntry {
     pub id: DbId,
     pub fs_name: String,
@@ -117,16 +152,33 @@ impl DirEntry {
 #[derive(Debug)]
 pub struct GlobPattern {
     pub id: DbId,
+    /// pattern text of the kind, the column name is from version 1
     pub regexp: String,
+    pub kind: PatternKind,
 }
 
 impl GlobPattern {
+    pub const COLUMNS: &'static str = "id, regexp, kind";
+
+    /// Unknown kinds are read as regexps.
     pub fn from_row(row: &sqlite::Row) -> GlobPattern {
         GlobPattern {
             id: row.read::<i64, usize>(0),
             regexp: row.read::<&str, usize>(1).to_owned(),
+            kind: row
+                .read::<&str, usize>(2)
+                .parse()
+                .unwrap_or(PatternKind::Regexp),
         }
     }
+
+    /// Fails with InvalidPattern if the text doesn't compile.
+    pub fn compile(&self) -> DbResult<Pattern> {
+        Pattern::parse(self.kind, &self.regexp).map_err(|error| DbError::InvalidPattern {
+            pattern: self.regexp.clone(),
+            error,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -151,9 +203,11 @@ pub struct GlobFilterToPattern {
     pub glob_pattern_id: DbId,
     pub include: bool,
     pub position: i32,
+    pub target: RuleTarget,
 }
 
 impl GlobFilterToPattern {
+    /// Unknown targets are read as both.
     pub fn from_row(row: &sqlite::Row) -> GlobFilterToPattern {
         GlobFilterToPattern {
             id: row.read::<i64, usize>(0),
@@ -161,10 +215,267 @@ impl GlobFilterToPattern {
             glob_pattern_id: row.read::<i64, usize>(2),
             include: row.read::<i64, usize>(3) != 0,
             position: row.read::<i64, usize>(4) as i32,
+            target: row
+                .read::<&str, usize>(5)
+                .parse()
+                .unwrap_or(RuleTarget::Both),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileHash {
+    pub dir_entry_id: DbId,
+    /// sha256, hex
+    pub hash_value: String,
+    /// of the file when it was hashed
+    pub fs_mod_time: i64,
+    pub fs_size: i64,
+}
+
+impl FileHash {
+    pub fn from_row(row: &sqlite::Row) -> FileHash {
+        FileHash {
+            dir_entry_id: row.read::<i64, usize>(0),
+            hash_value: row.read::<&str, usize>(1).to_owned(),
+            fs_mod_time: row.read::<i64, usize>(2),
+            fs_size: row.read::<i64, usize>(3),
+        }
+    }
+
+    /// true if the entry has not changed since it was hashed
+    pub fn is_valid_for(&self, entry: &DirEntry) -> bool {
+        self.dir_entry_id == entry.id
+            && self.fs_mod_time == entry.fs_mod_time
+            && self.fs_size == entry.fs_size
+    }
+}
+
+/// Dimensions and camera metadata of a photo or video, None if unknown.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct MediaInfo {
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    /// EXIF orientation, 1 is upright
+    pub orientation: Option<i64>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    /// millimeters
+    pub focal_length: Option<f64>,
+    /// seconds
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<i64>,
+    /// degrees, negative to the south
+    pub latitude: Option<f64>,
+    /// degrees, negative to the west
+    pub longitude: Option<f64>,
+    /// meters
+    pub altitude: Option<f64>,
+}
+
+impl MediaInfo {
+    const COLUMNS: &'static str = "width, height, orientation, camera_make, camera_model, lens,
+        focal_length, exposure_time, f_number, iso, latitude, longitude, altitude";
+
+    pub fn from_row(row: &sqlite::Row) -> MediaInfo {
+        let text = |i: usize| row.read::<Option<&str>, usize>(i).map(str::to_owned);
+        MediaInfo {
+            width: row.read::<Option<i64>, usize>(0),
+            height: row.read::<Option<i64>, usize>(1),
+            orientation: row.read::<Option<i64>, usize>(2),
+            camera_make: text(3),
+            camera_model: text(4),
+            lens: text(5),
+            focal_length: row.read::<Option<f64>, usize>(6),
+            exposure_time: row.read::<Option<f64>, usize>(7),
+            f_number: row.read::<Option<f64>, usize>(8),
+            iso: row.read::<Option<i64>, usize>(9),
+            latitude: row.read::<Option<f64>, usize>(10),
+            longitude: row.read::<Option<f64>, usize>(11),
+            altitude: row.read::<Option<f64>, usize>(12),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == MediaInfo::default()
+    }
+}
+
+/// Area between two parallels and two meridians, degrees. The box crosses
+/// the antimeridian if `west` > `east`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct GeoBox {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+impl GeoBox {
+    /// Condition over media_info `latitude` and `longitude`, the bounds are
+    /// appended to `params`.
+    pub fn sql_condition(&self, params: &mut Vec<sqlite::Value>) -> String {
+        let mut param = |value: f64| {
+            params.push(value.into());
+            format!("?{}", params.len())
+        };
+        let (south, north) = (param(self.south), param(self.north));
+        let (west, east) = (param(self.west), param(self.east));
+        let join = if self.west <= self.east { "AND" } else { "OR" };
+        format!(
+            "(latitude BETWEEN {south} AND {north} AND (longitude >= {west} {join} longitude <= {east}))"
+        )
+    }
+}
+
+impl std::str::FromStr for GeoBox {
+    type Err = String;
+
+    /// 'SOUTH,WEST,NORTH,EAST'
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("bad bounding box '{s}', expected 'SOUTH,WEST,NORTH,EAST' in degrees");
+        let bounds = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>().map_err(|_| bad()))
+            .collect::<Result<Vec<f64>, String>>()?;
+        let [south, west, north, east] = bounds[..] else {
+            return Err(bad());
+        };
+        let latitude = |v: f64| (-90.0..=90.0).contains(&v);
+        let longitude = |v: f64| (-180.0..=180.0).contains(&v);
+        if !(latitude(south)
+            && latitude(north)
+            && south <= north
+            && longitude(west)
+            && longitude(east))
+        {
+            return Err(bad());
+        }
+        Ok(GeoBox {
+            south,
+            west,
+            north,
+            east,
+        })
+    }
+}
+
+/// File found by `read::Db::select_files`.
+#[derive(Debug, PartialEq)]
+pub struct SelectedFile {
+    pub coll_name: String,
+    /// collection root
+    pub fs_path: String,
+    /// '/' separated, relative to the collection root
+    pub rel_path: String,
+}
+
+/// Sorting of `read::Db::select_files`, ties by collection name and path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectOrder {
+    Path,
+    ModTime,
+    /// capture time, or the modification time if unknown
+    Taken,
+}
+
+impl std::str::FromStr for SelectOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(SelectOrder::Path),
+            "date" => Ok(SelectOrder::ModTime),
+            "taken" => Ok(SelectOrder::Taken),
+            _ => Err(format!(
+                "unknown sort order '{s}', expected 'path', 'date' or 'taken'"
+            )),
+        }
+    }
+}
+
+/// File found by `read::Db::duplicate_files`.
+#[derive(Debug, Clone)]
+pub struct DuplicateFile {
+    /// sha256 or the size, the same for all files of a group
+    pub key: String,
+    pub entry: DirEntry,
+    pub coll_name: String,
+    /// collection root
+    pub fs_path: String,
+    /// '/' separated, relative to the collection root
+    pub rel_path: String,
+}
+
+/// Separates levels of hierarchical tag values, e.g. 'places|france|nice',
+/// the same as in lr:hierarchicalSubject.
+pub const TAG_PATH_SEPARATOR: char = '|';
+
+/// `tag_value` is the full path, `parent_id` refers to the tag one level up,
+/// e.g. 'places|france' for 'places|france|nice'.
+#[derive(Debug, PartialEq)]
+pub struct SubjectTag {
+    pub id: DbId,
+    pub tag_value: String,
+    pub parent_id: Option<DbId>,
+}
+
+impl SubjectTag {
+    pub fn from_row(row: &sqlite::Row) -> SubjectTag {
+        SubjectTag {
+            id: row.read::<i64, usize>(0),
+            tag_value: row.read::<&str, usize>(1).to_owned(),
+            parent_id: row.read::<Option<i64>, usize>(2),
         }
     }
+
+    pub fn table_name() -> &'static str {
+        "subject_tags"
+    }
+
+    /// Last level of the path, e.g. 'nice' for 'places|france|nice'.
+    pub fn name(&self) -> &str {
+        self.tag_value
+            .rsplit(TAG_PATH_SEPARATOR)
+            .next()
+            .unwrap_or(&self.tag_value)
+    }
+
+    /// 'places|france' for 'places|france|nice', None for top level tags.
+    pub fn parent_value(tag_value: &str) -> Option<&str> {
+        tag_value
+            .rsplit_once(TAG_PATH_SEPARATOR)
+            .map(|(parent, _)| parent)
+    }
+
+    /// Non-empty levels.
+    pub fn is_valid_value(tag_value: &str) -> bool {
+        tag_value
+            .split(TAG_PATH_SEPARATOR)
+            .all(|level| !level.trim().is_empty())
+    }
 }
 
+/// ids of the tag ?1 and all tags below it
+const TAG_SUBTREE_SQL: &str = "WITH RECURSIVE sub(id) AS (
+        SELECT ?1
+        UNION ALL
+        SELECT t.id FROM subject_tags t JOIN sub ON t.parent_id = sub.id)
+    SELECT id FROM sub";
+
+/// every entry of every collection with its '/' separated path relative
+/// to the collection root
+const COLLECTION_TREE_SQL: &str = "WITH RECURSIVE tree(id, col_id, rel_path) AS (
+        SELECT root_id, id, '' FROM collections
+        UNION ALL
+        SELECT d.entry_id, tree.col_id,
+            CASE WHEN tree.rel_path = '' THEN e.fs_name
+            ELSE tree.rel_path || '/' || e.fs_name END
+        FROM dir_to_sub d JOIN tree ON d.directory_id = tree.id
+        JOIN dir_entries e ON e.id = d.entry_id)";
+
 #[derive(Debug)]
 pub struct SettingValue {
     pub id: DbId,
@@ -172,6 +483,16 @@ pub struct SettingValue {
     pub value: String,
 }
 
+impl SettingValue {
+    pub fn from_row(row: &sqlite::Row) -> SettingValue {
+        SettingValue {
+            id: row.read::<i64, usize>(0),
+            key: row.read::<&str, usize>(1).to_owned(),
+            value: row.read::<&str, usize>(2).to_owned(),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum DbError {
     #[error("sqlite error: {error}")]
@@ -186,6 +507,12 @@ pub enum DbError {
     AlreadyExists { name: String },
     #[error("filter to glob integrity error, filter: {filter_id}, glob: {glob_id}")]
     FilterToGlobIntegrityError { filter_id: DbId, glob_id: DbId },
+    #[error("filter '{name}' is used by {users}")]
+    FilterInUse { name: String, users: String },
+    #[error("pattern {id} is used by filters: {filters}")]
+    PatternInUse { id: DbId, filters: String },
+    #[error("invalid pattern '{pattern}': {error}")]
+    InvalidPattern { pattern: String, error: String },
 }
 
 impl From<std::io::Error> for DbError {
@@ -226,7 +553,7 @@ where
 
 fn exec_sql_stmt_with_arg<T>(
     conn: &sqlite::Connection,
-    sql_text: &'static str,
+    sql_text: &str,
     arg: T,
 ) -> sqlite::Result<()>
 where
@@ -236,18 +563,99 @@ where
     exec_statement_with_arg(&mut stmt, arg)
 }
 
+/// Schema version of databases this version of palica reads and writes.
+pub const DB_VERSION: i64 = 5;
+
+/// Upgrades the schema from `version - 1` to `version`.
+struct Migration {
+    version: i64,
+    /// None if only the data changes
+    script: Option<&'static str>,
+    /// data changes the script can't do, run after it in the same transaction
+    after: Option<fn(&sqlite::Connection) -> DbResult<()>>,
+}
+
+/// Ordered by version, the first one upgrades schema1.sql.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 2,
+        script: Some("sql/migration2.sql"),
+        after: Some(link_parent_tags),
+    },
+    Migration {
+        version: 3,
+        script: Some("sql/migration3.sql"),
+        after: None,
+    },
+    Migration {
+        version: 4,
+        script: None,
+        after: Some(convert_pcre_patterns),
+    },
+    Migration {
+        version: 5,
+        script: Some("sql/migration5.sql"),
+        after: None,
+    },
+];
+
+/// Version 1 had no parent_id, the hierarchical tags get their parents.
+fn link_parent_tags(conn: &sqlite::Connection) -> DbResult<()> {
+    let rdb = read::Db::new(conn)?;
+    let wdb = write::Db::new(conn)?;
+    for tag in rdb.enum_subject_tags()? {
+        if tag.parent_id.is_none() && tag.tag_value.contains(TAG_PATH_SEPARATOR) {
+            wdb.rename_subject_tag(tag.id, &tag.tag_value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Regexps of version 3 were compiled by PCRE, they are rewritten to the
+/// regex crate syntax, see glob_filter::pcre_compat. The ones it can't
+/// compile are kept and reported, fsck lists them too.
+fn convert_pcre_patterns(conn: &sqlite::Connection) -> DbResult<()> {
+    let rdb = read::Db::new(conn)?;
+    let wdb = write::Db::new(conn)?;
+    for pattern in rdb.enum_glob_patterns()? {
+        if pattern.kind != PatternKind::Regexp {
+            continue;
+        }
+        if let Err(e) = pattern.compile() {
+            eprintln!("Unsupported pattern {}: {e}", pattern.id);
+            continue;
+        }
+        let converted = pcre_compat(&pattern.regexp);
+        if converted != pattern.regexp {
+            wdb.set_glob_pattern_text(pattern.id, &converted)?;
+        }
+    }
+    Ok(())
+}
+
+/// Value of app_info 'db_version', it is missing only in a broken database.
+pub fn db_version(conn: &sqlite::Connection) -> DbResult<Option<i64>> {
+    let mut stmt = conn.prepare("SELECT info_value FROM app_info WHERE info_key = 'db_version'")?;
+    match stmt.iter().next() {
+        Some(row) => Ok(row?.read::<&str, usize>(0).parse().ok()),
+        None => Ok(None),
+    }
+}
+
 pub mod read {
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet, VecDeque};
 
     use super::*;
-    use crate::glob_filter::{Filter, FilterItem, Pattern};
+    use crate::glob_filter::{Filter, FilterItem};
 
-    pub fn check_database_validity(_conn: &sqlite::Connection) -> bool {
-        // TODO check version, tables etc.
-        true
+    /// The schema version is known and not newer than [`DB_VERSION`],
+    /// older versions are migrated by [`open_existing`].
+    pub fn check_database_validity(conn: &sqlite::Connection) -> bool {
+        matches!(db_version(conn), Ok(Some(version)) if (1..=DB_VERSION).contains(&version))
     }
 
-    /// Either open an existing database, or fail.
+    /// Either open an existing database, or fail. A database of an older
+    /// schema version is backed up and migrated to [`DB_VERSION`].
     pub fn open_existing(fname: &str) -> DbResult<sqlite::Connection> {
         use std::path::Path;
 
@@ -269,6 +677,7 @@ pub mod read {
                 name: fname.to_owned(),
             });
         }
+        write::upgrade(fname, &conn)?;
 
         Ok(conn)
     }
@@ -316,6 +725,28 @@ pub mod read {
             res
         }
 
+        /// All entries inside the directory, recursively, with '/' separated
+        /// paths relative to it. Directories come before their contents.
+        pub fn enum_subtree(&mut self, dir_id: DbId) -> DbResult<Vec<(String, DirEntry)>> {
+            let mut res = Vec::new();
+            let mut subdirs = VecDeque::<(DbId, String)>::new();
+            subdirs.push_back((dir_id, String::new()));
+            while let Some((parent_id, prefix)) = subdirs.pop_front() {
+                for entry in self.enum_dir_entries(parent_id)? {
+                    let rel_path = if prefix.is_empty() {
+                        entry.fs_name.clone()
+                    } else {
+                        format!("{prefix}/{}", entry.fs_name)
+                    };
+                    if entry.is_dir {
+                        subdirs.push_back((entry.id, rel_path.clone()));
+                    }
+                    res.push((rel_path, entry));
+                }
+            }
+            Ok(res)
+        }
+
         pub fn enum_glob_filters(&self) -> DbResult<Vec<GlobFilter>> {
             let res = self
                 .conn
@@ -329,7 +760,10 @@ pub mod read {
         pub fn enum_glob_patterns(&self) -> DbResult<Vec<GlobPattern>> {
             let res = self
                 .conn
-                .prepare("SELECT id, regexp FROM glob_patterns ORDER BY regexp")?
+                .prepare(format!(
+                    "SELECT {} FROM glob_patterns ORDER BY regexp",
+                    GlobPattern::COLUMNS
+                ))?
                 .iter()
                 .map(|r| Ok(GlobPattern::from_row(&r?)))
                 .collect();
@@ -340,7 +774,8 @@ pub mod read {
         pub fn filter_patterns(&self, filter_id: DbId) -> DbResult<Vec<GlobFilterToPattern>> {
             let mut stmt = self.conn.prepare(
                 "SELECT id, glob_filter_id, glob_pattern_id, include,
-                    position FROM glob_filter_to_pattern WHERE glob_filter_id = ?1 ORDER BY position",
+                    position, target FROM glob_filter_to_pattern WHERE glob_filter_id = ?1
+                    ORDER BY position",
             )?;
             stmt.bind((1, filter_id))?;
             let res = stmt
@@ -350,6 +785,56 @@ pub mod read {
             res
         }
 
+        pub fn glob_filter(&self, filter_id: DbId) -> DbResult<Option<GlobFilter>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT id, name FROM glob_filters WHERE id = ?1")?;
+            stmt.bind((1, filter_id))?;
+            let res = stmt.iter().next().map(|r| Ok(GlobFilter::from_row(&r?)));
+            res.transpose()
+        }
+
+        pub fn glob_filter_by_name(&self, name: &str) -> DbResult<Option<GlobFilter>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT id, name FROM glob_filters WHERE name = ?1")?;
+            stmt.bind((1, name))?;
+            let res = stmt.iter().next().map(|r| Ok(GlobFilter::from_row(&r?)));
+            res.transpose()
+        }
+
+        pub fn glob_pattern(&self, pattern_id: DbId) -> DbResult<Option<GlobPattern>> {
+            let mut stmt = self.conn.prepare(format!(
+                "SELECT {} FROM glob_patterns WHERE id = ?1",
+                GlobPattern::COLUMNS
+            ))?;
+            stmt.bind((1, pattern_id))?;
+            let res = stmt.iter().next().map(|r| Ok(GlobPattern::from_row(&r?)));
+            res.transpose()
+        }
+
+        /// Filters with rules of the pattern, sorted by name.
+        pub fn filters_by_pattern(&self, pattern_id: DbId) -> DbResult<Vec<GlobFilter>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT DISTINCT f.id, f.name FROM glob_filters f
+                JOIN glob_filter_to_pattern fp ON fp.glob_filter_id = f.id
+                WHERE fp.glob_pattern_id = ?1 ORDER BY f.name",
+            )?;
+            stmt.bind((1, pattern_id))?;
+            let res = stmt.iter().map(|r| Ok(GlobFilter::from_row(&r?))).collect();
+            res
+        }
+
+        pub fn collections_by_filter(&self, filter_id: DbId) -> DbResult<Vec<Collection>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, coll_name, fs_path, root_id, glob_filter_id FROM collections
+                WHERE glob_filter_id = ?1 ORDER BY coll_name",
+            )?;
+            stmt.bind((1, filter_id))?;
+            let res = stmt.iter().map(|r| Ok(Collection::from_row(&r?))).collect();
+            res
+        }
+
         pub fn glob_filter_by_id(&self, filter_id: DbId) -> DbResult<Filter> {
             let dbpatterns = self.enum_glob_patterns()?;
             let dbfilter_patterns = self.filter_patterns(filter_id)?;
@@ -370,7 +855,7 @@ pub mod read {
                 let found_pattern = pattern_id_to_index.get(&filtered_pat.id);
                 if found_pattern.is_none() {
                     let new_index = patterns.len();
-                    patterns.push(Pattern::new(&filtered_pat.regexp));
+                    patterns.push(filtered_pat.compile()?);
                     pattern_id_to_index.insert(filtered_pat.id, new_index);
                 }
             }
@@ -386,6 +871,7 @@ pub mod read {
                         },
                     )?,
                     include: filter_pat.include,
+                    target: filter_pat.target,
                 });
             }
             Ok(Filter {
@@ -422,105 +908,605 @@ pub mod read {
             Ok(None)
         }
 
-        pub fn dir_entry_by_id(&self, id: DbId) -> DbResult<Option<DirEntry>> {
+        /// number of all files and directories inside the directory, recursively
+        pub fn subtree_entry_count(&self, dir_id: DbId) -> DbResult<usize> {
+            let mut stmt = self.conn.prepare(
+                "WITH RECURSIVE sub(id) AS (
+                    SELECT entry_id FROM dir_to_sub WHERE directory_id = ?1
+                    UNION ALL
+                    SELECT d.entry_id FROM dir_to_sub d JOIN sub ON d.directory_id = sub.id)
+                SELECT COUNT(*) FROM sub",
+            )?;
+            stmt.bind((1, dir_id))?;
+            stmt.next()?;
+            Ok(stmt.read::<i64, _>(0)? as usize)
+        }
+
+        pub fn setting_value(&self, key: &str) -> DbResult<Option<String>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT setting_value FROM settings WHERE setting_key = ?1")?;
+            stmt.bind((1, key))?;
+            match stmt.iter().next() {
+                Some(row) => Ok(Some(row?.read::<&str, usize>(0).to_owned())),
+                None => Ok(None),
+            }
+        }
+
+        /// Settings with keys starting with `prefix`, sorted by key.
+        pub fn settings_with_prefix(&self, prefix: &str) -> DbResult<Vec<SettingValue>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT id, setting_key, setting_value FROM settings
+                WHERE substr(setting_key, 1, length(?1)) = ?1 ORDER BY setting_key",
+            )?;
+            stmt.bind((1, prefix))?;
+            let res = stmt
+                .iter()
+                .map(|r| Ok(SettingValue::from_row(&r?)))
+                .collect();
+            res
+        }
+
+        pub fn child_by_name(&self, parent_id: DbId, name: &str) -> DbResult<Option<DirEntry>> {
             let mut prep = self.conn.prepare(
-                "SELECT id, fs_name, fs_mod_time,
-                    last_sync_time, is_dir, fs_size FROM dir_entries
-                    WHERE id = ?1",
+                "SELECT e.id, e.fs_name, e.fs_mod_time,
+                    e.last_sync_time, e.is_dir, e.fs_size FROM dir_entries e
+                    JOIN dir_to_sub d ON d.entry_id = e.id
+                    WHERE d.directory_id = ?1 AND e.fs_name = ?2",
             )?;
-            prep.bind((1, id))?;
-            for row in prep.into_iter() {
-                let row = row?;
-                return Ok(Some(DirEntry::from_row(&row)));
+            prep.bind((1, parent_id))?;
+            prep.bind((2, name))?;
+            match prep.into_iter().next() {
+                Some(row) => Ok(Some(DirEntry::from_row(&row?))),
+                None => Ok(None),
             }
-            Ok(None)
         }
-    }
-}
 
-pub fn nullable_from_option<T>(o: Option<T>) -> sqlite::Value
-where
-    sqlite::Value: From<T>,
-{
-    let r: sqlite::Value = match o {
-        Some(v) => v.into(),
-        None => sqlite::Value::Null,
-    };
-    r
-}
+        /// `rel_path` is relative to the root, '/' separated, empty for the root itself.
+        pub fn dir_entry_by_path(
+            &self,
+            root_id: DbId,
+            rel_path: &str,
+        ) -> DbResult<Option<DirEntry>> {
+            let mut entry = self.dir_entry_by_id(root_id)?;
+            for name in rel_path.split('/').filter(|s| !s.is_empty()) {
+                entry = match entry {
+                    Some(parent) if parent.is_dir => self.child_by_name(parent.id, name)?,
+                    _ => return Ok(None),
+                };
+            }
+            Ok(entry)
+        }
 
-pub fn value_from_bool(b: bool) -> sqlite::Value {
-    (b as i64).into()
-}
+        pub fn enum_subject_tags(&self) -> DbResult<Vec<SubjectTag>> {
+            let res = self
+                .conn
+                .prepare("SELECT id, tag_value, parent_id FROM subject_tags ORDER BY tag_value")?
+                .iter()
+                .map(|r| Ok(SubjectTag::from_row(&r?)))
+                .collect();
+            res
+        }
 
-/// For optimization hints before performing many inserts use Transaction.
-pub mod write {
-    use super::*;
-    use crate::dblayer::{Collection, DbId, DbResult, DirEntry};
+        pub fn subject_tag_by_value(&self, tag_value: &str) -> DbResult<Option<SubjectTag>> {
+            let mut prep = self.conn.prepare(
+                "SELECT id, tag_value, parent_id FROM subject_tags WHERE tag_value = ?1",
+            )?;
+            prep.bind((1, tag_value))?;
+            match prep.into_iter().next() {
+                Some(row) => Ok(Some(SubjectTag::from_row(&row?))),
+                None => Ok(None),
+            }
+        }
 
-    pub struct IdGen {
-        next_id: DbId,
-    }
+        /// sorted by value
+        pub fn tags_of_dir_entry(&self, entry_id: DbId) -> DbResult<Vec<SubjectTag>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT t.id, t.tag_value, t.parent_id FROM subject_tags t
+                JOIN tag_to_dir_entry m ON m.subject_tag_id = t.id
+                WHERE m.dir_entry_id = ?1 ORDER BY t.tag_value",
+            )?;
+            stmt.bind((1, entry_id))?;
+            let res = stmt.iter().map(|r| Ok(SubjectTag::from_row(&r?))).collect();
+            res
+        }
 
-    impl IdGen {
-        /// pass last id, e.g. result of select max(id) from my_sql_table;
-        pub fn new_with_last_id(last_id: DbId) -> IdGen {
-            IdGen {
-                next_id: last_id + 1,
+        pub fn mime_type_of_dir_entry(&self, entry_id: DbId) -> DbResult<Option<String>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT t.mime_value FROM mime_types t
+                JOIN mime_to_dir_entry m ON m.mime_type_id = t.id WHERE m.dir_entry_id = ?1",
+            )?;
+            stmt.bind((1, entry_id))?;
+            match stmt.iter().next() {
+                Some(row) => Ok(Some(row?.read::<&str, usize>(0).to_owned())),
+                None => Ok(None),
             }
         }
 
-        pub fn gen_id(&mut self) -> DbId {
-            let result = self.next_id;
-            self.next_id += 1;
-            result
+        pub fn capture_time(&self, entry_id: DbId) -> DbResult<Option<DbTime>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT date_time FROM capture_times WHERE dir_entry_id = ?1")?;
+            stmt.bind((1, entry_id))?;
+            match stmt.iter().next() {
+                Some(row) => Ok(Some(row?.read::<i64, usize>(0))),
+                None => Ok(None),
+            }
         }
-    }
 
-    #[derive(Error, Debug)]
-    pub enum DeleteError {
-        #[error("Db error: {error}")]
-        DbError { error: DbError },
-        #[error("Sqlite error: {error}")]
-        SqliteError { error: sqlite::Error },
-        #[error("Not a file: {name}")]
-        NotAfile { name: String },
-        #[error("Not a directory: {name}")]
-        NotAdir { name: String },
-        #[error("No root entry")]
-        NoRootEntry,
-    }
+        /// Capture time, or the modification time if unknown.
+        pub fn taken_time(&self, entry: &DirEntry) -> DbResult<DbTime> {
+            Ok(self.capture_time(entry.id)?.unwrap_or(entry.fs_mod_time))
+        }
 
-    impl From<sqlite::Error> for DeleteError {
-        fn from(error: sqlite::Error) -> Self {
-            DeleteError::SqliteError { error }
+        pub fn media_info(&self, entry_id: DbId) -> DbResult<Option<MediaInfo>> {
+            let mut stmt = self.conn.prepare(format!(
+                "SELECT {} FROM media_info WHERE dir_entry_id = ?1",
+                MediaInfo::COLUMNS
+            ))?;
+            stmt.bind((1, entry_id))?;
+            match stmt.iter().next() {
+                Some(row) => Ok(Some(MediaInfo::from_row(&row?))),
+                None => Ok(None),
+            }
         }
-    }
 
-    impl From<DbError> for DeleteError {
-        fn from(error: DbError) -> Self {
-            DeleteError::DbError { error }
+        /// Files shot with a lens matching the GLOB pattern.
+        pub fn entries_with_lens(&self, lens_glob: &str) -> DbResult<Vec<DbId>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT dir_entry_id FROM media_info WHERE lens GLOB ?1 ORDER BY dir_entry_id",
+            )?;
+            stmt.bind((1, lens_glob))?;
+            let res = stmt.iter().map(|r| Ok(r?.read::<i64, usize>(0))).collect();
+            res
         }
-    }
 
-    pub type DeleteResult<T> = Result<T, DeleteError>;
+        /// Files with GPS coordinates inside the box.
+        pub fn entries_in_box(&self, area: &GeoBox) -> DbResult<Vec<DbId>> {
+            let mut params = Vec::new();
+            let condition = area.sql_condition(&mut params);
+            let mut stmt = self.conn.prepare(format!(
+                "SELECT dir_entry_id FROM media_info WHERE {condition} ORDER BY dir_entry_id"
+            ))?;
+            for (i, param) in params.iter().enumerate() {
+                stmt.bind((i + 1, param))?;
+            }
+            let res = stmt.iter().map(|r| Ok(r?.read::<i64, usize>(0))).collect();
+            res
+        }
 
-    pub struct Db<'a> {
-        pub conn: &'a sqlite::Connection,
-        create_dir: sqlite::Statement<'a>,
-        map_dir: sqlite::Statement<'a>,
-    }
+        /// lens -> number of files, sorted by lens
+        pub fn lens_counts(&self) -> DbResult<Vec<(String, usize)>> {
+            let res = self
+                .conn
+                .prepare(
+                    "SELECT lens, COUNT(id) FROM media_info WHERE lens IS NOT NULL
+                    GROUP BY lens ORDER BY lens",
+                )?
+                .iter()
+                .map(|r| {
+                    let row = r?;
+                    Ok((
+                        row.read::<&str, usize>(0).to_owned(),
+                        row.read::<i64, usize>(1) as usize,
+                    ))
+                })
+                .collect();
+            res
+        }
 
-    impl Db<'_> {
-        pub fn new<'a>(conn: &'a sqlite::Connection) -> DbResult<Db<'a>> {
-            Ok(Db {
-                conn,
-                create_dir: conn.prepare(
+        /// (id, referenced id) of `table` rows whose `column` is not an id
+        /// of `referenced`, sorted by id. The schema has no foreign keys,
+        /// see fsck.
+        pub fn dangling_rows(
+            &self,
+            table: &str,
+            column: &str,
+            referenced: &str,
+        ) -> DbResult<Vec<(DbId, DbId)>> {
+            let res = self
+                .conn
+                .prepare(format!(
+                    "SELECT id, {column} FROM {table}
+                    WHERE {column} NOT IN (SELECT id FROM {referenced}) ORDER BY id"
+                ))?
+                .iter()
+                .map(|r| {
+                    let row = r?;
+                    Ok((row.read::<i64, usize>(0), row.read::<i64, usize>(1)))
+                })
+                .collect();
+            res
+        }
+
+        /// (filter id, pattern id) of the filter items with a missing pattern.
+        pub fn missing_filter_patterns(&self) -> DbResult<Vec<(DbId, DbId)>> {
+            let res = self
+                .conn
+                .prepare(
+                    "SELECT glob_filter_id, glob_pattern_id FROM glob_filter_to_pattern
+                    WHERE glob_pattern_id NOT IN (SELECT id FROM glob_patterns)
+                    ORDER BY glob_filter_id, position",
+                )?
+                .iter()
+                .map(|r| {
+                    let row = r?;
+                    Ok((row.read::<i64, usize>(0), row.read::<i64, usize>(1)))
+                })
+                .collect();
+            res
+        }
+
+        /// (entry id, directory id) of all dir_to_sub rows.
+        pub fn dir_links(&self) -> DbResult<Vec<(DbId, DbId)>> {
+            let res = self
+                .conn
+                .prepare("SELECT entry_id, directory_id FROM dir_to_sub")?
+                .iter()
+                .map(|r| {
+                    let row = r?;
+                    Ok((row.read::<i64, usize>(0), row.read::<i64, usize>(1)))
+                })
+                .collect();
+            res
+        }
+
+        pub fn dir_entry_ids(&self) -> DbResult<Vec<DbId>> {
+            let res = self
+                .conn
+                .prepare("SELECT id FROM dir_entries ORDER BY id")?
+                .iter()
+                .map(|r| Ok(r?.read::<i64, usize>(0)))
+                .collect();
+            res
+        }
+
+        /// mime type -> number of files, sorted by mime type
+        pub fn mime_type_counts(&self) -> DbResult<Vec<(String, usize)>> {
+            let res = self
+                .conn
+                .prepare(
+                    "SELECT t.mime_value, COUNT(m.id) FROM mime_types t
+                    JOIN mime_to_dir_entry m ON m.mime_type_id = t.id
+                    GROUP BY t.id ORDER BY t.mime_value",
+                )?
+                .iter()
+                .map(|r| {
+                    let row = r?;
+                    Ok((
+                        row.read::<&str, usize>(0).to_owned(),
+                        row.read::<i64, usize>(1) as usize,
+                    ))
+                })
+                .collect();
+            res
+        }
+
+        /// `mime_glob` is an SQL GLOB, e.g. 'video/*'
+        pub fn entries_with_mime(&self, mime_glob: &str) -> DbResult<Vec<DbId>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT m.dir_entry_id FROM mime_to_dir_entry m
+                JOIN mime_types t ON t.id = m.mime_type_id
+                WHERE t.mime_value GLOB ?1 ORDER BY m.dir_entry_id",
+            )?;
+            stmt.bind((1, mime_glob))?;
+            let res = stmt.iter().map(|r| Ok(r?.read::<i64, usize>(0))).collect();
+            res
+        }
+
+        pub fn tag_usage_count(&self, tag_id: DbId) -> DbResult<usize> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT COUNT(*) FROM tag_to_dir_entry WHERE subject_tag_id = ?1")?;
+            stmt.bind((1, tag_id))?;
+            stmt.next()?;
+            Ok(stmt.read::<i64, _>(0)? as usize)
+        }
+
+        pub fn file_hash(&self, entry_id: DbId) -> DbResult<Option<FileHash>> {
+            let mut stmt = self.conn.prepare(
+                "SELECT dir_entry_id, hash_value, fs_mod_time, fs_size FROM file_hashes
+                WHERE dir_entry_id = ?1",
+            )?;
+            stmt.bind((1, entry_id))?;
+            match stmt.iter().next() {
+                Some(row) => Ok(Some(FileHash::from_row(&row?))),
+                None => Ok(None),
+            }
+        }
+
+        /// Files of all collections matching the SQL `condition` over `e`
+        /// (dir_entries), `c` (collections), `ct` (capture_times) and
+        /// `tree.rel_path`, see query.rs.
+        pub fn select_files(
+            &self,
+            condition: &str,
+            params: &[sqlite::Value],
+            order: SelectOrder,
+        ) -> DbResult<Vec<SelectedFile>> {
+            let order_by = match order {
+                SelectOrder::Path => "c.coll_name, tree.rel_path",
+                SelectOrder::ModTime => "e.fs_mod_time, c.coll_name, tree.rel_path",
+                SelectOrder::Taken => {
+                    "COALESCE(ct.date_time, e.fs_mod_time), c.coll_name, tree.rel_path"
+                }
+            };
+            let mut stmt = self.conn.prepare(format!(
+                "{COLLECTION_TREE_SQL}
+                SELECT c.coll_name, c.fs_path, tree.rel_path FROM tree
+                JOIN dir_entries e ON e.id = tree.id
+                JOIN collections c ON c.id = tree.col_id
+                LEFT JOIN capture_times ct ON ct.dir_entry_id = e.id
+                WHERE e.is_dir = 0 AND ({condition})
+                ORDER BY {order_by}"
+            ))?;
+            for (i, param) in params.iter().enumerate() {
+                stmt.bind((i + 1, param))?;
+            }
+            let res = stmt
+                .iter()
+                .map(|r| {
+                    let row = r?;
+                    Ok(SelectedFile {
+                        coll_name: row.read::<&str, usize>(0).to_owned(),
+                        fs_path: row.read::<&str, usize>(1).to_owned(),
+                        rel_path: row.read::<&str, usize>(2).to_owned(),
+                    })
+                })
+                .collect();
+            res
+        }
+
+        /// Non-empty files of all collections sharing the content hash with
+        /// another file, or the size if `by_size`. Files without a valid hash
+        /// are skipped unless `by_size`. Sorted by key, collection name and path.
+        pub fn duplicate_files(&self, by_size: bool) -> DbResult<Vec<DuplicateFile>> {
+            let key = if by_size {
+                "CAST(e.fs_size AS TEXT)"
+            } else {
+                "h.hash_value"
+            };
+            let mut stmt = self.conn.prepare(format!(
+                "{COLLECTION_TREE_SQL},
+                files(key, id, col_id, rel_path) AS (
+                    SELECT {key}, e.id, tree.col_id, tree.rel_path FROM tree
+                    JOIN dir_entries e ON e.id = tree.id
+                    LEFT JOIN file_hashes h ON h.dir_entry_id = e.id
+                        AND h.fs_mod_time = e.fs_mod_time AND h.fs_size = e.fs_size
+                    WHERE e.is_dir = 0 AND e.fs_size > 0)
+                SELECT f.key, e.id, e.fs_name, e.fs_mod_time, e.last_sync_time, e.is_dir,
+                    e.fs_size, c.coll_name, c.fs_path, f.rel_path FROM files f
+                JOIN dir_entries e ON e.id = f.id
+                JOIN collections c ON c.id = f.col_id
+                WHERE f.key IN (SELECT key FROM files WHERE key IS NOT NULL
+                    GROUP BY key HAVING COUNT(*) > 1)
+                ORDER BY f.key, c.coll_name, f.rel_path"
+            ))?;
+            let res = stmt
+                .iter()
+                .map(|r| {
+                    let row = r?;
+                    Ok(DuplicateFile {
+                        key: row.read::<&str, usize>(0).to_owned(),
+                        entry: DirEntry {
+                            id: row.read::<i64, usize>(1),
+                            fs_name: row.read::<&str, usize>(2).to_owned(),
+                            fs_mod_time: row.read::<i64, usize>(3),
+                            last_sync_time: row.read::<i64, usize>(4),
+                            is_dir: row.read::<i64, usize>(5) != 0,
+                            fs_size: row.read::<i64, usize>(6),
+                        },
+                        coll_name: row.read::<&str, usize>(7).to_owned(),
+                        fs_path: row.read::<&str, usize>(8).to_owned(),
+                        rel_path: row.read::<&str, usize>(9).to_owned(),
+                    })
+                })
+                .collect();
+            res
+        }
+
+        /// Number of non-empty files without a hash matching their size and
+        /// modification time.
+        pub fn unhashed_file_count(&self) -> DbResult<usize> {
+            let mut stmt = self.conn.prepare(
+                "SELECT COUNT(*) FROM dir_entries e
+                LEFT JOIN file_hashes h ON h.dir_entry_id = e.id
+                    AND h.fs_mod_time = e.fs_mod_time AND h.fs_size = e.fs_size
+                WHERE e.is_dir = 0 AND e.fs_size > 0 AND h.id IS NULL",
+            )?;
+            stmt.next()?;
+            Ok(stmt.read::<i64, _>(0)? as usize)
+        }
+
+        /// The tag and all tags below it, sorted by value.
+        pub fn tag_subtree(&self, tag_id: DbId) -> DbResult<Vec<SubjectTag>> {
+            let mut stmt = self.conn.prepare(format!(
+                "SELECT id, tag_value, parent_id FROM subject_tags
+                WHERE id IN ({TAG_SUBTREE_SQL}) ORDER BY tag_value"
+            ))?;
+            stmt.bind((1, tag_id))?;
+            let res = stmt.iter().map(|r| Ok(SubjectTag::from_row(&r?))).collect();
+            res
+        }
+
+        /// Entries tagged with the tag or any tag below it, e.g. 'places'
+        /// includes 'places|france|nice'.
+        pub fn entries_with_tag_subtree(&self, tag_id: DbId) -> DbResult<Vec<DbId>> {
+            let mut stmt = self.conn.prepare(format!(
+                "SELECT DISTINCT dir_entry_id FROM tag_to_dir_entry
+                WHERE subject_tag_id IN ({TAG_SUBTREE_SQL}) ORDER BY dir_entry_id"
+            ))?;
+            stmt.bind((1, tag_id))?;
+            let res = stmt.iter().map(|r| Ok(r?.read::<i64, usize>(0))).collect();
+            res
+        }
+
+        pub fn entries_with_tag(&self, tag_id: DbId) -> DbResult<Vec<DbId>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT dir_entry_id FROM tag_to_dir_entry WHERE subject_tag_id = ?1")?;
+            stmt.bind((1, tag_id))?;
+            let res = stmt.iter().map(|r| Ok(r?.read::<i64, usize>(0))).collect();
+            res
+        }
+
+        pub fn parent_dir_id(&self, entry_id: DbId) -> DbResult<Option<DbId>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT directory_id FROM dir_to_sub WHERE entry_id = ?1")?;
+            stmt.bind((1, entry_id))?;
+            match stmt.iter().next() {
+                Some(row) => Ok(Some(row?.read::<i64, usize>(0))),
+                None => Ok(None),
+            }
+        }
+
+        pub fn collection_by_root_id(&self, root_id: DbId) -> DbResult<Option<Collection>> {
+            let mut prep = self.conn.prepare(
+                "SELECT id, coll_name, fs_path,
+                root_id, glob_filter_id FROM collections WHERE root_id=?1",
+            )?;
+            prep.bind((1, root_id))?;
+            match prep.into_iter().next() {
+                Some(row) => Ok(Some(Collection::from_row(&row?))),
+                None => Ok(None),
+            }
+        }
+
+        /// Collection of the entry and the '/' separated path relative to its root.
+        /// None for entries outside of collections, also for parent cycles of
+        /// a corrupt db, see fsck.
+        pub fn entry_location(&self, entry_id: DbId) -> DbResult<Option<(Collection, String)>> {
+            let mut names = Vec::new();
+            let mut visited = HashSet::new();
+            let mut id = entry_id;
+            loop {
+                if !visited.insert(id) {
+                    return Ok(None);
+                }
+                if let Some(col) = self.collection_by_root_id(id)? {
+                    names.reverse();
+                    return Ok(Some((col, names.join("/"))));
+                }
+                match self.dir_entry_by_id(id)? {
+                    Some(entry) => names.push(entry.fs_name),
+                    None => return Ok(None),
+                }
+                id = match self.parent_dir_id(id)? {
+                    Some(parent_id) => parent_id,
+                    None => return Ok(None),
+                };
+            }
+        }
+
+        pub fn dir_entry_by_id(&self, id: DbId) -> DbResult<Option<DirEntry>> {
+            let mut prep = self.conn.prepare(
+                "SELECT id, fs_name, fs_mod_time,
+                    last_sync_time, is_dir, fs_size FROM dir_entries
+                    WHERE id = ?1",
+            )?;
+            prep.bind((1, id))?;
+            for row in prep.into_iter() {
+                let row = row?;
+                return Ok(Some(DirEntry::from_row(&row)));
+            }
+            Ok(None)
+        }
+    }
+}
+
+pub fn nullable_from_option<T>(o: Option<T>) -> sqlite::Value
+where
+    sqlite::Value: From<T>,
+{
+    let r: sqlite::Value = match o {
+        Some(v) => v.into(),
+        None => sqlite::Value::Null,
+    };
+    r
+}
+
+pub fn value_from_bool(b: bool) -> sqlite::Value {
+    (b as i64).into()
+}
+
+/// For optimization hints before performing many inserts use Transaction.
+pub mod write {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::dblayer::{Collection, DbId, DbResult, DirEntry};
+
+    pub struct IdGen {
+        next_id: DbId,
+    }
+
+    impl IdGen {
+        /// pass last id, e.g. result of select max(id) from my_sql_table;
+        pub fn new_with_last_id(last_id: DbId) -> IdGen {
+            IdGen {
+                next_id: last_id + 1,
+            }
+        }
+
+        pub fn gen_id(&mut self) -> DbId {
+            let result = self.next_id;
+            self.next_id += 1;
+            result
+        }
+    }
+
+    #[derive(Error, Debug)]
+    pub enum DeleteError {
+        #[error("Db error: {error}")]
+        DbError { error: DbError },
+        #[error("Sqlite error: {error}")]
+        SqliteError { error: sqlite::Error },
+        #[error("Not a file: {name}")]
+        NotAfile { name: String },
+        #[error("Not a directory: {name}")]
+        NotAdir { name: String },
+        #[error("No root entry")]
+        NoRootEntry,
+    }
+
+    impl From<sqlite::Error> for DeleteError {
+        fn from(error: sqlite::Error) -> Self {
+            DeleteError::SqliteError { error }
+        }
+    }
+
+    impl From<DbError> for DeleteError {
+        fn from(error: DbError) -> Self {
+            DeleteError::DbError { error }
+        }
+    }
+
+    pub type DeleteResult<T> = Result<T, DeleteError>;
+
+    pub struct Db<'a> {
+        pub conn: &'a sqlite::Connection,
+        create_dir: sqlite::Statement<'a>,
+        update_dir: sqlite::Statement<'a>,
+        map_dir: sqlite::Statement<'a>,
+    }
+
+    impl Db<'_> {
+        /// Doesn't write, see Transaction::commit.
+        pub fn new<'a>(conn: &'a sqlite::Connection) -> DbResult<Db<'a>> {
+            Ok(Db {
+                conn,
+                create_dir: conn.prepare(
                     "INSERT INTO dir_entries(id, fs_name,
                 fs_mod_time, last_sync_time, is_dir, fs_size)
                 VALUES(:id, :fs_name, :fs_mod_time, :last_sync_time,
                        :is_dir, :fs_size)",
                 )?,
+                update_dir: conn.prepare(
+                    "UPDATE dir_entries SET fs_name = :fs_name,
+                fs_mod_time = :fs_mod_time, last_sync_time = :last_sync_time,
+                is_dir = :is_dir, fs_size = :fs_size WHERE id = :id",
+                )?,
                 map_dir: conn.prepare(
                     "INSERT INTO dir_to_sub(directory_id,
                 entry_id) VALUES(:directory_id, :entry_id)",
@@ -545,6 +1531,24 @@ pub mod write {
             Ok(())
         }
 
+        /// Overwrites all fields of an existing entry with the same id.
+        pub fn update_dir_entry(&mut self, entry: &DirEntry) -> DbResult<()> {
+            self.update_dir.bind_iter::<_, (_, sqlite::Value)>([
+                (":id", entry.id.into()),
+                (":fs_name", entry.fs_name.clone().into()),
+                (":fs_mod_time", entry.fs_mod_time.into()),
+                (":last_sync_time", entry.last_sync_time.into()),
+                (":is_dir", value_from_bool(entry.is_dir)),
+                (":fs_size", entry.fs_size.into()),
+            ])?;
+
+            while let sqlite::State::Row = self.update_dir.next()? {}
+
+            self.update_dir.reset()?;
+
+            Ok(())
+        }
+
         pub fn max_id(&self, table_name: &str) -> DbId {
             for row in self
                 .conn
@@ -558,6 +1562,234 @@ pub mod write {
             -1
         }
 
+        pub fn set_setting_value(&self, key: &str, value: &str) -> DbResult<()> {
+            let mut stmt = self.conn.prepare(
+                "INSERT INTO settings(setting_key, setting_value) VALUES(?1, ?2)
+                ON CONFLICT(setting_key) DO UPDATE SET setting_value = excluded.setting_value",
+            )?;
+            stmt.bind((1, key))?;
+            stmt.bind((2, value))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
+        pub fn delete_setting(&self, key: &str) -> DbResult<()> {
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM settings WHERE setting_key = ?1",
+                key,
+            )?;
+            Ok(())
+        }
+
+        fn subject_tag_id(&self, tag_value: &str) -> DbResult<Option<DbId>> {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT id FROM subject_tags WHERE tag_value = ?1")?;
+            stmt.bind((1, tag_value))?;
+            match stmt.iter().next() {
+                Some(row) => Ok(Some(row?.read::<i64, usize>(0))),
+                None => Ok(None),
+            }
+        }
+
+        /// Parent tag id for the value, missing parent tags are created.
+        fn ensure_parent_tag(&self, tag_value: &str) -> DbResult<Option<DbId>> {
+            match SubjectTag::parent_value(tag_value) {
+                Some(parent_value) => match self.subject_tag_id(parent_value)? {
+                    Some(id) => Ok(Some(id)),
+                    None => Ok(Some(self.create_subject_tag(parent_value)?.id)),
+                },
+                None => Ok(None),
+            }
+        }
+
+        /// Creates missing parent tags too, e.g. 'places' and 'places|france'
+        /// for 'places|france|nice'.
+        pub fn create_subject_tag(&self, tag_value: &str) -> DbResult<SubjectTag> {
+            let mut tx = Transaction::new(self.conn);
+            let parent_id = self.ensure_parent_tag(tag_value)?;
+            let new_id = self.max_id(SubjectTag::table_name()) + 1;
+            let mut stmt = self
+                .conn
+                .prepare("INSERT INTO subject_tags(id, tag_value, parent_id) VALUES(?1, ?2, ?3)")?;
+            stmt.bind((1, new_id))?;
+            stmt.bind((2, tag_value))?;
+            stmt.bind((3, parent_id))?;
+            complete_statement(&mut stmt)?;
+            tx.commit();
+            Ok(SubjectTag {
+                id: new_id,
+                tag_value: tag_value.to_owned(),
+                parent_id,
+            })
+        }
+
+        /// Renames the tags below it too, the new value can move the tag
+        /// to another parent, e.g. 'nice' to 'places|france|nice'.
+        pub fn rename_subject_tag(&self, tag_id: DbId, new_value: &str) -> DbResult<()> {
+            let mut tx = Transaction::new(self.conn);
+            let mut stmt = self
+                .conn
+                .prepare("SELECT tag_value FROM subject_tags WHERE id = ?1")?;
+            stmt.bind((1, tag_id))?;
+            let old_value = match stmt.iter().next() {
+                Some(row) => row?.read::<&str, usize>(0).to_owned(),
+                None => return Ok(()),
+            };
+            let parent_id = self.ensure_parent_tag(new_value)?;
+
+            let mut stmt = self
+                .conn
+                .prepare("UPDATE subject_tags SET tag_value = ?2, parent_id = ?3 WHERE id = ?1")?;
+            stmt.bind((1, tag_id))?;
+            stmt.bind((2, new_value))?;
+            stmt.bind((3, parent_id))?;
+            complete_statement(&mut stmt)?;
+
+            let mut stmt = self.conn.prepare(
+                "UPDATE subject_tags SET tag_value = ?2 || substr(tag_value, length(?1) + 1)
+                WHERE substr(tag_value, 1, length(?1) + 1) = ?1 || ?3",
+            )?;
+            stmt.bind((1, old_value.as_str()))?;
+            stmt.bind((2, new_value))?;
+            stmt.bind((3, TAG_PATH_SEPARATOR.to_string().as_str()))?;
+            complete_statement(&mut stmt)?;
+            tx.commit();
+            Ok(())
+        }
+
+        /// Deletes the tag and the tags below it, unassigns them from all entries.
+        pub fn delete_subject_tag(&self, tag_id: DbId) -> DbResult<()> {
+            let mut tx = Transaction::new(self.conn);
+            exec_sql_stmt_with_arg(
+                self.conn,
+                &format!(
+                    "DELETE FROM tag_to_dir_entry WHERE subject_tag_id IN ({TAG_SUBTREE_SQL})"
+                ),
+                tag_id,
+            )?;
+            exec_sql_stmt_with_arg(
+                self.conn,
+                &format!("DELETE FROM subject_tags WHERE id IN ({TAG_SUBTREE_SQL})"),
+                tag_id,
+            )?;
+            tx.commit();
+            Ok(())
+        }
+
+        pub fn set_file_hash(&self, hash: &FileHash) -> DbResult<()> {
+            let mut stmt = self.conn.prepare(
+                "INSERT INTO file_hashes(dir_entry_id, hash_value, fs_mod_time, fs_size)
+                VALUES(?1, ?2, ?3, ?4)
+                ON CONFLICT(dir_entry_id) DO UPDATE SET hash_value = excluded.hash_value,
+                fs_mod_time = excluded.fs_mod_time, fs_size = excluded.fs_size",
+            )?;
+            stmt.bind((1, hash.dir_entry_id))?;
+            stmt.bind((2, hash.hash_value.as_str()))?;
+            stmt.bind((3, hash.fs_mod_time))?;
+            stmt.bind((4, hash.fs_size))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
+        /// Removes the capture time if None.
+        pub fn set_capture_time(&self, entry_id: DbId, date_time: Option<DbTime>) -> DbResult<()> {
+            let Some(date_time) = date_time else {
+                exec_sql_stmt_with_arg(
+                    self.conn,
+                    "DELETE FROM capture_times WHERE dir_entry_id = ?1",
+                    entry_id,
+                )?;
+                return Ok(());
+            };
+            let mut stmt = self.conn.prepare(
+                "INSERT INTO capture_times(dir_entry_id, date_time) VALUES(?1, ?2)
+                ON CONFLICT(dir_entry_id) DO UPDATE SET date_time = excluded.date_time",
+            )?;
+            stmt.bind((1, entry_id))?;
+            stmt.bind((2, date_time))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
+        /// Removes the media info if None or empty.
+        pub fn set_media_info(&self, entry_id: DbId, info: Option<&MediaInfo>) -> DbResult<()> {
+            let Some(info) = info.filter(|info| !info.is_empty()) else {
+                exec_sql_stmt_with_arg(
+                    self.conn,
+                    "DELETE FROM media_info WHERE dir_entry_id = ?1",
+                    entry_id,
+                )?;
+                return Ok(());
+            };
+            let mut stmt = self.conn.prepare(format!(
+                "INSERT OR REPLACE INTO media_info(dir_entry_id, {})
+                VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                MediaInfo::COLUMNS
+            ))?;
+            stmt.bind((1, entry_id))?;
+            stmt.bind((2, info.width))?;
+            stmt.bind((3, info.height))?;
+            stmt.bind((4, info.orientation))?;
+            stmt.bind((5, info.camera_make.as_deref()))?;
+            stmt.bind((6, info.camera_model.as_deref()))?;
+            stmt.bind((7, info.lens.as_deref()))?;
+            stmt.bind((8, info.focal_length))?;
+            stmt.bind((9, info.exposure_time))?;
+            stmt.bind((10, info.f_number))?;
+            stmt.bind((11, info.iso))?;
+            stmt.bind((12, info.latitude))?;
+            stmt.bind((13, info.longitude))?;
+            stmt.bind((14, info.altitude))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
+        /// Replaces the mime type of the entry, adds the type if it is new.
+        pub fn set_mime_type(&self, entry_id: DbId, mime_value: &str) -> DbResult<()> {
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "INSERT OR IGNORE INTO mime_types(mime_value) VALUES(?1)",
+                mime_value,
+            )?;
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM mime_to_dir_entry WHERE dir_entry_id = ?1",
+                entry_id,
+            )?;
+            let mut stmt = self.conn.prepare(
+                "INSERT INTO mime_to_dir_entry(mime_type_id, dir_entry_id)
+                SELECT id, ?2 FROM mime_types WHERE mime_value = ?1",
+            )?;
+            stmt.bind((1, mime_value))?;
+            stmt.bind((2, entry_id))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
+        /// Does nothing if the tag is already assigned.
+        pub fn assign_tag(&self, tag_id: DbId, entry_id: DbId) -> DbResult<()> {
+            let mut stmt = self.conn.prepare(
+                "INSERT OR IGNORE INTO tag_to_dir_entry(subject_tag_id, dir_entry_id)
+                VALUES(?1, ?2)",
+            )?;
+            stmt.bind((1, tag_id))?;
+            stmt.bind((2, entry_id))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
+        pub fn unassign_tag(&self, tag_id: DbId, entry_id: DbId) -> DbResult<()> {
+            let mut stmt = self.conn.prepare(
+                "DELETE FROM tag_to_dir_entry WHERE subject_tag_id = ?1 AND dir_entry_id = ?2",
+            )?;
+            stmt.bind((1, tag_id))?;
+            stmt.bind((2, entry_id))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
         pub fn create_collection(
             &self,
             coll_name: &str,
@@ -606,6 +1838,28 @@ pub mod write {
             Ok(())
         }
 
+        /// Re-parents the entry keeping its id and all associated data.
+        pub fn move_dir_entry(&self, entry_id: DbId, parent_id: DbId) -> DbResult<()> {
+            let mut stmt = self
+                .conn
+                .prepare("UPDATE dir_to_sub SET directory_id = ?2 WHERE entry_id = ?1")?;
+            stmt.bind((1, entry_id))?;
+            stmt.bind((2, parent_id))?;
+            while let sqlite::State::Row = stmt.next()? {}
+            Ok(())
+        }
+
+        /// Re-parents all entries of the directory `from_id` to `to_id`.
+        pub fn move_dir_children(&self, from_id: DbId, to_id: DbId) -> DbResult<()> {
+            let mut stmt = self
+                .conn
+                .prepare("UPDATE dir_to_sub SET directory_id = ?2 WHERE directory_id = ?1")?;
+            stmt.bind((1, from_id))?;
+            stmt.bind((2, to_id))?;
+            while let sqlite::State::Row = stmt.next()? {}
+            Ok(())
+        }
+
         fn delete_dir_entry_dir(&self, entry: DirEntry) -> DeleteResult<()> {
             if !entry.is_dir {
                 return Err(DeleteError::NotAfile {
@@ -646,26 +1900,41 @@ pub mod write {
                     name: entry.fs_name,
                 });
             }
-            // TODO thumbnails
+            // thumbnail dbs are cleaned up by thumbnails::prune_profiles
             exec_sql_stmt_with_arg(self.conn, "DELETE FROM dir_entries WHERE id = ?1", entry.id)?;
             exec_sql_stmt_with_arg(
                 self.conn,
-                "DELETE FROM dir_to_sub WHERE entry_id = ?1",
+                "DELETE FROM dir_to_sub WHERE entry_id = ?1",
+                entry.id,
+            )?;
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM tag_to_dir_entry WHERE dir_entry_id = ?1",
+                entry.id,
+            )?;
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM last_edit WHERE dir_entry_id = ?1",
+                entry.id,
+            )?;
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM mime_to_dir_entry WHERE dir_entry_id = ?1",
                 entry.id,
             )?;
             exec_sql_stmt_with_arg(
                 self.conn,
-                "DELETE FROM tag_to_dir_entry WHERE dir_entry_id = ?1",
+                "DELETE FROM capture_times WHERE dir_entry_id = ?1",
                 entry.id,
             )?;
             exec_sql_stmt_with_arg(
                 self.conn,
-                "DELETE FROM last_edit WHERE dir_entry_id = ?1",
+                "DELETE FROM media_info WHERE dir_entry_id = ?1",
                 entry.id,
             )?;
             exec_sql_stmt_with_arg(
                 self.conn,
-                "DELETE FROM mime_to_dir_entry WHERE dir_entry_id = ?1",
+                "DELETE FROM file_hashes WHERE dir_entry_id = ?1",
                 entry.id,
             )?;
             Ok(())
@@ -696,6 +1965,176 @@ pub mod write {
             exec_sql_stmt_with_arg(self.conn, "DELETE FROM collections WHERE id = ?1", col.id)?;
             Ok(())
         }
+
+        pub fn create_glob_filter(&self, name: &str) -> DbResult<GlobFilter> {
+            let new_id = self.max_id("glob_filters") + 1;
+            let mut stmt = self
+                .conn
+                .prepare("INSERT INTO glob_filters(id, name) VALUES(?1, ?2)")?;
+            stmt.bind((1, new_id))?;
+            stmt.bind((2, name))?;
+            complete_statement(&mut stmt)?;
+            Ok(GlobFilter {
+                id: new_id,
+                name: name.to_owned(),
+            })
+        }
+
+        /// Deletes the filter with its rules, unless a collection or the
+        /// 'default_filter' setting refers to it.
+        pub fn delete_glob_filter(&self, filter: &GlobFilter) -> DbResult<()> {
+            let rdb = read::Db::new(self.conn)?;
+            let collections = rdb.collections_by_filter(filter.id)?;
+            if !collections.is_empty() {
+                let names: Vec<String> = collections
+                    .iter()
+                    .map(|c| format!("'{}'", c.coll_name))
+                    .collect();
+                return Err(DbError::FilterInUse {
+                    name: filter.name.clone(),
+                    users: format!("collections {}", names.join(", ")),
+                });
+            }
+            if rdb.setting_value(DEFAULT_FILTER_KEY)? == Some(filter.id.to_string()) {
+                return Err(DbError::FilterInUse {
+                    name: filter.name.clone(),
+                    users: format!("setting '{DEFAULT_FILTER_KEY}'"),
+                });
+            }
+            let mut tx = Transaction::new(self.conn);
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM glob_filter_to_pattern WHERE glob_filter_id = ?1",
+                filter.id,
+            )?;
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM glob_filters WHERE id = ?1",
+                filter.id,
+            )?;
+            tx.commit();
+            Ok(())
+        }
+
+        /// Replaces the rules of the filter by (pattern id, include, target)
+        /// in the order of application, positions are renumbered from 1.
+        pub fn set_filter_rules(
+            &self,
+            filter_id: DbId,
+            rules: &[(DbId, bool, RuleTarget)],
+        ) -> DbResult<()> {
+            let rdb = read::Db::new(self.conn)?;
+            let mut tx = Transaction::new(self.conn);
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM glob_filter_to_pattern WHERE glob_filter_id = ?1",
+                filter_id,
+            )?;
+            let mut stmt = self.conn.prepare(
+                "INSERT INTO glob_filter_to_pattern(glob_filter_id, glob_pattern_id, include,
+                position, target) VALUES(?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (position, (pattern_id, include, target)) in rules.iter().enumerate() {
+                if rdb.glob_pattern(*pattern_id)?.is_none() {
+                    return Err(DbError::FilterToGlobIntegrityError {
+                        filter_id,
+                        glob_id: *pattern_id,
+                    });
+                }
+                stmt.reset()?;
+                stmt.bind((1, filter_id))?;
+                stmt.bind((2, *pattern_id))?;
+                stmt.bind((3, *include as i64))?;
+                stmt.bind((4, position as i64 + 1))?;
+                stmt.bind((5, target.as_str()))?;
+                complete_statement(&mut stmt)?;
+            }
+            tx.commit();
+            Ok(())
+        }
+
+        /// Fails with InvalidPattern if the text doesn't compile.
+        pub fn create_glob_pattern(&self, kind: PatternKind, text: &str) -> DbResult<GlobPattern> {
+            let mut pattern = GlobPattern {
+                id: 0,
+                regexp: text.to_owned(),
+                kind,
+            };
+            pattern.compile()?;
+            pattern.id = self.max_id("glob_patterns") + 1;
+            let mut stmt = self
+                .conn
+                .prepare("INSERT INTO glob_patterns(id, regexp, kind) VALUES(?1, ?2, ?3)")?;
+            stmt.bind((1, pattern.id))?;
+            stmt.bind((2, text))?;
+            stmt.bind((3, kind.as_str()))?;
+            complete_statement(&mut stmt)?;
+            Ok(pattern)
+        }
+
+        /// The text is not validated, see create_glob_pattern.
+        pub fn set_glob_pattern_text(&self, pattern_id: DbId, text: &str) -> DbResult<()> {
+            let mut stmt = self
+                .conn
+                .prepare("UPDATE glob_patterns SET regexp = ?2 WHERE id = ?1")?;
+            stmt.bind((1, pattern_id))?;
+            stmt.bind((2, text))?;
+            complete_statement(&mut stmt)?;
+            Ok(())
+        }
+
+        /// Deletes the pattern unless a filter has a rule with it.
+        pub fn delete_glob_pattern(&self, pattern_id: DbId) -> DbResult<()> {
+            let rdb = read::Db::new(self.conn)?;
+            let filters = rdb.filters_by_pattern(pattern_id)?;
+            if !filters.is_empty() {
+                let names: Vec<String> = filters.iter().map(|f| format!("'{}'", f.name)).collect();
+                return Err(DbError::PatternInUse {
+                    id: pattern_id,
+                    filters: names.join(", "),
+                });
+            }
+            exec_sql_stmt_with_arg(
+                self.conn,
+                "DELETE FROM glob_patterns WHERE id = ?1",
+                pattern_id,
+            )?;
+            Ok(())
+        }
+
+        /// Deletes the rows by id only, e.g. dir entries without the
+        /// mappings, see delete_dangling_rows.
+        pub fn delete_rows(&self, table: &str, ids: &[DbId]) -> DbResult<()> {
+            let mut stmt = self
+                .conn
+                .prepare(format!("DELETE FROM {table} WHERE id = ?1"))?;
+            for id in ids {
+                stmt.reset()?;
+                exec_statement_with_arg(&mut stmt, *id)?;
+            }
+            Ok(())
+        }
+
+        /// Deletes `table` rows whose `column` is not an id of `referenced`.
+        pub fn delete_dangling_rows(
+            &self,
+            table: &str,
+            column: &str,
+            referenced: &str,
+        ) -> DbResult<()> {
+            self.conn.execute(format!(
+                "DELETE FROM {table} WHERE {column} NOT IN (SELECT id FROM {referenced})"
+            ))?;
+            Ok(())
+        }
+
+        /// Collection roots are never subentries.
+        pub fn unlink_collection_roots(&self) -> DbResult<()> {
+            self.conn.execute(
+                "DELETE FROM dir_to_sub WHERE entry_id IN (SELECT root_id FROM collections)",
+            )?;
+            Ok(())
+        }
     }
 
     pub fn create_new(fname: &str) -> DbResult<sqlite::Connection> {
@@ -719,13 +2158,77 @@ pub mod write {
         eprintln!("reading schema {}", schema);
         let sql = read_to_string(schema)?;
         conn.execute(sql)?;
+        migrate(&conn, 1)?;
         Ok(conn)
     }
+
+    /// Migrates an older database to [`DB_VERSION`], a copy of the file
+    /// is kept next to it, see [`backup_path`]. Returns the backup path,
+    /// or `None` if the database is up to date.
+    pub fn upgrade(fname: &str, conn: &sqlite::Connection) -> DbResult<Option<PathBuf>> {
+        let version = db_version(conn)?.ok_or_else(|| DbError::WrongDbSchema {
+            name: fname.to_owned(),
+        })?;
+        if version >= DB_VERSION {
+            return Ok(None);
+        }
+
+        let backup = if fname == ":memory:" {
+            None
+        } else {
+            let backup = backup_path(fname, version);
+            std::fs::copy(fname, &backup)?;
+            Some(backup)
+        };
+        eprintln!(
+            "migrating '{}' from version {} to {}, backup {:?}",
+            fname, version, DB_VERSION, backup
+        );
+        migrate(conn, version)?;
+        Ok(backup)
+    }
+
+    /// `{fname}.v{version}.bak`, or with a number appended if it exists,
+    /// e.g. `{fname}.v1.bak.2`.
+    pub fn backup_path(fname: &str, version: i64) -> PathBuf {
+        let path = PathBuf::from(format!("{fname}.v{version}.bak"));
+        let mut result = path.clone();
+        let mut n = 1;
+        while result.exists() {
+            n += 1;
+            result = PathBuf::from(format!("{}.{n}", path.display()));
+        }
+        result
+    }
+
+    /// Applies the migrations after `version` in one transaction.
+    fn migrate(conn: &sqlite::Connection, version: i64) -> DbResult<()> {
+        let mut tx = Transaction::new(conn);
+        for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+            if let Some(script) = migration.script {
+                eprintln!("reading migration {}", script);
+                let sql = std::fs::read_to_string(script)?;
+                conn.execute(sql)?;
+            }
+            if let Some(after) = migration.after {
+                after(conn)?;
+            }
+            exec_sql_stmt_with_arg(
+                conn,
+                "UPDATE app_info SET info_value = ?1 WHERE info_key = 'db_version'",
+                migration.version.to_string().as_str(),
+            )?;
+        }
+        // sets app_version
+        tx.commit();
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::TmpDir;
     #[test]
     fn open_and_make() {
         let r = write::create_new(":memory:");
@@ -898,10 +2401,134 @@ mod tests {
         let conn = write::create_new(":memory:").unwrap();
         let db = read::Db::new(&conn).unwrap();
 
-        let mut default_filter = db.glob_filter_by_id(1).unwrap();
+        let default_filter = db.glob_filter_by_id(1).unwrap();
         eprintln!("default filter = {:?}", default_filter);
-        assert!(default_filter.include("/asdasd/abra.jpeg"));
-        assert_eq!(default_filter.include("asdasd/.git/abra.jpeg"), false);
+        assert!(default_filter.include("/asdasd/abra.jpeg", false));
+        assert_eq!(
+            default_filter.include("asdasd/.git/abra.jpeg", false),
+            false
+        );
+    }
+
+    #[test]
+    fn filter_editing() {
+        let conn = write::create_new(":memory:").unwrap();
+        let wdb = write::Db::new(&conn).unwrap();
+        let rdb = read::Db::new(&conn).unwrap();
+
+        let jpegs = wdb
+            .create_glob_pattern(PatternKind::Regexp, r"(?i)\.jpe?g$")
+            .unwrap();
+        assert!(matches!(
+            wdb.create_glob_pattern(PatternKind::Regexp, "(unclosed"),
+            Err(DbError::InvalidPattern { .. })
+        ));
+        assert!(matches!(
+            wdb.create_glob_pattern(PatternKind::Glob, "*.{jpg"),
+            Err(DbError::InvalidPattern { .. })
+        ));
+        let pngs = wdb
+            .create_glob_pattern(PatternKind::Glob, "**/*.png")
+            .unwrap();
+        assert_eq!(
+            rdb.glob_pattern(pngs.id).unwrap().unwrap().kind,
+            PatternKind::Glob
+        );
+        let filter = wdb.create_glob_filter("jpegs").unwrap();
+        assert_eq!(
+            rdb.glob_filter_by_name("jpegs").unwrap().unwrap().id,
+            filter.id
+        );
+        assert_eq!(rdb.glob_filter(filter.id).unwrap().unwrap().name, "jpegs");
+        // exclude everything by an empty filter
+        assert!(!rdb
+            .glob_filter_by_id(filter.id)
+            .unwrap()
+            .include("/a.jpg", false));
+
+        wdb.set_filter_rules(
+            filter.id,
+            &[
+                (25, true, RuleTarget::Both),
+                (jpegs.id, false, RuleTarget::Both),
+            ],
+        )
+        .unwrap();
+        let rules = rdb.filter_patterns(filter.id).unwrap();
+        let positions: Vec<_> = rules.iter().map(|r| r.position).collect();
+        assert_eq!(positions, [1, 2]);
+        let compiled = rdb.glob_filter_by_id(filter.id).unwrap();
+        assert!(compiled.include("/a.png", false));
+        assert!(!compiled.include("/a.JPG", false));
+
+        // a files only rule doesn't exclude directories
+        wdb.set_filter_rules(
+            filter.id,
+            &[
+                (25, true, RuleTarget::Both),
+                (jpegs.id, false, RuleTarget::Files),
+            ],
+        )
+        .unwrap();
+        let rules = rdb.filter_patterns(filter.id).unwrap();
+        assert_eq!(rules[1].target, RuleTarget::Files);
+        let compiled = rdb.glob_filter_by_id(filter.id).unwrap();
+        assert!(!compiled.include("/a.JPG", false));
+        assert!(compiled.include("/a.JPG", true));
+
+        wdb.set_filter_rules(filter.id, &[(pngs.id, true, RuleTarget::Both)])
+            .unwrap();
+        let compiled = rdb.glob_filter_by_id(filter.id).unwrap();
+        assert!(compiled.include("/media/a/b.png", false));
+        assert!(!compiled.include("/media/a/b.jpg", false));
+        // a broken pattern is an error, not a panic
+        conn.execute(format!(
+            "UPDATE glob_patterns SET regexp = '*.{{jpg' WHERE id = {}",
+            pngs.id
+        ))
+        .unwrap();
+        assert!(matches!(
+            rdb.glob_filter_by_id(filter.id),
+            Err(DbError::InvalidPattern { .. })
+        ));
+        wdb.set_filter_rules(
+            filter.id,
+            &[
+                (25, true, RuleTarget::Both),
+                (jpegs.id, false, RuleTarget::Both),
+            ],
+        )
+        .unwrap();
+        assert!(matches!(
+            wdb.set_filter_rules(filter.id, &[(1000, true, RuleTarget::Both)]),
+            Err(DbError::FilterToGlobIntegrityError { glob_id: 1000, .. })
+        ));
+        assert_eq!(rdb.filter_patterns(filter.id).unwrap().len(), 2);
+
+        assert!(matches!(
+            wdb.delete_glob_pattern(jpegs.id),
+            Err(DbError::PatternInUse { .. })
+        ));
+        wdb.create_collection("col", "/media", 1, filter.id)
+            .unwrap();
+        let err = wdb.delete_glob_filter(&filter).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "filter 'jpegs' is used by collections 'col'"
+        );
+        let default = rdb.glob_filter(DEFAULT_FILTER_ID).unwrap().unwrap();
+        let err = wdb.delete_glob_filter(&default).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "filter 'default' is used by setting 'default_filter'"
+        );
+
+        conn.execute("DELETE FROM collections").unwrap();
+        wdb.delete_glob_filter(&filter).unwrap();
+        assert!(rdb.glob_filter(filter.id).unwrap().is_none());
+        assert!(rdb.filter_patterns(filter.id).unwrap().is_empty());
+        wdb.delete_glob_pattern(jpegs.id).unwrap();
+        assert!(rdb.glob_pattern(jpegs.id).unwrap().is_none());
     }
 
     #[test]
@@ -1015,6 +2642,507 @@ mod tests {
         assert_eq!(db.max_id(DirEntry::table_name()), 1);
     }
 
+    #[test]
+    fn update_dir() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let mut file_entry = DirEntry {
+            id: 1,
+            fs_name: "myfile".to_owned(),
+            fs_mod_time: 1,
+            last_sync_time: 2,
+            is_dir: false,
+            fs_size: 7,
+        };
+        db.create_dir_entry(&file_entry).unwrap();
+        file_entry.fs_mod_time = 5;
+        file_entry.fs_size = 9;
+        db.update_dir_entry(&file_entry).unwrap();
+
+        let read_db = read::Db::new(&conn).unwrap();
+        let updated = read_db.dir_entry_by_id(1).unwrap().unwrap();
+        assert_eq!(updated.fs_mod_time, 5);
+        assert_eq!(updated.fs_size, 9);
+    }
+
+    #[test]
+    fn nested_tx() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let mut tx = Transaction::new(&conn);
+        let file_entry = DirEntry {
+            id: 1,
+            fs_name: "myfile".to_owned(),
+            fs_mod_time: 1,
+            last_sync_time: 2,
+            is_dir: false,
+            fs_size: 7,
+        };
+        db.create_dir_entry(&file_entry).unwrap();
+        // delete_dir_entry starts its own transaction
+        db.delete_dir_entry(file_entry).unwrap();
+        tx.rollback();
+        assert_eq!(db.max_id(DirEntry::table_name()), 0);
+    }
+
+    #[test]
+    fn file_hashes() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut wdb = write::Db::new(&conn).unwrap();
+        let entry = DirEntry {
+            id: 1,
+            fs_name: "myfile".to_owned(),
+            fs_mod_time: 1,
+            last_sync_time: 2,
+            is_dir: false,
+            fs_size: 7,
+        };
+        wdb.create_dir_entry(&entry).unwrap();
+        let mut hash = FileHash {
+            dir_entry_id: 1,
+            hash_value: "abc".to_owned(),
+            fs_mod_time: 1,
+            fs_size: 7,
+        };
+        wdb.set_file_hash(&hash).unwrap();
+        hash.hash_value = "def".to_owned();
+        wdb.set_file_hash(&hash).unwrap();
+
+        let rdb = read::Db::new(&conn).unwrap();
+        let stored = rdb.file_hash(1).unwrap().unwrap();
+        assert_eq!(stored, hash);
+        assert!(stored.is_valid_for(&entry));
+        assert!(!stored.is_valid_for(&DirEntry {
+            fs_size: 8,
+            ..entry.clone()
+        }));
+
+        wdb.delete_dir_entry(entry).unwrap();
+        assert!(rdb.file_hash(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn mime_types() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut wdb = write::Db::new(&conn).unwrap();
+        let mut entries = Vec::new();
+        for (id, name) in [(1, "a.jpg"), (2, "b.jpg"), (3, "c.mp4")] {
+            let entry = DirEntry {
+                id,
+                fs_name: name.to_owned(),
+                fs_mod_time: 1,
+                last_sync_time: 2,
+                is_dir: false,
+                fs_size: 7,
+            };
+            wdb.create_dir_entry(&entry).unwrap();
+            entries.push(entry);
+        }
+        wdb.set_mime_type(1, "image/jpeg").unwrap();
+        wdb.set_mime_type(2, "image/png").unwrap();
+        wdb.set_mime_type(2, "image/jpeg").unwrap();
+        wdb.set_mime_type(3, "video/mp4").unwrap();
+
+        let rdb = read::Db::new(&conn).unwrap();
+        assert_eq!(
+            rdb.mime_type_of_dir_entry(2).unwrap().as_deref(),
+            Some("image/jpeg")
+        );
+        assert_eq!(
+            rdb.mime_type_counts().unwrap(),
+            vec![("image/jpeg".to_owned(), 2), ("video/mp4".to_owned(), 1)]
+        );
+        assert_eq!(rdb.entries_with_mime("image/*").unwrap(), vec![1, 2]);
+        assert_eq!(rdb.entries_with_mime("video/*").unwrap(), vec![3]);
+
+        wdb.delete_dir_entry(entries.remove(2)).unwrap();
+        assert!(rdb.mime_type_of_dir_entry(3).unwrap().is_none());
+        assert!(rdb.entries_with_mime("video/*").unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrations() {
+        use std::fs;
+        let tmp = TmpDir::new("db-migrations");
+        let tmp_dir = tmp.path();
+        let fname = tmp_dir.join("old.db");
+        let fname = fname.to_str().unwrap();
+
+        {
+            // a catalogue of the first release
+            let conn = sqlite::Connection::open(fname).unwrap();
+            conn.execute(fs::read_to_string("sql/schema1.sql").unwrap())
+                .unwrap();
+            conn.execute("INSERT INTO subject_tags(id, tag_value) VALUES(1, 'places|france')")
+                .unwrap();
+            conn.execute(
+                r"INSERT INTO glob_patterns(id, regexp) VALUES(100, '\Qa+b\E\Z'), (101, '(?<=x)y')",
+            )
+            .unwrap();
+        }
+
+        let conn = read::open_existing(fname).unwrap();
+        assert_eq!(db_version(&conn).unwrap(), Some(DB_VERSION));
+        assert!(tmp_dir.join("old.db.v1.bak").exists());
+        let rdb = read::Db::new(&conn).unwrap();
+        let tags = rdb.enum_subject_tags().unwrap();
+        let values: Vec<_> = tags.iter().map(|t| t.tag_value.as_str()).collect();
+        assert_eq!(values, ["places", "places|france"]);
+        assert_eq!(tags[1].parent_id, Some(tags[0].id));
+        assert_eq!(rdb.media_info(1).unwrap(), None);
+        // PCRE syntax is converted, unsupported patterns are kept
+        let pattern = rdb.glob_pattern(100).unwrap().unwrap();
+        assert_eq!(pattern.regexp, r"a\+b\z");
+        assert_eq!(pattern.kind, PatternKind::Regexp);
+        assert!(rdb.glob_pattern(101).unwrap().unwrap().compile().is_err());
+        let rules = rdb.filter_patterns(DEFAULT_FILTER_ID).unwrap();
+        assert!(rules.iter().all(|r| r.target == RuleTarget::Both));
+        drop(rdb);
+
+        // up to date, no second backup
+        drop(conn);
+        let conn = read::open_existing(fname).unwrap();
+        assert!(!tmp_dir.join("old.db.v1.bak.2").exists());
+        assert_eq!(
+            write::backup_path(fname, 1),
+            tmp_dir.join("old.db.v1.bak.2")
+        );
+
+        let app_version = |conn: &sqlite::Connection| {
+            let mut stmt = conn
+                .prepare("SELECT info_value FROM app_info WHERE info_key = 'app_version'")
+                .unwrap();
+            let row = stmt.iter().next().unwrap().unwrap();
+            row.read::<&str, usize>(0).to_owned()
+        };
+        // the migration is a write
+        assert_eq!(app_version(&conn), crate::PALICA_VERSION);
+        conn.execute("UPDATE app_info SET info_value = '1' WHERE info_key = 'app_version'")
+            .unwrap();
+        {
+            let wdb = write::Db::new(&conn).unwrap();
+            // no changes, nothing is written
+            Transaction::new(&conn).commit();
+            assert_eq!(app_version(&conn), "1");
+            let tx = Transaction::new(&conn);
+            wdb.set_setting_value(UPDATE_XMP_KEY, "1").unwrap();
+            drop(tx);
+            assert_eq!(app_version(&conn), "1");
+            let mut tx = Transaction::new(&conn);
+            wdb.set_setting_value(UPDATE_XMP_KEY, "1").unwrap();
+            tx.commit();
+            assert_eq!(app_version(&conn), crate::PALICA_VERSION);
+        }
+
+        conn.execute(format!(
+            "UPDATE app_info SET info_value = '{}' WHERE info_key = 'db_version'",
+            DB_VERSION + 1
+        ))
+        .unwrap();
+        drop(conn);
+        assert!(matches!(
+            read::open_existing(fname),
+            Err(DbError::WrongDbSchema { .. })
+        ));
+    }
+
+    #[test]
+    fn media_info() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut wdb = write::Db::new(&conn).unwrap();
+        let mut entries = Vec::new();
+        for (id, name) in [(1, "nice.jpg"), (2, "fiji.jpg"), (3, "plain.jpg")] {
+            let entry = DirEntry {
+                id,
+                fs_name: name.to_owned(),
+                fs_mod_time: 1,
+                last_sync_time: 2,
+                is_dir: false,
+                fs_size: 7,
+            };
+            wdb.create_dir_entry(&entry).unwrap();
+            entries.push(entry);
+        }
+        let nice = MediaInfo {
+            width: Some(4608),
+            height: Some(3456),
+            orientation: Some(1),
+            camera_make: Some("OLYMPUS CORPORATION".to_owned()),
+            lens: Some("OLYMPUS M.12-40mm F2.8".to_owned()),
+            focal_length: Some(12.0),
+            exposure_time: Some(0.004),
+            iso: Some(200),
+            latitude: Some(43.705),
+            longitude: Some(7.26),
+            ..Default::default()
+        };
+        wdb.set_media_info(1, Some(&nice)).unwrap();
+        let fiji = MediaInfo {
+            lens: Some("LUMIX G 20/F1.7 II".to_owned()),
+            latitude: Some(-17.7),
+            longitude: Some(178.1),
+            ..Default::default()
+        };
+        wdb.set_media_info(2, Some(&fiji)).unwrap();
+        wdb.set_media_info(3, Some(&MediaInfo::default())).unwrap();
+
+        let rdb = read::Db::new(&conn).unwrap();
+        assert_eq!(rdb.media_info(1).unwrap(), Some(nice));
+        assert_eq!(rdb.media_info(3).unwrap(), None);
+        assert_eq!(rdb.entries_with_lens("OLYMPUS*").unwrap(), vec![1]);
+        assert_eq!(rdb.entries_with_lens("*").unwrap(), vec![1, 2]);
+        assert_eq!(
+            rdb.lens_counts().unwrap(),
+            vec![
+                ("LUMIX G 20/F1.7 II".to_owned(), 1),
+                ("OLYMPUS M.12-40mm F2.8".to_owned(), 1)
+            ]
+        );
+        let europe: GeoBox = "35,-10,60,30".parse().unwrap();
+        assert_eq!(rdb.entries_in_box(&europe).unwrap(), vec![1]);
+        // across the antimeridian
+        let pacific: GeoBox = "-30,170,0,-170".parse().unwrap();
+        assert_eq!(rdb.entries_in_box(&pacific).unwrap(), vec![2]);
+        assert!("60,-10,35,30".parse::<GeoBox>().is_err());
+        assert!("35,-10,60".parse::<GeoBox>().is_err());
+
+        wdb.set_media_info(2, None).unwrap();
+        assert_eq!(rdb.media_info(2).unwrap(), None);
+        wdb.delete_dir_entry(entries.remove(0)).unwrap();
+        assert!(rdb.entries_with_lens("*").unwrap().is_empty());
+    }
+
+    #[test]
+    fn settings() {
+        let conn = write::create_new(":memory:").unwrap();
+        let rdb = read::Db::new(&conn).unwrap();
+        assert_eq!(rdb.setting_value("update_xmp").unwrap().unwrap(), "1");
+        assert!(rdb.setting_value("no_such_key").unwrap().is_none());
+
+        let wdb = write::Db::new(&conn).unwrap();
+        wdb.set_setting_value(SYNC_DELETE_THRESHOLD_KEY, "10")
+            .unwrap();
+        wdb.set_setting_value("new_key", "abc").unwrap();
+        assert_eq!(
+            rdb.setting_value(SYNC_DELETE_THRESHOLD_KEY)
+                .unwrap()
+                .unwrap(),
+            "10"
+        );
+        assert_eq!(rdb.setting_value("new_key").unwrap().unwrap(), "abc");
+
+        wdb.set_setting_value("new_key2", "def").unwrap();
+        let keys: Vec<String> = rdb
+            .settings_with_prefix("new_")
+            .unwrap()
+            .into_iter()
+            .map(|s| s.key)
+            .collect();
+        assert_eq!(keys, vec!["new_key", "new_key2"]);
+        wdb.delete_setting("new_key").unwrap();
+        assert!(rdb.setting_value("new_key").unwrap().is_none());
+    }
+
+    #[test]
+    fn subtree_count() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        for id in 1..=4 {
+            db.create_dir_entry(&DirEntry {
+                id,
+                fs_name: format!("entry{id}"),
+                fs_mod_time: 1,
+                last_sync_time: 2,
+                is_dir: id < 3,
+                fs_size: 0,
+            })
+            .unwrap();
+        }
+        db.map_dir_entry_to_parent_dir(2, 1).unwrap();
+        db.map_dir_entry_to_parent_dir(3, 1).unwrap();
+        db.map_dir_entry_to_parent_dir(4, 2).unwrap();
+        let rdb = read::Db::new(&conn).unwrap();
+        assert_eq!(rdb.subtree_entry_count(1).unwrap(), 3);
+        assert_eq!(rdb.subtree_entry_count(2).unwrap(), 1);
+        assert_eq!(rdb.subtree_entry_count(4).unwrap(), 0);
+    }
+
+    #[test]
+    fn subject_tags() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut wdb = write::Db::new(&conn).unwrap();
+        wdb.create_dir_entry(&DirEntry {
+            id: 1,
+            fs_name: "myfile".to_owned(),
+            fs_mod_time: 1,
+            last_sync_time: 2,
+            is_dir: false,
+            fs_size: 7,
+        })
+        .unwrap();
+        let family = wdb.create_subject_tag("family").unwrap();
+        let city = wdb.create_subject_tag("city").unwrap();
+        assert_eq!(family.id, 1);
+        assert_eq!(city.id, 2);
+        assert!(wdb.create_subject_tag("city").is_err());
+
+        wdb.assign_tag(family.id, 1).unwrap();
+        wdb.assign_tag(family.id, 1).unwrap();
+        wdb.assign_tag(city.id, 1).unwrap();
+
+        let rdb = read::Db::new(&conn).unwrap();
+        let tags = rdb.tags_of_dir_entry(1).unwrap();
+        assert_eq!(tags, vec![city, family]);
+        assert_eq!(rdb.tag_usage_count(1).unwrap(), 1);
+
+        wdb.rename_subject_tag(1, "relatives").unwrap();
+        assert_eq!(
+            rdb.subject_tag_by_value("relatives").unwrap().unwrap().id,
+            1
+        );
+        assert!(rdb.subject_tag_by_value("family").unwrap().is_none());
+
+        wdb.unassign_tag(2, 1).unwrap();
+        assert_eq!(rdb.tags_of_dir_entry(1).unwrap().len(), 1);
+        wdb.delete_subject_tag(1).unwrap();
+        assert!(rdb.tags_of_dir_entry(1).unwrap().is_empty());
+        assert_eq!(rdb.enum_subject_tags().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn hierarchical_tags() {
+        let conn = write::create_new(":memory:").unwrap();
+        let wdb = write::Db::new(&conn).unwrap();
+        let nice = wdb.create_subject_tag("places|france|nice").unwrap();
+        let paris = wdb.create_subject_tag("places|france|paris").unwrap();
+        let city = wdb.create_subject_tag("city").unwrap();
+        assert_eq!(nice.name(), "nice");
+        assert_eq!(city.parent_id, None);
+        wdb.assign_tag(nice.id, 10).unwrap();
+        wdb.assign_tag(paris.id, 11).unwrap();
+        wdb.assign_tag(city.id, 12).unwrap();
+
+        let rdb = read::Db::new(&conn).unwrap();
+        let places = rdb.subject_tag_by_value("places").unwrap().unwrap();
+        let france = rdb.subject_tag_by_value("places|france").unwrap().unwrap();
+        assert_eq!(france.parent_id, Some(places.id));
+        assert_eq!(nice.parent_id, Some(france.id));
+        assert_eq!(rdb.tag_subtree(places.id).unwrap().len(), 4);
+        assert_eq!(
+            rdb.entries_with_tag_subtree(places.id).unwrap(),
+            vec![10, 11]
+        );
+        assert_eq!(rdb.entries_with_tag_subtree(nice.id).unwrap(), vec![10]);
+
+        wdb.rename_subject_tag(places.id, "geo").unwrap();
+        let values: Vec<String> = rdb
+            .enum_subject_tags()
+            .unwrap()
+            .into_iter()
+            .map(|t| t.tag_value)
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                "city",
+                "geo",
+                "geo|france",
+                "geo|france|nice",
+                "geo|france|paris"
+            ]
+        );
+        wdb.rename_subject_tag(city.id, "geo|city").unwrap();
+        assert_eq!(
+            rdb.subject_tag_by_value("geo|city")
+                .unwrap()
+                .unwrap()
+                .parent_id,
+            Some(places.id)
+        );
+
+        wdb.delete_subject_tag(france.id).unwrap();
+        assert_eq!(rdb.enum_subject_tags().unwrap().len(), 2);
+        assert_eq!(rdb.entries_with_tag_subtree(places.id).unwrap(), vec![12]);
+    }
+
+    #[test]
+    fn entry_by_path() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        for (id, name, is_dir) in [(1, "root", true), (2, "dcim", true), (3, "a.jpg", false)] {
+            db.create_dir_entry(&DirEntry {
+                id,
+                fs_name: name.to_owned(),
+                fs_mod_time: 1,
+                last_sync_time: 2,
+                is_dir,
+                fs_size: 0,
+            })
+            .unwrap();
+        }
+        db.map_dir_entry_to_parent_dir(2, 1).unwrap();
+        db.map_dir_entry_to_parent_dir(3, 2).unwrap();
+        let rdb = read::Db::new(&conn).unwrap();
+        assert_eq!(rdb.dir_entry_by_path(1, "").unwrap().unwrap().id, 1);
+        assert_eq!(
+            rdb.dir_entry_by_path(1, "dcim/a.jpg").unwrap().unwrap().id,
+            3
+        );
+        assert!(rdb.dir_entry_by_path(1, "dcim/b.jpg").unwrap().is_none());
+        assert!(rdb.dir_entry_by_path(1, "dcim/a.jpg/x").unwrap().is_none());
+
+        assert!(rdb.entry_location(3).unwrap().is_none());
+        db.create_collection("col", "/media", 1, DEFAULT_FILTER_ID)
+            .unwrap();
+        let (col, rel_path) = rdb.entry_location(3).unwrap().unwrap();
+        assert_eq!(col.coll_name, "col");
+        assert_eq!(rel_path, "dcim/a.jpg");
+        assert_eq!(rdb.entry_location(1).unwrap().unwrap().1, "");
+
+        // a parent cycle outside of the collection
+        for id in [4, 5] {
+            db.create_dir_entry(&DirEntry {
+                id,
+                fs_name: format!("loop{id}"),
+                fs_mod_time: 1,
+                last_sync_time: 2,
+                is_dir: true,
+                fs_size: 0,
+            })
+            .unwrap();
+        }
+        db.map_dir_entry_to_parent_dir(4, 5).unwrap();
+        db.map_dir_entry_to_parent_dir(5, 4).unwrap();
+        assert!(rdb.entry_location(4).unwrap().is_none());
+    }
+
+    #[test]
+    fn subtree() {
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        for (id, name, is_dir) in [(1, "root", true), (2, "dcim", true), (3, "a.jpg", false)] {
+            db.create_dir_entry(&DirEntry {
+                id,
+                fs_name: name.to_owned(),
+                fs_mod_time: 1,
+                last_sync_time: 2,
+                is_dir,
+                fs_size: 0,
+            })
+            .unwrap();
+        }
+        db.map_dir_entry_to_parent_dir(2, 1).unwrap();
+        db.map_dir_entry_to_parent_dir(3, 2).unwrap();
+        let mut rdb = read::Db::new(&conn).unwrap();
+        let paths: Vec<String> = rdb
+            .enum_subtree(1)
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, vec!["dcim", "dcim/a.jpg"]);
+    }
+
     #[test]
     fn delete_collection() {
         let conn = write::create_new(":memory:").unwrap();
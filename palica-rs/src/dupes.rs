@@ -0,0 +1,185 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::dblayer::{self, DuplicateFile};
+use crate::fsdbtime;
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DupesFormat {
+    /// every group with the canonical file first
+    Groups,
+    /// paths of the non-canonical files, one per line
+    Plain,
+    /// non-canonical files as vienik's RichFileList
+    Toml,
+}
+
+impl FromStr for DupesFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "groups" => Ok(DupesFormat::Groups),
+            "plain" => Ok(DupesFormat::Plain),
+            "toml" => Ok(DupesFormat::Toml),
+            _ => Err(format!(
+                "unknown dupes format '{s}', expected 'groups', 'plain' or 'toml'"
+            )),
+        }
+    }
+}
+
+/// Identical files, the first one is canonical.
+#[derive(Debug)]
+pub struct DupeGroup {
+    /// sha256 or the size
+    pub key: String,
+    pub files: Vec<DuplicateFile>,
+}
+
+impl DupeGroup {
+    pub fn canonical(&self) -> &DuplicateFile {
+        &self.files[0]
+    }
+
+    /// All files but the canonical one.
+    pub fn others(&self) -> &[DuplicateFile] {
+        &self.files[1..]
+    }
+}
+
+pub fn fs_path(file: &DuplicateFile) -> PathBuf {
+    Path::new(&file.fs_path).join(&file.rel_path)
+}
+
+/// Groups of files with the same content hash, or the same size if `by_size`.
+/// The canonical file of a group is its first file from the `prefer`
+/// collection, or the first by collection name and path.
+pub fn find_duplicates(
+    rdb: &dblayer::read::Db,
+    by_size: bool,
+    prefer: Option<&str>,
+) -> dblayer::DbResult<Vec<DupeGroup>> {
+    let mut groups: Vec<DupeGroup> = Vec::new();
+    for file in rdb.duplicate_files(by_size)? {
+        match groups.last_mut() {
+            Some(group) if group.key == file.key => group.files.push(file),
+            _ => groups.push(DupeGroup {
+                key: file.key.clone(),
+                files: vec![file],
+            }),
+        }
+    }
+    if let Some(prefer) = prefer {
+        for group in groups.iter_mut() {
+            if let Some(pos) = group.files.iter().position(|f| f.coll_name == prefer) {
+                let preferred = group.files.remove(pos);
+                group.files.insert(0, preferred);
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Non-canonical files of the groups with their db tags, the modification
+/// date in the local time zone, the capture date and the size, like
+/// `vienik plain-to-rich`.
+pub fn rich_list(
+    rdb: &dblayer::read::Db,
+    groups: &[DupeGroup],
+) -> dblayer::DbResult<vienik_rs::RichFileList> {
+    let mut list = vienik_rs::RichFileList::new();
+    for file in groups.iter().flat_map(|g| g.others()) {
+        let tags = rdb.tags_of_dir_entry(file.entry.id)?;
+        let dt_utc: chrono::DateTime<chrono::Utc> =
+            fsdbtime::systime_from_db(file.entry.fs_mod_time).into();
+        let dt_local: chrono::DateTime<chrono::Local> = dt_utc.into();
+        let taken_date = rdb.capture_time(file.entry.id)?.map(|t| {
+            let taken: chrono::DateTime<chrono::Utc> = fsdbtime::systime_from_db(t).into();
+            taken.date_naive()
+        });
+        list.files.push(vienik_rs::FileListItem {
+            path: fs_path(file).to_string_lossy().into_owned(),
+            tags: if tags.is_empty() {
+                None
+            } else {
+                Some(tags.into_iter().map(|t| t.tag_value).collect())
+            },
+            mod_date: Some(dt_local.date_naive()),
+            taken_date,
+            size: Some(file.entry.fs_size as u64),
+        });
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coll_builder::new_collection;
+    use crate::glob_filter::Filter;
+    use crate::hashing::hash_collection;
+
+    #[test]
+    fn dupes() {
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let mut cols = Vec::new();
+        for name in ["nas", "laptop"] {
+            cols.push(
+                new_collection(
+                    &mut wdb,
+                    name,
+                    Path::new("../sample-data"),
+                    1,
+                    &Filter::new(),
+                    &|_| {},
+                )
+                .unwrap(),
+            );
+        }
+        hash_collection(&conn, &cols[0], false, &|_, _| {}).unwrap();
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        assert!(find_duplicates(&rdb, false, None).unwrap().is_empty());
+        let file_count = rdb.unhashed_file_count().unwrap();
+        assert!(file_count > 3);
+        assert_eq!(find_duplicates(&rdb, true, None).unwrap().len(), file_count);
+
+        hash_collection(&conn, &cols[1], false, &|_, _| {}).unwrap();
+        assert_eq!(rdb.unhashed_file_count().unwrap(), 0);
+        let groups = find_duplicates(&rdb, false, None).unwrap();
+        assert_eq!(groups.len(), file_count);
+        for group in &groups {
+            assert_eq!(group.key.len(), 64);
+            assert_eq!(group.files.len(), 2);
+            assert_eq!(group.canonical().coll_name, "laptop");
+            assert_eq!(group.others()[0].rel_path, group.canonical().rel_path);
+        }
+
+        let groups = find_duplicates(&rdb, false, Some("nas")).unwrap();
+        assert!(groups.iter().all(|g| g.canonical().coll_name == "nas"));
+        let list = rich_list(&rdb, &groups).unwrap();
+        assert_eq!(list.files.len(), file_count);
+        assert!(list.files.iter().all(|f| f.size.is_some_and(|s| s > 0)));
+        let serialized = toml::to_string(&list).unwrap();
+        let parsed: vienik_rs::RichFileList = toml::from_str(&serialized).unwrap();
+        assert_eq!(parsed.files.len(), file_count);
+    }
+}
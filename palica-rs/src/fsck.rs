@@ -0,0 +1,401 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+//! Integrity checks of the catalogue. The schema has no foreign keys, an
+//! interrupted or buggy write leaves rows referencing deleted entries.
+use crate::dblayer::{self, DbError, DbId, DbResult, Transaction};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// (table, column, referenced table), every row must reference an
+/// existing row. Filter patterns are checked separately, see
+/// [`Problem::MissingPattern`].
+const REFERENCES: &[(&str, &str, &str)] = &[
+    ("dir_to_sub", "directory_id", "dir_entries"),
+    ("dir_to_sub", "entry_id", "dir_entries"),
+    ("tag_to_dir_entry", "subject_tag_id", "subject_tags"),
+    ("tag_to_dir_entry", "dir_entry_id", "dir_entries"),
+    ("mime_to_dir_entry", "mime_type_id", "mime_types"),
+    ("mime_to_dir_entry", "dir_entry_id", "dir_entries"),
+    ("last_edit", "dir_entry_id", "dir_entries"),
+    ("file_hashes", "dir_entry_id", "dir_entries"),
+    ("capture_times", "dir_entry_id", "dir_entries"),
+    ("media_info", "dir_entry_id", "dir_entries"),
+    ("glob_filter_to_pattern", "glob_filter_id", "glob_filters"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// `table` row `id` references a missing `referenced_id` by `column`
+    Orphan {
+        table: &'static str,
+        column: &'static str,
+        id: DbId,
+        referenced_id: DbId,
+    },
+    MissingRoot {
+        coll_name: String,
+        root_id: DbId,
+    },
+    /// see DbError::FilterToGlobIntegrityError
+    MissingPattern {
+        filter_id: DbId,
+        glob_id: DbId,
+    },
+    /// the pattern doesn't compile, e.g. PCRE lookaround, needs a manual fix
+    InvalidPattern {
+        id: DbId,
+        error: String,
+    },
+    /// the entries are subentries of each other, sorted by id
+    Cycle {
+        entry_ids: Vec<DbId>,
+    },
+    /// top of a tree of `count` entries outside of all collections
+    Unreachable {
+        entry_id: DbId,
+        fs_name: String,
+        count: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Orphan {
+                table,
+                column,
+                id,
+                referenced_id,
+            } => write!(f, "{table} {id}: missing {column} {referenced_id}"),
+            Problem::MissingRoot { coll_name, root_id } => {
+                write!(f, "collection '{coll_name}': missing root entry {root_id}")
+            }
+            Problem::MissingPattern { filter_id, glob_id } => write!(
+                f,
+                "{}",
+                DbError::FilterToGlobIntegrityError {
+                    filter_id: *filter_id,
+                    glob_id: *glob_id,
+                }
+            ),
+            Problem::InvalidPattern { id, error } => write!(f, "pattern {id}: {error}"),
+            Problem::Cycle { entry_ids } => {
+                let ids: Vec<String> = entry_ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "dir_to_sub cycle: entries {}", ids.join(", "))
+            }
+            Problem::Unreachable {
+                entry_id,
+                fs_name,
+                count,
+            } => write!(
+                f,
+                "entry {entry_id} '{fs_name}': not in any collection, {count} entries"
+            ),
+        }
+    }
+}
+
+/// Ids of the entries reachable from the existing collection roots.
+fn reachable_entries(
+    rdb: &dblayer::read::Db,
+    children: &HashMap<DbId, Vec<DbId>>,
+) -> DbResult<HashSet<DbId>> {
+    let mut reachable = HashSet::new();
+    let mut queue = Vec::new();
+    for col in rdb.enum_collections()? {
+        if rdb.dir_entry_by_id(col.root_id)?.is_some() {
+            queue.push(col.root_id);
+        }
+    }
+    while let Some(id) = queue.pop() {
+        if reachable.insert(id) {
+            queue.extend(children.get(&id).into_iter().flatten());
+        }
+    }
+    Ok(reachable)
+}
+
+/// Directory entry -> subentries.
+fn children_map(links: &[(DbId, DbId)]) -> HashMap<DbId, Vec<DbId>> {
+    let mut children: HashMap<DbId, Vec<DbId>> = HashMap::new();
+    for (entry_id, directory_id) in links {
+        children.entry(*directory_id).or_default().push(*entry_id);
+    }
+    children
+}
+
+/// Loops of the entry -> directory links, every entry has one directory
+/// at most, dir_to_sub.entry_id is unique.
+fn find_cycles(links: &[(DbId, DbId)]) -> Vec<Vec<DbId>> {
+    let parents: HashMap<DbId, DbId> = links.iter().copied().collect();
+    let mut starts: Vec<DbId> = parents.keys().copied().collect();
+    starts.sort();
+    let mut done: HashSet<DbId> = HashSet::new();
+    let mut cycles = Vec::new();
+    for start in starts {
+        let mut path: Vec<DbId> = Vec::new();
+        let mut current = Some(start);
+        while let Some(id) = current {
+            if done.contains(&id) {
+                break;
+            }
+            if let Some(pos) = path.iter().position(|p| *p == id) {
+                let mut cycle = path[pos..].to_vec();
+                cycle.sort();
+                cycles.push(cycle);
+                break;
+            }
+            path.push(id);
+            current = parents.get(&id).copied();
+        }
+        done.extend(path);
+    }
+    cycles
+}
+
+/// All problems, none of them is fixed.
+pub fn check(conn: &sqlite::Connection) -> DbResult<Vec<Problem>> {
+    let rdb = dblayer::read::Db::new(conn)?;
+    let mut problems = Vec::new();
+    for &(table, column, referenced) in REFERENCES {
+        for (id, referenced_id) in rdb.dangling_rows(table, column, referenced)? {
+            problems.push(Problem::Orphan {
+                table,
+                column,
+                id,
+                referenced_id,
+            });
+        }
+    }
+    for col in rdb.enum_collections()? {
+        if rdb.dir_entry_by_id(col.root_id)?.is_none() {
+            problems.push(Problem::MissingRoot {
+                coll_name: col.coll_name,
+                root_id: col.root_id,
+            });
+        }
+    }
+    for (filter_id, glob_id) in rdb.missing_filter_patterns()? {
+        problems.push(Problem::MissingPattern { filter_id, glob_id });
+    }
+    for pattern in rdb.enum_glob_patterns()? {
+        if let Err(e) = pattern.compile() {
+            problems.push(Problem::InvalidPattern {
+                id: pattern.id,
+                error: e.to_string(),
+            });
+        }
+    }
+
+    let links = rdb.dir_links()?;
+    let cycles = find_cycles(&links);
+    let children = children_map(&links);
+    let reachable = reachable_entries(&rdb, &children)?;
+    let in_cycle: HashSet<DbId> = cycles.iter().flatten().copied().collect();
+    let parents: HashMap<DbId, DbId> = links.iter().copied().collect();
+    let entry_ids: HashSet<DbId> = rdb.dir_entry_ids()?.into_iter().collect();
+    let mut sorted_ids: Vec<DbId> = entry_ids.iter().copied().collect();
+    sorted_ids.sort();
+    for entry_id in sorted_ids {
+        if reachable.contains(&entry_id) || in_cycle.contains(&entry_id) {
+            continue;
+        }
+        // subentries are counted for their top entry
+        if parents
+            .get(&entry_id)
+            .is_some_and(|p| entry_ids.contains(p))
+        {
+            continue;
+        }
+        let mut count = 0;
+        let mut seen = HashSet::new();
+        let mut queue = vec![entry_id];
+        while let Some(id) = queue.pop() {
+            if seen.insert(id) {
+                count += 1;
+                queue.extend(children.get(&id).into_iter().flatten());
+            }
+        }
+        let fs_name = rdb
+            .dir_entry_by_id(entry_id)?
+            .map(|e| e.fs_name)
+            .unwrap_or_default();
+        problems.push(Problem::Unreachable {
+            entry_id,
+            fs_name,
+            count,
+        });
+    }
+    for entry_ids in cycles {
+        problems.push(Problem::Cycle { entry_ids });
+    }
+    Ok(problems)
+}
+
+/// Fixes the problems in one transaction and returns all found: collections
+/// without a root are removed, collection roots are unlinked from their
+/// directories, the entries outside of collections (cycles too) are
+/// deleted, then the rows referencing missing rows. Invalid patterns are
+/// left as they are.
+pub fn repair(conn: &sqlite::Connection) -> DbResult<Vec<Problem>> {
+    let problems = check(conn)?;
+    if problems.is_empty() {
+        return Ok(problems);
+    }
+    let mut tx = Transaction::new(conn);
+    let wdb = dblayer::write::Db::new(conn)?;
+    let rdb = dblayer::read::Db::new(conn)?;
+    let missing_roots: Vec<DbId> = rdb
+        .enum_collections()?
+        .into_iter()
+        .filter(|c| {
+            problems.iter().any(|p| {
+                matches!(p, Problem::MissingRoot { coll_name, .. } if *coll_name == c.coll_name)
+            })
+        })
+        .map(|c| c.id)
+        .collect();
+    wdb.delete_rows("collections", &missing_roots)?;
+    wdb.unlink_collection_roots()?;
+
+    let links = rdb.dir_links()?;
+    let reachable = reachable_entries(&rdb, &children_map(&links))?;
+    let unreachable: Vec<DbId> = rdb
+        .dir_entry_ids()?
+        .into_iter()
+        .filter(|id| !reachable.contains(id))
+        .collect();
+    wdb.delete_rows("dir_entries", &unreachable)?;
+
+    for (table, column, referenced) in REFERENCES {
+        wdb.delete_dangling_rows(table, column, referenced)?;
+    }
+    wdb.delete_dangling_rows("glob_filter_to_pattern", "glob_pattern_id", "glob_patterns")?;
+    tx.commit();
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coll_builder::new_collection;
+    use crate::glob_filter::Filter;
+
+    use std::path::Path;
+
+    #[test]
+    fn check_and_repair() {
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let col = new_collection(
+            &mut wdb,
+            "sample",
+            Path::new("../sample-data"),
+            1,
+            &Filter::new(),
+            &|_| {},
+        )
+        .unwrap();
+        new_collection(
+            &mut wdb,
+            "broken",
+            Path::new("../sample-data"),
+            1,
+            &Filter::new(),
+            &|_| {},
+        )
+        .unwrap();
+        assert_eq!(check(&conn).unwrap(), []);
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let entry_count = rdb.dir_entry_ids().unwrap().len();
+
+        // interrupted delete_collection of 'broken'
+        let broken = rdb.collection_by_name("broken").unwrap().unwrap();
+        wdb.delete_rows("dir_entries", &[broken.root_id]).unwrap();
+        // a loop and leftovers of deleted entries
+        conn.execute(
+            "INSERT INTO dir_entries(id, fs_name, fs_mod_time, last_sync_time, is_dir, fs_size)
+                VALUES(1000, 'a', 0, 0, 1, 0), (1001, 'b', 0, 0, 1, 0);
+            INSERT INTO dir_to_sub(directory_id, entry_id) VALUES(1000, 1001), (1001, 1000);
+            INSERT INTO tag_to_dir_entry(subject_tag_id, dir_entry_id) VALUES(5, 2000);
+            INSERT INTO glob_filter_to_pattern(glob_filter_id, glob_pattern_id, include, position)
+                VALUES(1, 100, 0, 20);",
+        )
+        .unwrap();
+
+        let problems = check(&conn).unwrap();
+        assert!(problems.contains(&Problem::MissingRoot {
+            coll_name: "broken".to_owned(),
+            root_id: broken.root_id,
+        }));
+        assert!(problems.contains(&Problem::Cycle {
+            entry_ids: vec![1000, 1001]
+        }));
+        assert!(problems.contains(&Problem::MissingPattern {
+            filter_id: 1,
+            glob_id: 100
+        }));
+        assert_eq!(
+            problems
+                .iter()
+                .filter(|p| matches!(
+                    p,
+                    Problem::Orphan {
+                        table: "tag_to_dir_entry",
+                        ..
+                    }
+                ))
+                .count(),
+            2
+        );
+        // the subentries of the deleted root
+        let unreachable: usize = problems
+            .iter()
+            .map(|p| match p {
+                Problem::Unreachable { count, .. } => *count,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(unreachable, entry_count / 2 - 1);
+        assert_eq!(
+            problems
+                .iter()
+                .find(|p| matches!(p, Problem::MissingPattern { .. }))
+                .unwrap()
+                .to_string(),
+            "filter to glob integrity error, filter: 1, glob: 100"
+        );
+
+        assert_eq!(repair(&conn).unwrap(), problems);
+        assert_eq!(check(&conn).unwrap(), []);
+        assert!(rdb.collection_by_name("broken").unwrap().is_none());
+        assert_eq!(rdb.dir_entry_ids().unwrap().len(), entry_count / 2);
+        assert!(rdb.dir_entry_by_id(col.root_id).unwrap().is_some());
+        rdb.glob_filter_by_id(1).unwrap();
+
+        conn.execute("INSERT INTO glob_patterns(id, regexp) VALUES(100, '(?<=x)y')")
+            .unwrap();
+        let problems = repair(&conn).unwrap();
+        assert!(matches!(
+            problems[..],
+            [Problem::InvalidPattern { id: 100, .. }]
+        ));
+        assert_eq!(check(&conn).unwrap(), problems);
+    }
+}
@@ -29,6 +29,28 @@ pub fn dbtime_from_sys(systime: SystemTime) -> i64 {
         .expect("failed to get nanos")
 }
 
+/// Days since 1970-01-01 for the proleptic Gregorian date.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Midnight UTC of the date, None outside of the nanoseconds range, about
+/// years 1678 to 2262.
+pub fn dbtime_from_date(year: i64, month: u32, day: u32) -> Option<i64> {
+    // days_from_civil itself overflows only for much larger years
+    if year.unsigned_abs() > 1_000_000 {
+        return None;
+    }
+    days_from_civil(year, month, day).checked_mul(86_400 * 1_000_000_000)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::fsdbtime::*;
@@ -48,4 +70,21 @@ mod tests {
             u128::from(day * 31 + sec)
         );
     }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(
+            systime_from_db(dbtime_from_date(2023, 1, 2).unwrap())
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            1_672_617_600
+        );
+        assert_eq!(dbtime_from_date(2263, 1, 1), None);
+        assert_eq!(dbtime_from_date(99_999_999_999, 1, 1), None);
+        assert_eq!(dbtime_from_date(i64::MIN, 1, 1), None);
+    }
 }
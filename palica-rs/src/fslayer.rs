@@ -51,8 +51,8 @@ pub mod read {
 
     // TODO use thiserror and meaningful errors
     type FsResult<T> = anyhow::Result<T>;
-    /// return true to include the path
-    type FilterFn<'a> = dyn FnMut(&Path) -> bool + 'a;
+    /// return true to include the path, the flag is true for directories
+    type FilterFn<'a> = dyn FnMut(&Path, bool) -> bool + 'a;
 
     /// not recursive
     /*
@@ -90,7 +90,10 @@ pub mod read {
                     None
                 }
             })
-            .filter(|item| filter_fn(&item.path()))
+            .filter(|item| {
+                let path = item.path();
+                filter_fn(&path, path.is_dir())
+            })
             .map(|item| {
                 let r = dir_entry(&item.path());
                 if r.is_err() {
@@ -160,7 +163,7 @@ mod tests {
 
     #[test]
     fn dir_entries() {
-        let mut filter_fn = |_: &Path| true;
+        let mut filter_fn = |_: &Path, _| true;
         let items =
             read::dir_entries(std::path::Path::new("../sample-data"), &mut filter_fn).unwrap();
         let mut count = 0;
@@ -15,21 +15,231 @@
     You should have received a copy of the GNU General Public License
     along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// How the text of a pattern is matched against a full file path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PatternKind {
+    /// PCRE regular expression, e.g. '(?i)\.jpe?g$'
+    Regexp,
+    /// shell glob, e.g. '**/*.{jpg,JPG}', see glob_to_regexp
+    Glob,
+    /// literal end of the path, e.g. '.jpg'
+    Suffix,
+}
+
+impl PatternKind {
+    /// Value of the glob_patterns.kind column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PatternKind::Regexp => "regexp",
+            PatternKind::Glob => "glob",
+            PatternKind::Suffix => "suffix",
+        }
+    }
+}
+
+impl FromStr for PatternKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regexp" => Ok(PatternKind::Regexp),
+            "glob" => Ok(PatternKind::Glob),
+            "suffix" => Ok(PatternKind::Suffix),
+            _ => Err(format!(
+                "unknown pattern kind '{s}', expected 'regexp', 'glob' or 'suffix'"
+            )),
+        }
+    }
+}
+
+/// Rewrites the PCRE syntax the regex crate lacks: `\Q...\E` literals,
+/// `\Z` and `\h`. Inline flags like `(?i)` are the same in both. Lookaround
+/// and backreferences have no equivalent, Pattern::parse fails on them.
+pub fn pcre_compat(text: &str) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text);
+    }
+    let mut res = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Q') => {
+                let rest = chars.as_str();
+                let (literal, after) = match rest.find("\\E") {
+                    Some(end) => (&rest[..end], &rest[end + 2..]),
+                    None => (rest, ""),
+                };
+                res.push_str(&regex::escape(literal));
+                chars = after.chars();
+            }
+            Some('Z') => res.push_str("\\z"),
+            Some('h') => res.push_str("[ \\t]"),
+            // PCRE ignores a stray \E
+            Some('E') => {}
+            Some(other) => {
+                res.push('\\');
+                res.push(other);
+            }
+            None => res.push('\\'),
+        }
+    }
+    Cow::Owned(res)
+}
+
+/// Converts a glob to a regexp matching the end of a path at a '/'
+/// boundary, or the whole path if the glob starts with '/'. So '*.jpg'
+/// matches a file name in any directory, '.git/**' everything in a '.git'
+/// directory. `*` and `?` don't match '/', `**` does, `**/` matches zero
+/// or more directories. `[abc]`, `[!abc]` and `{jpg,png}` are supported.
+pub fn glob_to_regexp(glob: &str) -> Result<String, String> {
+    let mut res = String::from(if glob.starts_with('/') {
+        "^"
+    } else {
+        "(?:^|/)"
+    });
+    let chars: Vec<char> = glob.chars().collect();
+    let mut in_braces = false;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    res.push_str("(?:.*/)?");
+                    i += 1;
+                } else {
+                    res.push_str(".*");
+                }
+                i += 1;
+            }
+            '*' => res.push_str("[^/]*"),
+            '?' => res.push_str("[^/]"),
+            '[' => {
+                res.push('[');
+                let mut start = i + 1;
+                if chars.get(start) == Some(&'!') {
+                    res.push('^');
+                    start += 1;
+                }
+                // ']' right after '[' or '[!' is literal, as in shells
+                let end = chars
+                    .get(start + 1..)
+                    .and_then(|rest| rest.iter().position(|c| *c == ']'))
+                    .map(|pos| start + 1 + pos)
+                    .ok_or_else(|| format!("unclosed '[' in glob '{glob}'"))?;
+                let class = &chars[start..end];
+                // '&&', '--' and '~~' are set operations in the regex crate
+                let mut dash = false;
+                let mut range_end = false;
+                for (j, c) in class.iter().enumerate() {
+                    let range_dash = *c == '-' && j > 0 && j + 1 < class.len() && !range_end;
+                    if !range_dash && matches!(c, '\\' | '[' | ']' | '^' | '&' | '~' | '-') {
+                        res.push('\\');
+                    }
+                    res.push(*c);
+                    range_end = dash;
+                    dash = range_dash;
+                }
+                res.push(']');
+                i = end;
+            }
+            '{' if !in_braces => {
+                in_braces = true;
+                res.push_str("(?:");
+            }
+            ',' if in_braces => res.push('|'),
+            '}' if in_braces => {
+                in_braces = false;
+                res.push(')');
+            }
+            c => res.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    if in_braces {
+        return Err(format!("unclosed '{{' in glob '{glob}'"));
+    }
+    res.push('$');
+    Ok(res)
+}
+
+/// Compiled once, filters are shared by threads.
 #[derive(Debug)]
 pub struct Pattern {
-    compiled: pcre::Pcre,
+    compiled: regex::Regex,
 }
 
 impl Pattern {
+    /// Regexp of a test, panics if it doesn't compile.
+    #[cfg(test)]
     pub fn new(text: &str) -> Pattern {
-        Pattern {
-            compiled: pcre::Pcre::compile(text)
-                .expect(&format!("Failed to compile regexp: {}", text)),
+        Pattern::parse(PatternKind::Regexp, text)
+            .unwrap_or_else(|e| panic!("Failed to compile regexp {text}: {e}"))
+    }
+
+    /// Fails with the glob or regexp compilation error message.
+    pub fn parse(kind: PatternKind, text: &str) -> Result<Pattern, String> {
+        let regexp = match kind {
+            PatternKind::Regexp => pcre_compat(text).into_owned(),
+            PatternKind::Glob => glob_to_regexp(text)?,
+            PatternKind::Suffix => format!("{}$", regex::escape(text)),
+        };
+        regex::Regex::new(&regexp)
+            .map(|compiled| Pattern { compiled })
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn accept(&self, text: &str) -> bool {
+        self.compiled.is_match(text)
+    }
+}
+
+/// Kind of directory entries a filter item applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleTarget {
+    Both,
+    Files,
+    /// excluded directories are not descended into
+    Dirs,
+}
+
+impl RuleTarget {
+    /// Value of the glob_filter_to_pattern.target column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleTarget::Both => "both",
+            RuleTarget::Files => "files",
+            RuleTarget::Dirs => "dirs",
         }
     }
 
-    pub fn accept(&mut self, text: &str) -> bool {
-        self.compiled.exec(text).is_some()
+    pub fn applies_to(&self, is_dir: bool) -> bool {
+        match self {
+            RuleTarget::Both => true,
+            RuleTarget::Files => !is_dir,
+            RuleTarget::Dirs => is_dir,
+        }
+    }
+}
+
+impl FromStr for RuleTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(RuleTarget::Both),
+            "files" => Ok(RuleTarget::Files),
+            "dirs" => Ok(RuleTarget::Dirs),
+            _ => Err(format!(
+                "unknown rule target '{s}', expected 'both', 'files' or 'dirs'"
+            )),
+        }
     }
 }
 
@@ -37,10 +247,16 @@ impl Pattern {
 pub struct FilterItem {
     pub pattern_index: usize,
     pub include: bool,
+    pub target: RuleTarget,
 }
 
 impl FilterItem {
-    pub fn include(&self, text: &str, patterns: &mut [Pattern]) -> Option<bool> {
+    /// None if the pattern doesn't match or the item doesn't target
+    /// this kind of entries.
+    pub fn include(&self, text: &str, is_dir: bool, patterns: &[Pattern]) -> Option<bool> {
+        if !self.target.applies_to(is_dir) {
+            return None;
+        }
         let accepted = patterns[self.pattern_index].accept(text);
         if accepted {
             Some(accepted == self.include)
@@ -50,6 +266,14 @@ impl FilterItem {
     }
 }
 
+/// Item of a filter whose pattern matched, see Filter::explain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RuleMatch {
+    /// index in Filter::items, which are sorted by position
+    pub item_index: usize,
+    pub include: bool,
+}
+
 #[derive(Debug)]
 pub struct Filter {
     pub patterns: Vec<Pattern>,
@@ -60,21 +284,40 @@ impl Filter {
     /// Creates default filter that accepts every non-empty path.
     pub fn new() -> Filter {
         let mut patterns = Vec::new();
-        patterns.push(Pattern::new(".+"));
+        patterns.push(Pattern {
+            compiled: regex::Regex::new(".+").unwrap(),
+        });
 
         let mut items = Vec::new();
         items.push(FilterItem {
             pattern_index: 0,
             include: true,
+            target: RuleTarget::Both,
         });
         Filter { patterns, items }
     }
-    /// true if the text must be included (allowed by the filter)
-    pub fn include(&mut self, text: &str) -> bool {
+    /// true if the text must be included (allowed by the filter),
+    /// `is_dir` selects the items targeting directories or files
+    pub fn include(&self, text: &str, is_dir: bool) -> bool {
         self.items.iter().fold(false, |prev, i| {
-            i.include(text, &mut self.patterns).unwrap_or(prev)
+            i.include(text, is_dir, &self.patterns).unwrap_or(prev)
         })
     }
+
+    /// Items matching the text in the order of application, the last one
+    /// decides like in `include`, the text is excluded if none matched.
+    pub fn explain(&self, text: &str, is_dir: bool) -> Vec<RuleMatch> {
+        let mut matches = Vec::new();
+        for (item_index, item) in self.items.iter().enumerate() {
+            if let Some(include) = item.include(text, is_dir, &self.patterns) {
+                matches.push(RuleMatch {
+                    item_index,
+                    include,
+                });
+            }
+        }
+        matches
+    }
 }
 
 #[cfg(test)]
@@ -82,29 +325,31 @@ mod tests {
     use super::*;
     #[test]
     fn filter1() {
-        let mut f = Filter {
+        let f = Filter {
             patterns: vec![Pattern::new(r"^.+$"), Pattern::new(r"/\.thumbnails$")],
             items: vec![
                 FilterItem {
                     pattern_index: 0,
                     include: true,
+                    target: RuleTarget::Both,
                 },
                 FilterItem {
                     pattern_index: 1,
                     include: false,
+                    target: RuleTarget::Both,
                 },
             ],
         };
 
-        assert_eq!(f.include("abc"), true);
-        assert_eq!(f.include("/abc/def/.thumbnails/jkk"), true);
-        assert_eq!(f.include("/abc/def/.thumbnails"), false);
-        assert_eq!(f.include(".thumbnails/jkk"), true);
+        assert_eq!(f.include("abc", false), true);
+        assert_eq!(f.include("/abc/def/.thumbnails/jkk", false), true);
+        assert_eq!(f.include("/abc/def/.thumbnails", false), false);
+        assert_eq!(f.include(".thumbnails/jkk", false), true);
     }
 
     #[test]
     fn filter2() {
-        let mut f = Filter {
+        let f = Filter {
             patterns: vec![
                 Pattern::new(r"^.+$"),
                 Pattern::new(r"/\.thumbnails$"),
@@ -114,21 +359,183 @@ mod tests {
                 FilterItem {
                     pattern_index: 0,
                     include: false,
+                    target: RuleTarget::Both,
                 },
                 FilterItem {
                     pattern_index: 2,
                     include: true,
+                    target: RuleTarget::Both,
                 },
                 FilterItem {
                     pattern_index: 1,
                     include: true,
+                    target: RuleTarget::Both,
                 },
             ],
         };
 
-        assert_eq!(f.include("abc"), false);
-        assert_eq!(f.include("/abc/def/.thumbnails/jkk"), false);
-        assert_eq!(f.include("/abc/def/.thumbnails"), true);
-        assert_eq!(f.include("/abc/def/.png"), true);
+        assert_eq!(f.include("abc", false), false);
+        assert_eq!(f.include("/abc/def/.thumbnails/jkk", false), false);
+        assert_eq!(f.include("/abc/def/.thumbnails", false), true);
+        assert_eq!(f.include("/abc/def/.png", false), true);
+    }
+
+    #[test]
+    fn targets() {
+        let f = Filter {
+            patterns: vec![
+                Pattern::new(r"^.+$"),
+                Pattern::parse(PatternKind::Glob, ".git").unwrap(),
+                Pattern::parse(PatternKind::Glob, "*.db").unwrap(),
+            ],
+            items: vec![
+                FilterItem {
+                    pattern_index: 0,
+                    include: true,
+                    target: RuleTarget::Both,
+                },
+                FilterItem {
+                    pattern_index: 1,
+                    include: false,
+                    target: RuleTarget::Dirs,
+                },
+                FilterItem {
+                    pattern_index: 2,
+                    include: false,
+                    target: RuleTarget::Files,
+                },
+            ],
+        };
+
+        assert!(!f.include("/a/.git", true));
+        assert!(f.include("/a/.git", false));
+        assert!(!f.include("/a/Thumbs.db", false));
+        assert!(f.include("/a/Thumbs.db", true));
+        assert!(f.include("/a/b.jpg", false));
+        assert_eq!(f.explain("/a/.git", false).len(), 1);
+        assert_eq!("dirs".parse(), Ok(RuleTarget::Dirs));
+        assert!("dir".parse::<RuleTarget>().is_err());
+    }
+
+    #[test]
+    fn globs() {
+        let accept =
+            |kind, text: &str, path: &str| Pattern::parse(kind, text).unwrap().accept(path);
+        assert!(accept(PatternKind::Glob, "**/*.jpg", "/media/a/b.jpg"));
+        assert!(accept(PatternKind::Glob, "*.jpg", "/media/a/b.jpg"));
+        assert!(!accept(PatternKind::Glob, "*.jpg", "/media/a/b.jpg.xmp"));
+        assert!(!accept(PatternKind::Glob, "*.jpg", "/media/a/b.JPG"));
+        assert!(accept(PatternKind::Glob, "*.{jpg,JPG}", "/media/a/b.JPG"));
+        assert!(accept(
+            PatternKind::Glob,
+            ".git/**",
+            "/media/.git/objects/x"
+        ));
+        assert!(!accept(PatternKind::Glob, ".git/**", "/media/a.git/x"));
+        assert!(accept(
+            PatternKind::Glob,
+            "/media/**/raw/*",
+            "/media/raw/a.orf"
+        ));
+        assert!(accept(
+            PatternKind::Glob,
+            "/media/**/raw/*",
+            "/media/2023/05/raw/a.orf"
+        ));
+        assert!(!accept(
+            PatternKind::Glob,
+            "/media/**/raw/*",
+            "/mnt/media/raw/a.orf"
+        ));
+        assert!(accept(
+            PatternKind::Glob,
+            "IMG_????.[!x]*",
+            "/a/IMG_0001.jpg"
+        ));
+        assert!(!accept(
+            PatternKind::Glob,
+            "IMG_????.[!x]*",
+            "/a/IMG_0001.xmp"
+        ));
+        assert!(accept(PatternKind::Glob, "a+b (1).png", "/a/a+b (1).png"));
+        assert!(accept(PatternKind::Suffix, ".tar.gz", "/a/b.tar.gz"));
+        assert!(!accept(PatternKind::Suffix, ".tar.gz", "/a/b.tar_gz"));
+        assert!(accept(PatternKind::Regexp, r"(?i)\.jpe?g$", "/a/b.JPEG"));
+
+        assert!(accept(PatternKind::Glob, "[a&&b]", "/a"));
+        assert!(accept(PatternKind::Glob, "[a&&b]", "/&"));
+        assert!(!accept(PatternKind::Glob, "[a&&b]", "/c"));
+        assert!(accept(PatternKind::Glob, "[x~~y]", "/~"));
+        assert!(accept(PatternKind::Glob, "[a-c]", "/b"));
+        assert!(!accept(PatternKind::Glob, "[a-c]", "/-"));
+        assert!(accept(PatternKind::Glob, "[a-]", "/-"));
+        assert!(accept(PatternKind::Glob, "[-a]", "/-"));
+        assert!(accept(PatternKind::Glob, "[a-c-e]", "/-"));
+        assert!(!accept(PatternKind::Glob, "[a-c-e]", "/d"));
+        assert!(accept(PatternKind::Glob, "[]a]", "/]"));
+        assert!(accept(PatternKind::Glob, "[!]]", "/a"));
+        assert!(!accept(PatternKind::Glob, "[!]]", "/]"));
+        assert!(accept(PatternKind::Glob, "[^]", "/^"));
+        assert!(Pattern::parse(PatternKind::Glob, "[!]").is_err());
+        assert!(Pattern::parse(PatternKind::Glob, "[]").is_err());
+        assert!(Pattern::parse(PatternKind::Glob, "*.[jpg").is_err());
+        assert!(Pattern::parse(PatternKind::Glob, "*.{jpg,png").is_err());
+        assert!(Pattern::parse(PatternKind::Regexp, "(").is_err());
+        assert_eq!("glob".parse(), Ok(PatternKind::Glob));
+        assert!("shell".parse::<PatternKind>().is_err());
+    }
+
+    #[test]
+    fn pcre_syntax() {
+        assert_eq!(pcre_compat(r"(?i)\.jpe?g$"), r"(?i)\.jpe?g$");
+        assert_eq!(pcre_compat(r"/\Q[a].b\E/\Z"), r"/\[a\]\.b/\z");
+        assert_eq!(pcre_compat(r"\Qa.b"), r"a\.b");
+        assert_eq!(pcre_compat(r"x\hy"), r"x[ \t]y");
+        assert!(Pattern::new(r"(?i)/Thumbs.db$").accept("/a/THUMBS.DB"));
+        assert!(Pattern::new(r"\Q(1)\E\Z").accept("/a/b (1)"));
+        assert!(Pattern::parse(PatternKind::Regexp, r"(?<=x)y").is_err());
+
+        fn shared<T: Send + Sync>(_: &T) {}
+        shared(&Filter::new());
+    }
+
+    #[test]
+    fn explain() {
+        let f = Filter {
+            patterns: vec![Pattern::new(r"^.+$"), Pattern::new(r"/\.thumbnails")],
+            items: vec![
+                FilterItem {
+                    pattern_index: 0,
+                    include: true,
+                    target: RuleTarget::Both,
+                },
+                FilterItem {
+                    pattern_index: 1,
+                    include: false,
+                    target: RuleTarget::Both,
+                },
+            ],
+        };
+
+        let matches = f.explain("/abc/.thumbnails/a.png", false);
+        assert_eq!(
+            matches,
+            [
+                RuleMatch {
+                    item_index: 0,
+                    include: true
+                },
+                RuleMatch {
+                    item_index: 1,
+                    include: false
+                }
+            ]
+        );
+        assert_eq!(f.explain("/abc/a.png", false).len(), 1);
+        assert!(f.explain("", false).is_empty());
+        for text in ["/abc/.thumbnails/a.png", "/abc/a.png", ""] {
+            let decision = f.explain(text, false).last().is_some_and(|m| m.include);
+            assert_eq!(decision, f.include(text, false));
+        }
     }
 }
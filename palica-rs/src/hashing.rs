@@ -0,0 +1,173 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::dblayer::{self, Collection, FileHash};
+use crate::fslayer;
+use crate::sync::{compare_db_to_fsitem, DbFsCompareResult};
+
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Commit every this many hashed files, so that an interrupted run keeps
+/// most of its work.
+const HASH_BATCH_SIZE: usize = 256;
+
+/// sha256 of the file contents, hex
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct HashStats {
+    pub hashed: usize,
+    /// unchanged since the last hash
+    pub skipped: usize,
+    /// unreadable or changed since the last sync
+    pub failed: usize,
+}
+
+/// Called with the filesystem path and the new hash.
+pub type OnFileHash<'a> = dyn Fn(&Path, &str) + 'a;
+
+/// Stores sha256 of the collection files. Files with the same modification
+/// time and size as at the last hashing are skipped unless `force`.
+pub fn hash_collection(
+    conn: &sqlite::Connection,
+    col: &Collection,
+    force: bool,
+    on_hash: &OnFileHash,
+) -> anyhow::Result<HashStats> {
+    let col_root = Path::new(&col.fs_path);
+    if !col_root.is_dir() {
+        return Err(anyhow::Error::msg(format!(
+            "Collection '{}' is not available at '{}'!",
+            col.coll_name, col.fs_path
+        )));
+    }
+    let mut rdb = dblayer::read::Db::new(conn)?;
+    let wdb = dblayer::write::Db::new(conn)?;
+    let mut stats = HashStats::default();
+    let mut tx = dblayer::Transaction::new(conn);
+    let mut batch = 0;
+
+    for (rel_path, entry) in rdb.enum_subtree(col.root_id)? {
+        if entry.is_dir {
+            continue;
+        }
+        if !force {
+            if let Some(hash) = rdb.file_hash(entry.id)? {
+                if hash.is_valid_for(&entry) {
+                    stats.skipped += 1;
+                    continue;
+                }
+            }
+        }
+
+        let fs_path = col_root.join(&rel_path);
+        // the hash must describe the db entry
+        let unchanged = match fslayer::read::dir_entry(&fs_path) {
+            Ok(fs_entry) => compare_db_to_fsitem(&entry, &fs_entry) == DbFsCompareResult::Same,
+            Err(_) => false,
+        };
+        if !unchanged {
+            eprintln!(
+                "Skipped '{}': changed since the last sync.",
+                fs_path.to_string_lossy()
+            );
+            stats.failed += 1;
+            continue;
+        }
+        let hash_value = match sha256_file(&fs_path) {
+            Ok(hash_value) => hash_value,
+            Err(e) => {
+                eprintln!("Skipped '{}': {e}", fs_path.to_string_lossy());
+                stats.failed += 1;
+                continue;
+            }
+        };
+        wdb.set_file_hash(&FileHash {
+            dir_entry_id: entry.id,
+            hash_value: hash_value.clone(),
+            fs_mod_time: entry.fs_mod_time,
+            fs_size: entry.fs_size,
+        })?;
+        on_hash(&fs_path, &hash_value);
+        stats.hashed += 1;
+
+        batch += 1;
+        if batch == HASH_BATCH_SIZE {
+            tx.commit();
+            tx = dblayer::Transaction::new(conn);
+            batch = 0;
+        }
+    }
+
+    tx.commit();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coll_builder::new_collection;
+    use crate::glob_filter::Filter;
+    use crate::test_util::TmpDir;
+
+    #[test]
+    fn sha256() {
+        assert_eq!(
+            sha256_file(Path::new("../sample-data/img1.jpg"))
+                .unwrap()
+                .len(),
+            64
+        );
+        let tmp = TmpDir::new("hash");
+        let tmp_file = tmp.path().join("abc.txt");
+        std::fs::write(&tmp_file, "abc").unwrap();
+        let hash_value = sha256_file(&tmp_file).unwrap();
+        assert_eq!(
+            hash_value,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn hash_col() {
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let col = new_collection(
+            &mut wdb,
+            "sample",
+            Path::new("../sample-data"),
+            1,
+            &Filter::new(),
+            &|_| {},
+        )
+        .unwrap();
+        let first = hash_collection(&conn, &col, false, &|_, _| {}).unwrap();
+        assert!(first.hashed > 3);
+        assert_eq!(first.failed, 0);
+        let second = hash_collection(&conn, &col, false, &|_, _| {}).unwrap();
+        assert_eq!(second.hashed, 0);
+        assert_eq!(second.skipped, first.hashed);
+        let forced = hash_collection(&conn, &col, true, &|_, _| {}).unwrap();
+        assert_eq!(forced.hashed, first.hashed);
+    }
+}
@@ -18,9 +18,22 @@
 pub mod cli;
 pub mod coll_builder;
 pub mod dblayer;
+pub mod dupes;
+pub mod fsck;
 pub mod fsdbtime;
 pub mod fslayer;
 pub mod glob_filter;
+pub mod hashing;
+pub mod mime;
+pub mod query;
 pub mod sync;
+pub mod sync_moves;
+pub mod sync_report;
+pub mod tags;
+#[cfg(test)]
+mod test_util;
+pub mod thumbnails;
+pub mod vpath;
+pub mod xmp;
 
 pub const PALICA_VERSION: &str = env!("CARGO_PKG_VERSION");
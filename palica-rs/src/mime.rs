@@ -0,0 +1,222 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+//! MIME type detection by the magic bytes, falling back to the extension.
+use std::io::Read;
+use std::path::Path;
+
+/// Enough for all the signatures below.
+const MAGIC_LEN: usize = 32;
+
+const APPLICATION_OCTET_STREAM: &str = "application/octet-stream";
+
+/// lowercase extension -> mime
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("jxl", "image/jxl"),
+    ("avif", "image/avif"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("bmp", "image/bmp"),
+    ("svg", "image/svg+xml"),
+    ("xcf", "image/x-xcf"),
+    ("cr2", "image/x-canon-cr2"),
+    ("cr3", "image/x-canon-cr3"),
+    ("nef", "image/x-nikon-nef"),
+    ("arw", "image/x-sony-arw"),
+    ("orf", "image/x-olympus-orf"),
+    ("rw2", "image/x-panasonic-rw2"),
+    ("dng", "image/x-adobe-dng"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("mov", "video/quicktime"),
+    ("mkv", "video/x-matroska"),
+    ("webm", "video/webm"),
+    ("avi", "video/x-msvideo"),
+    ("mts", "video/mp2t"),
+    ("m2ts", "video/mp2t"),
+    ("3gp", "video/3gpp"),
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("flac", "audio/flac"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("xmp", "application/rdf+xml"),
+    ("xml", "application/xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("xz", "application/x-xz"),
+    ("txt", "text/plain"),
+];
+
+/// Containers whose magic bytes are shared by more specific formats,
+/// e.g. most raw photo formats are tiff files.
+const REFINED_BY_EXTENSION: &[&str] = &["image/tiff", "video/x-matroska", "video/mp4"];
+
+fn ftyp_mime(brand: &[u8]) -> &'static str {
+    match brand {
+        b"heic" | b"heix" | b"heim" | b"heis" => "image/heic",
+        b"mif1" | b"msf1" => "image/heif",
+        b"avif" | b"avis" => "image/avif",
+        b"crx " => "image/x-canon-cr3",
+        b"qt  " => "video/quicktime",
+        b"M4A " => "audio/mp4",
+        b"3gp4" | b"3gp5" | b"3gp6" => "video/3gpp",
+        _ => "video/mp4",
+    }
+}
+
+/// Mime type by the first bytes of a file.
+pub fn from_magic(data: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| data.starts_with(magic);
+    let riff = |kind: &[u8]| starts(b"RIFF") && data.get(8..12) == Some(kind);
+    if starts(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if starts(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some("image/png")
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        Some("image/gif")
+    } else if riff(b"WEBP") {
+        Some("image/webp")
+    } else if riff(b"AVI ") {
+        Some("video/x-msvideo")
+    } else if riff(b"WAVE") {
+        Some("audio/wav")
+    } else if starts(&[0xFF, 0x0A])
+        || starts(&[
+            0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A,
+        ])
+    {
+        Some("image/jxl")
+    } else if starts(b"II*\0") || starts(b"MM\0*") {
+        Some("image/tiff")
+    } else if starts(b"BM") && data.len() >= 14 {
+        Some("image/bmp")
+    } else if starts(b"gimp xcf") {
+        Some("image/x-xcf")
+    } else if data.get(4..8) == Some(b"ftyp") {
+        data.get(8..12).map(ftyp_mime)
+    } else if starts(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        Some("video/x-matroska")
+    } else if starts(b"ID3") || starts(&[0xFF, 0xFB]) || starts(&[0xFF, 0xF3]) {
+        Some("audio/mpeg")
+    } else if starts(b"fLaC") {
+        Some("audio/flac")
+    } else if starts(b"OggS") {
+        Some("audio/ogg")
+    } else if starts(b"%PDF-") {
+        Some("application/pdf")
+    } else if starts(b"PK\x03\x04") {
+        Some("application/zip")
+    } else if starts(&[0x1F, 0x8B]) {
+        Some("application/gzip")
+    } else if starts(&[0xFD, b'7', b'z', b'X', b'Z', 0]) {
+        Some("application/x-xz")
+    } else {
+        None
+    }
+}
+
+/// Mime type by the file name extension, case insensitive.
+pub fn from_extension(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map(|(_, mime)| *mime)
+}
+
+/// Mime type by the magic bytes, or by the extension if they are unknown
+/// or only tell the container format.
+pub fn detect_from(path: &Path, data: &[u8]) -> &'static str {
+    match (from_magic(data), from_extension(path)) {
+        (Some(magic), Some(ext)) if REFINED_BY_EXTENSION.contains(&magic) => {
+            // keep the magic if the extension is of a different kind
+            if ext.split('/').next() == magic.split('/').next() {
+                ext
+            } else {
+                magic
+            }
+        }
+        (Some(magic), _) => magic,
+        (None, Some(ext)) => ext,
+        (None, None) => APPLICATION_OCTET_STREAM,
+    }
+}
+
+/// Reads the first bytes of the file.
+pub fn detect(path: &Path) -> std::io::Result<&'static str> {
+    let mut data = Vec::with_capacity(MAGIC_LEN);
+    std::fs::File::open(path)?
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut data)?;
+    Ok(detect_from(path, &data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_mime() {
+        let p = Path::new;
+        assert_eq!(detect(p("../sample-data/img1.jpg")).unwrap(), "image/jpeg");
+        assert_eq!(detect(p("../sample-data/img1.webp")).unwrap(), "image/webp");
+        assert_eq!(detect(p("../sample-data/img1.jxl")).unwrap(), "image/jxl");
+        assert_eq!(
+            detect(p("../sample-data/lossless/img1.png")).unwrap(),
+            "image/png"
+        );
+        assert_eq!(
+            detect(p("../sample-data/img1.xcf.xz")).unwrap(),
+            "application/x-xz"
+        );
+
+        // magic wins over a wrong extension
+        let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0];
+        assert_eq!(detect_from(p("photo.JPG"), &png), "image/png");
+        // raw formats are tiff
+        assert_eq!(
+            detect_from(p("a.NEF"), b"MM\0*\0\0\0\x08"),
+            "image/x-nikon-nef"
+        );
+        assert_eq!(detect_from(p("a.tif"), b"II*\0\x08\0\0\0"), "image/tiff");
+        assert_eq!(detect_from(p("a.txt"), b"II*\0\x08\0\0\0"), "image/tiff");
+        let mov = b"\0\0\0\x14ftypqt  \0\0\0\0";
+        assert_eq!(detect_from(p("clip.mp4"), mov), "video/quicktime");
+        let mp4 = b"\0\0\0\x18ftypisom\0\0\0\0";
+        assert_eq!(detect_from(p("clip.m4v"), mp4), "video/mp4");
+        let heic = b"\0\0\0\x18ftypheic\0\0\0\0";
+        assert_eq!(detect_from(p("IMG_1.HEIC"), heic), "image/heic");
+        let mkv = [0x1A, 0x45, 0xDF, 0xA3, 0x01];
+        assert_eq!(detect_from(p("a.webm"), &mkv), "video/webm");
+        // by the extension
+        assert_eq!(
+            detect_from(p("a.xmp"), b"<x:xmpmeta"),
+            "application/rdf+xml"
+        );
+        assert_eq!(detect_from(p("noext"), b"?"), APPLICATION_OCTET_STREAM);
+    }
+}
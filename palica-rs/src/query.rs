@@ -0,0 +1,705 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+//! File selection expressions, e.g.
+//! `tag:places|france and not tag:family and date:2023-05..2023-07 and size>1M`
+//!
+//! Terms:
+//! * `tag:VALUE` tagged with the tag or its subtags
+//! * `tagged`, `untagged` has any tags, has no tags
+//! * `col:GLOB` collection name
+//! * `path:GLOB` or `path:COLLECTION:/GLOB` path relative to the collection root
+//! * `name:GLOB` file name
+//! * `mime:GLOB` mime type, e.g. `image/*`
+//! * `lens:GLOB` lens model, e.g. `lens:"*12-40mm*"`
+//! * `camera:GLOB` camera make or model
+//! * `gps:SOUTH,WEST,NORTH,EAST` GPS position inside the box, degrees
+//! * `date` (modification time, UTC) with `YYYY`, `YYYY-MM` or `YYYY-MM-DD`
+//! * `taken` capture time from EXIF or the movie header, falls back to
+//!   the modification time, the same values as `date`
+//! * `size` in bytes with optional k, M, G suffixes
+//!
+//! `date`, `taken` and `size` accept `:` (within), `<`, `<=`, `>`, `>=` and ranges
+//! `FROM..TO`. Globs are SQLite GLOB patterns, `*` matches '/' too.
+//! Terms are combined with `and` (also implied), `or`, `not` and parentheses,
+//! values with spaces are quoted, e.g. `tag:"my trip"`.
+use crate::dblayer::{GeoBox, TAG_PATH_SEPARATOR};
+use crate::fsdbtime::dbtime_from_date;
+use crate::vpath;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum QueryError {
+    #[error("unexpected end of the expression")]
+    UnexpectedEnd,
+    #[error("unexpected '{token}'")]
+    UnexpectedToken { token: String },
+    #[error("unknown term '{term}'")]
+    UnknownTerm { term: String },
+    #[error("bad value '{value}' for '{key}'")]
+    BadValue { key: String, value: String },
+    #[error("unclosed quote")]
+    UnclosedQuote,
+}
+
+pub type QueryResult<T> = Result<T, QueryError>;
+
+/// `[start, end)`, unbounded if None.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Range {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Term {
+    Tag(String),
+    Tagged,
+    Untagged,
+    Collection(String),
+    Path {
+        collection: Option<String>,
+        glob: String,
+    },
+    Name(String),
+    Mime(String),
+    Lens(String),
+    /// make or model
+    Camera(String),
+    Gps(GeoBox),
+    ModTime(Range),
+    /// capture time, or the modification time if unknown
+    Taken(Range),
+    Size(Range),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Term(Term),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    /// `quoted` words are never keywords
+    Word {
+        text: String,
+        quoted: bool,
+    },
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word { text, quoted: false } if text == keyword)
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Token::LParen => "(".to_owned(),
+            Token::RParen => ")".to_owned(),
+            Token::Word { text, .. } => text.clone(),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> QueryResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    if c == '"' {
+                        quoted = true;
+                        loop {
+                            match chars.next() {
+                                Some('"') => break,
+                                Some(c) => word.push(c),
+                                None => return Err(QueryError::UnclosedQuote),
+                            }
+                        }
+                    } else {
+                        word.push(c);
+                    }
+                }
+                tokens.push(Token::Word { text: word, quoted });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+
+    fn parse_or(&mut self) -> QueryResult<Expr> {
+        let mut left = self.parse_and()?;
+        while self.peek().is_some_and(|t| t.is_keyword("or")) {
+            self.next();
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> QueryResult<Expr> {
+        let mut left = self.parse_not()?;
+        loop {
+            match self.peek() {
+                None | Some(Token::RParen) => break,
+                Some(t) if t.is_keyword("or") => break,
+                Some(t) if t.is_keyword("and") => {
+                    self.next();
+                }
+                _ => (),
+            }
+            left = Expr::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> QueryResult<Expr> {
+        if self.peek().is_some_and(|t| t.is_keyword("not")) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> QueryResult<Expr> {
+        match self.next() {
+            None => Err(QueryError::UnexpectedEnd),
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    Some(t) => Err(QueryError::UnexpectedToken { token: t.text() }),
+                    None => Err(QueryError::UnexpectedEnd),
+                }
+            }
+            Some(Token::RParen) => Err(QueryError::UnexpectedToken {
+                token: ")".to_owned(),
+            }),
+            Some(t @ Token::Word { quoted: false, .. })
+                if t.is_keyword("and") || t.is_keyword("or") =>
+            {
+                Err(QueryError::UnexpectedToken { token: t.text() })
+            }
+            Some(Token::Word { text, .. }) => Ok(Expr::Term(parse_term(text)?)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Op {
+    Within,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+}
+
+/// Splits 'size>=1M' into key, operator and value.
+fn split_term(term: &str) -> Option<(&str, Op, &str)> {
+    let pos = term.find([':', '=', '<', '>'])?;
+    let (key, rest) = term.split_at(pos);
+    for (op_text, op) in [
+        ("<=", Op::LessEq),
+        (">=", Op::GreaterEq),
+        ("<", Op::Less),
+        (">", Op::Greater),
+        (":", Op::Within),
+        ("=", Op::Within),
+    ] {
+        if let Some(value) = rest.strip_prefix(op_text) {
+            return Some((key, op, value));
+        }
+    }
+    None
+}
+
+/// `[start, end)` of 'YYYY', 'YYYY-MM' or 'YYYY-MM-DD'.
+fn parse_date_period(text: &str) -> Option<(i64, i64)> {
+    let parts: Vec<&str> = text.split('-').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let year: i64 = parts[0].parse().ok()?;
+    let month: u32 = match parts.get(1) {
+        Some(m) => m.parse().ok().filter(|m| (1..=12).contains(m))?,
+        None => 1,
+    };
+    let day: u32 = match parts.get(2) {
+        Some(d) => d.parse().ok().filter(|d| (1..=31).contains(d))?,
+        None => 1,
+    };
+    let start = dbtime_from_date(year, month, day)?;
+    let end = match parts.len() {
+        1 => dbtime_from_date(year + 1, 1, 1)?,
+        2 if month == 12 => dbtime_from_date(year + 1, 1, 1)?,
+        2 => dbtime_from_date(year, month + 1, 1)?,
+        _ => start.checked_add(86_400 * 1_000_000_000)?,
+    };
+    Some((start, end))
+}
+
+/// `[n, n + 1)` for sizes like '100', '1k', '2M', '1G'.
+fn parse_size_period(text: &str) -> Option<(i64, i64)> {
+    let (digits, multiplier) = match text.char_indices().last()? {
+        (pos, 'k' | 'K') => (&text[..pos], 1 << 10),
+        (pos, 'm' | 'M') => (&text[..pos], 1 << 20),
+        (pos, 'g' | 'G') => (&text[..pos], 1 << 30),
+        _ => (text, 1),
+    };
+    let size = digits.parse::<i64>().ok()?.checked_mul(multiplier)?;
+    Some((size, size.checked_add(1)?))
+}
+
+fn parse_range(
+    key: &str,
+    op: Op,
+    value: &str,
+    parse_period: fn(&str) -> Option<(i64, i64)>,
+) -> QueryResult<Range> {
+    let bad_value = || QueryError::BadValue {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    };
+    if let Some((from, to)) = value.split_once("..") {
+        if op != Op::Within {
+            return Err(bad_value());
+        }
+        let (start, _) = parse_period(from).ok_or_else(bad_value)?;
+        let (_, end) = parse_period(to).ok_or_else(bad_value)?;
+        return Ok(Range {
+            start: Some(start),
+            end: Some(end),
+        });
+    }
+    let (start, end) = parse_period(value).ok_or_else(bad_value)?;
+    Ok(match op {
+        Op::Within => Range {
+            start: Some(start),
+            end: Some(end),
+        },
+        Op::Less => Range {
+            start: None,
+            end: Some(start),
+        },
+        Op::LessEq => Range {
+            start: None,
+            end: Some(end),
+        },
+        Op::Greater => Range {
+            start: Some(end),
+            end: None,
+        },
+        Op::GreaterEq => Range {
+            start: Some(start),
+            end: None,
+        },
+    })
+}
+
+fn parse_term(text: &str) -> QueryResult<Term> {
+    match text {
+        "tagged" => return Ok(Term::Tagged),
+        "untagged" => return Ok(Term::Untagged),
+        _ => (),
+    }
+    let unknown = || QueryError::UnknownTerm {
+        term: text.to_owned(),
+    };
+    let (key, op, value) = split_term(text).ok_or_else(unknown)?;
+    let bad_value = || QueryError::BadValue {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    };
+    match key {
+        "date" => {
+            return Ok(Term::ModTime(parse_range(
+                key,
+                op,
+                value,
+                parse_date_period,
+            )?))
+        }
+        "taken" => return Ok(Term::Taken(parse_range(key, op, value, parse_date_period)?)),
+        "size" => return Ok(Term::Size(parse_range(key, op, value, parse_size_period)?)),
+        _ => (),
+    }
+    if op != Op::Within || value.is_empty() {
+        return Err(bad_value());
+    }
+    let value = value.to_owned();
+    match key {
+        "tag" => Ok(Term::Tag(value)),
+        "col" => Ok(Term::Collection(value)),
+        "name" => Ok(Term::Name(value)),
+        "mime" => Ok(Term::Mime(value)),
+        "lens" => Ok(Term::Lens(value)),
+        "camera" => Ok(Term::Camera(value)),
+        "gps" => Ok(Term::Gps(value.parse().map_err(|_| bad_value())?)),
+        "path" => Ok(match vpath::parse(&value) {
+            Some((col_name, glob)) => Term::Path {
+                collection: Some(col_name.to_owned()),
+                glob: glob.to_owned(),
+            },
+            None => Term::Path {
+                collection: None,
+                glob: value.trim_start_matches('/').to_owned(),
+            },
+        }),
+        _ => Err(unknown()),
+    }
+}
+
+pub fn parse(text: &str) -> QueryResult<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    let expr = parser.parse_or()?;
+    match parser.next() {
+        None => Ok(expr),
+        Some(t) => Err(QueryError::UnexpectedToken { token: t.text() }),
+    }
+}
+
+/// SQL condition over `e` (dir_entries), `c` (collections), `ct`
+/// (capture_times) and `tree.rel_path`, see `dblayer::read::Db::select_files`.
+#[derive(Debug)]
+pub struct SqlCondition {
+    pub sql: String,
+    pub params: Vec<sqlite::Value>,
+}
+
+fn range_sql(column: &str, range: &Range, params: &mut Vec<sqlite::Value>) -> String {
+    let mut conditions = Vec::new();
+    if let Some(start) = range.start {
+        params.push(start.into());
+        conditions.push(format!("{column} >= ?{}", params.len()));
+    }
+    if let Some(end) = range.end {
+        params.push(end.into());
+        conditions.push(format!("{column} < ?{}", params.len()));
+    }
+    if conditions.is_empty() {
+        "1".to_owned()
+    } else {
+        format!("({})", conditions.join(" AND "))
+    }
+}
+
+fn term_sql(term: &Term, params: &mut Vec<sqlite::Value>) -> String {
+    let mut param = |value: &str| {
+        params.push(value.into());
+        format!("?{}", params.len())
+    };
+    match term {
+        Term::Tag(value) => {
+            let p = param(value);
+            format!(
+                "e.id IN (SELECT m.dir_entry_id FROM tag_to_dir_entry m
+                JOIN subject_tags t ON t.id = m.subject_tag_id
+                WHERE t.tag_value = {p}
+                OR substr(t.tag_value, 1, length({p}) + 1) = {p} || '{TAG_PATH_SEPARATOR}')"
+            )
+        }
+        Term::Tagged => "e.id IN (SELECT dir_entry_id FROM tag_to_dir_entry)".to_owned(),
+        Term::Untagged => "e.id NOT IN (SELECT dir_entry_id FROM tag_to_dir_entry)".to_owned(),
+        Term::Collection(glob) => format!("c.coll_name GLOB {}", param(glob)),
+        Term::Path {
+            collection: Some(col_name),
+            glob,
+        } => {
+            let col_param = param(col_name);
+            format!(
+                "(c.coll_name = {col_param} AND tree.rel_path GLOB {})",
+                param(glob)
+            )
+        }
+        Term::Path {
+            collection: None,
+            glob,
+        } => format!("tree.rel_path GLOB {}", param(glob)),
+        Term::Name(glob) => format!("e.fs_name GLOB {}", param(glob)),
+        Term::Mime(glob) => format!(
+            "e.id IN (SELECT m.dir_entry_id FROM mime_to_dir_entry m
+            JOIN mime_types t ON t.id = m.mime_type_id WHERE t.mime_value GLOB {})",
+            param(glob)
+        ),
+        Term::Lens(glob) => format!(
+            "e.id IN (SELECT dir_entry_id FROM media_info WHERE lens GLOB {})",
+            param(glob)
+        ),
+        Term::Camera(glob) => {
+            let p = param(glob);
+            format!(
+                "e.id IN (SELECT dir_entry_id FROM media_info
+                WHERE camera_make GLOB {p} OR camera_model GLOB {p})"
+            )
+        }
+        Term::Gps(area) => format!(
+            "e.id IN (SELECT dir_entry_id FROM media_info WHERE {})",
+            area.sql_condition(params)
+        ),
+        Term::ModTime(range) => range_sql("e.fs_mod_time", range, params),
+        Term::Taken(range) => range_sql("COALESCE(ct.date_time, e.fs_mod_time)", range, params),
+        Term::Size(range) => range_sql("e.fs_size", range, params),
+    }
+}
+
+fn expr_sql(expr: &Expr, params: &mut Vec<sqlite::Value>) -> String {
+    match expr {
+        Expr::Term(term) => term_sql(term, params),
+        Expr::Not(e) => format!("NOT ({})", expr_sql(e, params)),
+        Expr::And(a, b) => format!("({} AND {})", expr_sql(a, params), expr_sql(b, params)),
+        Expr::Or(a, b) => format!("({} OR {})", expr_sql(a, params), expr_sql(b, params)),
+    }
+}
+
+pub fn compile(expr: &Expr) -> SqlCondition {
+    let mut params = Vec::new();
+    let sql = expr_sql(expr, &mut params);
+    SqlCondition { sql, params }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dblayer::{self, DirEntry, MediaInfo, SelectOrder};
+
+    fn tag(value: &str) -> Box<Expr> {
+        Box::new(Expr::Term(Term::Tag(value.to_owned())))
+    }
+
+    #[test]
+    fn parse_expr() {
+        assert_eq!(
+            parse("tag:a tag:b or not (tag:\"c d\")").unwrap(),
+            Expr::Or(
+                Box::new(Expr::And(tag("a"), tag("b"))),
+                Box::new(Expr::Not(tag("c d")))
+            )
+        );
+        assert_eq!(
+            parse("size>=1k").unwrap(),
+            Expr::Term(Term::Size(Range {
+                start: Some(1024),
+                end: None
+            }))
+        );
+        assert_eq!(
+            parse("path:card:/dcim/*.jpg").unwrap(),
+            Expr::Term(Term::Path {
+                collection: Some("card".to_owned()),
+                glob: "dcim/*.jpg".to_owned()
+            })
+        );
+        assert_eq!(parse("tag:a and").unwrap_err(), QueryError::UnexpectedEnd);
+        assert_eq!(parse("(tag:a").unwrap_err(), QueryError::UnexpectedEnd);
+        assert!(matches!(
+            parse("date:2023-13").unwrap_err(),
+            QueryError::BadValue { .. }
+        ));
+        for overflow in [
+            "size:9223372036854775807",
+            "size>8589934592G",
+            "date:99999999999",
+        ] {
+            assert!(matches!(
+                parse(overflow).unwrap_err(),
+                QueryError::BadValue { .. }
+            ));
+        }
+        assert!(matches!(
+            parse("colour:red").unwrap_err(),
+            QueryError::UnknownTerm { .. }
+        ));
+    }
+
+    #[test]
+    fn date_ranges() {
+        let day = 86_400 * 1_000_000_000;
+        let jan1 = dbtime_from_date(2023, 1, 1).unwrap();
+        assert_eq!(parse_date_period("2023-01-01"), Some((jan1, jan1 + day)));
+        assert_eq!(
+            parse_date_period("2023-12"),
+            Some((
+                dbtime_from_date(2023, 12, 1).unwrap(),
+                dbtime_from_date(2024, 1, 1).unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_range("date", Op::Greater, "2023", parse_date_period).unwrap(),
+            Range {
+                start: Some(dbtime_from_date(2024, 1, 1).unwrap()),
+                end: None
+            }
+        );
+        assert_eq!(
+            parse_range("date", Op::Within, "2023-02..2023-03", parse_date_period).unwrap(),
+            Range {
+                start: Some(dbtime_from_date(2023, 2, 1).unwrap()),
+                end: Some(dbtime_from_date(2023, 4, 1).unwrap())
+            }
+        );
+    }
+
+    #[test]
+    fn select() {
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let files = [
+            (1, "root", true, 0, 0),
+            (2, "dcim", true, 0, 0),
+            (
+                3,
+                "a.jpg",
+                false,
+                100,
+                dbtime_from_date(2023, 5, 3).unwrap(),
+            ),
+            (
+                4,
+                "b.png",
+                false,
+                2 << 20,
+                dbtime_from_date(2022, 1, 1).unwrap(),
+            ),
+            (5, "c.jpg", false, 10, dbtime_from_date(2023, 7, 1).unwrap()),
+        ];
+        for (id, name, is_dir, size, mod_time) in files {
+            wdb.create_dir_entry(&DirEntry {
+                id,
+                fs_name: name.to_owned(),
+                fs_mod_time: mod_time,
+                last_sync_time: 0,
+                is_dir,
+                fs_size: size,
+            })
+            .unwrap();
+        }
+        wdb.map_dir_entry_to_parent_dir(2, 1).unwrap();
+        wdb.map_dir_entry_to_parent_dir(3, 2).unwrap();
+        wdb.map_dir_entry_to_parent_dir(4, 2).unwrap();
+        wdb.map_dir_entry_to_parent_dir(5, 1).unwrap();
+        wdb.create_collection("card", "/mnt/card", 1, dblayer::DEFAULT_FILTER_ID)
+            .unwrap();
+        wdb.set_mime_type(3, "image/jpeg").unwrap();
+        wdb.set_mime_type(4, "image/png").unwrap();
+        wdb.set_mime_type(5, "video/mp4").unwrap();
+        wdb.set_capture_time(5, Some(dbtime_from_date(2021, 6, 1).unwrap()))
+            .unwrap();
+        let nice = wdb.create_subject_tag("places|france|nice").unwrap();
+        wdb.assign_tag(nice.id, 3).unwrap();
+        let olympus = MediaInfo {
+            camera_make: Some("OLYMPUS CORPORATION".to_owned()),
+            camera_model: Some("E-M5MarkII".to_owned()),
+            lens: Some("OLYMPUS M.12-40mm F2.8".to_owned()),
+            latitude: Some(43.705),
+            longitude: Some(7.26),
+            ..Default::default()
+        };
+        wdb.set_media_info(3, Some(&olympus)).unwrap();
+        let phone = MediaInfo {
+            camera_model: Some("Pixel 7".to_owned()),
+            latitude: Some(-17.7),
+            longitude: Some(178.1),
+            ..Default::default()
+        };
+        wdb.set_media_info(5, Some(&phone)).unwrap();
+
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let select_sorted = |text: &str, order: SelectOrder| -> Vec<String> {
+            let cond = compile(&parse(text).unwrap());
+            rdb.select_files(&cond.sql, &cond.params, order)
+                .unwrap()
+                .into_iter()
+                .map(|f| f.rel_path)
+                .collect()
+        };
+        let select = |text: &str| select_sorted(text, SelectOrder::Path);
+        assert_eq!(select("tag:places"), vec!["dcim/a.jpg"]);
+        assert!(select("tag:place").is_empty());
+        assert_eq!(select("untagged"), vec!["c.jpg", "dcim/b.png"]);
+        assert_eq!(select("path:dcim/*"), vec!["dcim/a.jpg", "dcim/b.png"]);
+        assert_eq!(select("name:*.jpg and date:2023-07"), vec!["c.jpg"]);
+        assert_eq!(select("size>1M or date<2023"), vec!["dcim/b.png"]);
+        assert_eq!(
+            select("col:card and not path:other:/*"),
+            vec!["c.jpg", "dcim/a.jpg", "dcim/b.png"]
+        );
+        assert_eq!(select("mime:image/*"), vec!["dcim/a.jpg", "dcim/b.png"]);
+        assert_eq!(
+            select("mime:video/* or mime:image/png"),
+            vec!["c.jpg", "dcim/b.png"]
+        );
+        assert!(select("mime:audio/*").is_empty());
+        assert_eq!(select("lens:*12-40mm*"), vec!["dcim/a.jpg"]);
+        assert_eq!(select("camera:OLYMPUS*"), vec!["dcim/a.jpg"]);
+        assert_eq!(select("camera:Pixel*"), vec!["c.jpg"]);
+        assert_eq!(select("gps:35,-10,60,30"), vec!["dcim/a.jpg"]);
+        assert_eq!(select("gps:-30,170,0,-170"), vec!["c.jpg"]);
+        assert_eq!(select("not gps:-90,-180,90,180"), vec!["dcim/b.png"]);
+        assert!(parse("gps:1,2,3").is_err());
+        assert_eq!(select("taken:2021"), vec!["c.jpg"]);
+        assert_eq!(select("taken<2023"), vec!["c.jpg", "dcim/b.png"]);
+        assert_eq!(select("date<2023"), vec!["dcim/b.png"]);
+        assert_eq!(
+            select_sorted("name:*", SelectOrder::Taken),
+            vec!["c.jpg", "dcim/b.png", "dcim/a.jpg"]
+        );
+        assert_eq!(
+            select_sorted("name:*", SelectOrder::ModTime),
+            vec!["dcim/b.png", "dcim/a.jpg", "c.jpg"]
+        );
+    }
+}
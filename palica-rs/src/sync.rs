@@ -1,14 +1,24 @@
-use crate::dblayer::DirEntry;
+use crate::coll_builder::{new_entry_from_fs, store_file_metadata};
+use crate::dblayer::{self, Collection, DbId, DirEntry};
 use crate::fsdbtime::dbtime_from_sys;
-use crate::fslayer::FsDirEntry;
+use crate::fslayer::{self, FsDirEntry};
+use crate::glob_filter::Filter;
+use crate::hashing::sha256_file;
+use crate::sync_moves::{match_moves, MoveCandidate};
 
-#[derive(PartialEq, Debug)]
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum DbFsCompareResult {
     Same,
     DbItemBecameDir,
     DbItemBecameFile,
     ModTime,
     Size,
+    /// same date and size, but the content differs from the stored hash
+    Content,
 }
 
 /// Quick comparison. Checks date for dir, date + size for a file.
@@ -30,10 +40,12 @@ pub fn compare_db_to_fsitem(db_item: &DirEntry, fs_item: &FsDirEntry) -> DbFsCom
             DbFsCompareResult::ModTime
         };
     } else {
-        if is_same_date && db_item.fs_size == fs_item.size as i64 {
-            return DbFsCompareResult::Same;
-        } else {
+        if db_item.fs_size != fs_item.size as i64 {
             return DbFsCompareResult::Size;
+        } else if !is_same_date {
+            return DbFsCompareResult::ModTime;
+        } else {
+            return DbFsCompareResult::Same;
         }
     }
 }
@@ -48,15 +60,771 @@ Sync directory algorithm fs -> db:
         - if not found then add to "new"
         - if different then add to "update"
     - remove the handled item from the db list
-5) the db list now contains items to delete
+5) the db list now contains vanished items
+6) match the vanished items and their db subtrees with the new ones, see
+   sync_moves.rs, matched items keep their db rows and get the new name and
+   parent, the rest of the vanished items are deleted
 
+Directory modification time changes only if direct children are added or
+removed, so every directory is visited regardless of its date.
 */
 
+pub type SyncResult<T> = anyhow::Result<T>;
+
+#[derive(Debug)]
+pub enum SyncChange<'a> {
+    Added(&'a DirEntry),
+    /// Reported for every entry existing both in the db and on the
+    /// filesystem, including the unchanged ones.
+    Compared {
+        db_item: &'a DirEntry,
+        fs_item: &'a FsDirEntry,
+        result: DbFsCompareResult,
+    },
+    Deleted(&'a DirEntry),
+    /// Renamed or moved to another directory, `entry` has the new attributes
+    /// and the old id, the path passed along is the new one.
+    Moved {
+        entry: &'a DirEntry,
+        from: &'a Path,
+    },
+}
+
+/// Called with the filesystem path of the entry.
+pub type OnSyncChange<'a> = dyn Fn(&Path, &SyncChange) + 'a;
+
+/// Used if the 'sync_delete_threshold' setting is missing.
+pub const DEFAULT_DELETE_THRESHOLD_PERCENT: u32 = 25;
+
+#[derive(Debug, Default)]
+pub struct SyncOptions {
+    /// Only report, do not modify the db.
+    pub dry: bool,
+    /// Refuse to sync if more entries than the percentage of the collection
+    /// are about to be deleted, e.g. the media is not mounted or mismatched.
+    pub delete_threshold_percent: Option<u32>,
+    /// Compare contents of the files looking unchanged with their stored
+    /// hashes, see hashing.rs.
+    pub verify_content: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SyncStats {
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    /// entries inside the deleted directories
+    pub deleted_nested: usize,
+    /// renamed or moved entries, not counting the ones inside the moved
+    /// directories
+    pub moved: usize,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SyncError {
+    #[error("collection root is not available: {path}")]
+    RootNotAvailable { path: String },
+    #[error("too many entries to delete: {deleted} of {total}")]
+    TooManyDeletions { deleted: usize, total: usize },
+}
+
+/// true if deleting `deleted` of `total` entries exceeds the threshold
+pub fn exceeds_delete_threshold(deleted: usize, total: usize, threshold_percent: u32) -> bool {
+    total > 0 && deleted * 100 > total * threshold_percent as usize
+}
+
+fn count_nested(read_db: &dblayer::read::Db, entry: &DirEntry) -> SyncResult<usize> {
+    if entry.is_dir {
+        Ok(read_db.subtree_entry_count(entry.id)?)
+    } else {
+        Ok(0)
+    }
+}
+
+/// The vanished entries followed by their db subtrees.
+fn with_subtrees(
+    read_db: &mut dblayer::read::Db,
+    vanished: Vec<MoveCandidate>,
+) -> SyncResult<Vec<MoveCandidate>> {
+    let mut res = Vec::new();
+    for item in vanished {
+        let (path, entry) = (item.path.clone(), item.entry.clone());
+        res.push(item);
+        if !entry.is_dir {
+            continue;
+        }
+        let mut dir_ids = HashMap::from([(String::new(), entry.id)]);
+        for (rel_path, sub_entry) in read_db.enum_subtree(entry.id)? {
+            let parent_rel = rel_path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            let parent_id = dir_ids[parent_rel];
+            if sub_entry.is_dir {
+                dir_ids.insert(rel_path.clone(), sub_entry.id);
+            }
+            res.push(MoveCandidate {
+                path: path.join(&rel_path),
+                entry: sub_entry,
+                parent_id,
+            });
+        }
+    }
+    Ok(res)
+}
+
+/// Valid stored hashes of the vanished files.
+fn stored_hashes(
+    read_db: &dblayer::read::Db,
+    vanished: &[MoveCandidate],
+) -> SyncResult<HashMap<DbId, String>> {
+    let mut res = HashMap::new();
+    for item in vanished.iter().filter(|item| !item.entry.is_dir) {
+        if let Some(hash) = read_db.file_hash(item.entry.id)? {
+            if hash.is_valid_for(&item.entry) {
+                res.insert(item.entry.id, hash.hash_value);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Moves the matched vanished entries to the places of the new ones,
+/// deletes the rest of the vanished entries and reports the added ones.
+fn apply_moves(
+    write_db: &mut dblayer::write::Db,
+    vanished: Vec<MoveCandidate>,
+    added: Vec<MoveCandidate>,
+    options: &SyncOptions,
+    sync_time: SystemTime,
+    stats: &mut SyncStats,
+    on_change: &OnSyncChange,
+) -> SyncResult<()> {
+    let mut read_db = dblayer::read::Db::new(write_db.conn)?;
+    let vanished = with_subtrees(&mut read_db, vanished)?;
+    let old_hashes = stored_hashes(&read_db, &vanished)?;
+    let pairs = match_moves(
+        &vanished,
+        &added,
+        &old_hashes,
+        &mut |path| match sha256_file(path) {
+            Ok(hash_value) => Some(hash_value),
+            Err(e) => {
+                eprintln!("Failed to hash '{}': {e}", path.to_string_lossy());
+                None
+            }
+        },
+    );
+
+    // new directories replaced by the vanished ones
+    let new_to_old: HashMap<DbId, DbId> = pairs
+        .iter()
+        .map(|p| (added[p.new].entry.id, vanished[p.old].entry.id))
+        .collect();
+    for pair in &pairs {
+        let (old, new) = (&vanished[pair.old], &added[pair.new]);
+        let parent_id = *new_to_old.get(&new.parent_id).unwrap_or(&new.parent_id);
+        let moved = DirEntry {
+            id: old.entry.id,
+            last_sync_time: dbtime_from_sys(sync_time),
+            ..new.entry.clone()
+        };
+        // entries inside moved directories keep their parent and name
+        if parent_id != old.parent_id || moved.fs_name != old.entry.fs_name {
+            on_change(
+                &new.path,
+                &SyncChange::Moved {
+                    entry: &moved,
+                    from: &old.path,
+                },
+            );
+            stats.moved += 1;
+        }
+        if !options.dry {
+            write_db.update_dir_entry(&moved)?;
+            write_db.move_dir_entry(moved.id, parent_id)?;
+            store_file_metadata(write_db, &moved, &new.path)?;
+            if let Some(hash_value) = &pair.hash {
+                write_db.set_file_hash(&dblayer::FileHash {
+                    dir_entry_id: moved.id,
+                    hash_value: hash_value.clone(),
+                    fs_mod_time: moved.fs_mod_time,
+                    fs_size: moved.fs_size,
+                })?;
+            }
+        }
+    }
+
+    let matched_old: HashSet<usize> = pairs.iter().map(|p| p.old).collect();
+    let deleted_ids: HashSet<DbId> = vanished
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !matched_old.contains(i))
+        .map(|(_, item)| item.entry.id)
+        .collect();
+    for item in vanished
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !matched_old.contains(i))
+        .map(|(_, item)| item)
+    {
+        // deleted along with the parent
+        if deleted_ids.contains(&item.parent_id) {
+            stats.deleted_nested += 1;
+            continue;
+        }
+        on_change(&item.path, &SyncChange::Deleted(&item.entry));
+        stats.deleted += 1;
+        if !options.dry {
+            // the matched entries of the subtree are moved out already
+            write_db.delete_dir_entry(item.entry)?;
+        }
+    }
+
+    let matched_new: HashSet<usize> = pairs.iter().map(|p| p.new).collect();
+    for (i, item) in added.into_iter().enumerate() {
+        if !matched_new.contains(&i) {
+            on_change(&item.path, &SyncChange::Added(&item.entry));
+            stats.added += 1;
+            continue;
+        }
+        if !options.dry {
+            if let Some(old_id) = new_to_old.get(&item.entry.id) {
+                write_db.move_dir_children(item.entry.id, *old_id)?;
+            }
+            write_db.delete_dir_entry(item.entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// New hash of the file if it differs from the valid stored hash.
+fn changed_content_hash(
+    read_db: &dblayer::read::Db,
+    db_item: &DirEntry,
+    path: &Path,
+) -> SyncResult<Option<String>> {
+    let stored = match read_db.file_hash(db_item.id)? {
+        Some(hash) if hash.is_valid_for(db_item) => hash,
+        _ => return Ok(None),
+    };
+    match sha256_file(path) {
+        Ok(hash_value) if hash_value != stored.hash_value => Ok(Some(hash_value)),
+        Ok(_) => Ok(None),
+        Err(e) => {
+            eprintln!("Failed to verify '{}': {e}", path.to_string_lossy());
+            Ok(None)
+        }
+    }
+}
+
+fn updated_entry_from_fs(
+    db_item: &DirEntry,
+    fs_item: &FsDirEntry,
+    sync_time: SystemTime,
+) -> DirEntry {
+    DirEntry {
+        id: db_item.id,
+        fs_name: db_item.fs_name.clone(),
+        fs_mod_time: dbtime_from_sys(fs_item.mod_time),
+        last_sync_time: dbtime_from_sys(sync_time),
+        is_dir: db_item.is_dir,
+        fs_size: fs_item.size as i64,
+    }
+}
+
+/// Brings the collection entries in line with the filesystem: inserts new
+/// entries, updates changed ones and deletes vanished or filtered out ones.
+pub fn sync_collection(
+    write_db: &mut dblayer::write::Db,
+    col: &Collection,
+    filter: &Filter,
+    options: &SyncOptions,
+    on_change: &OnSyncChange,
+) -> SyncResult<SyncStats> {
+    let src_path = Path::new(&col.fs_path);
+    if !src_path.is_dir() {
+        return Err(SyncError::RootNotAvailable {
+            path: col.fs_path.clone(),
+        }
+        .into());
+    }
+    let root_fs_entry = fslayer::read::dir_entry(src_path)?;
+    if !root_fs_entry.is_dir {
+        return Err(anyhow::Error::msg(format!(
+            "Collection path is not a directory: '{}'!",
+            col.fs_path
+        )));
+    }
+
+    let mut tx = dblayer::Transaction::new(write_db.conn);
+    let sync_time = SystemTime::now();
+    let mut read_db = dblayer::read::Db::new(write_db.conn)?;
+    let mut stats = SyncStats::default();
+
+    let root_entry = read_db.dir_entry_by_id(col.root_id)?.ok_or_else(|| {
+        anyhow::Error::msg(format!("No root entry for collection '{}'!", col.coll_name))
+    })?;
+    let total_entries = read_db.subtree_entry_count(root_entry.id)?;
+    let root_cmp = compare_db_to_fsitem(&root_entry, &root_fs_entry);
+    on_change(
+        src_path,
+        &SyncChange::Compared {
+            db_item: &root_entry,
+            fs_item: &root_fs_entry,
+            result: root_cmp,
+        },
+    );
+    if root_cmp != DbFsCompareResult::Same {
+        let updated = updated_entry_from_fs(&root_entry, &root_fs_entry, sync_time);
+        if !options.dry {
+            write_db.update_dir_entry(&updated)?;
+        }
+        stats.updated += 1;
+    }
+
+    let mut id_gen =
+        dblayer::write::IdGen::new_with_last_id(write_db.max_id(DirEntry::table_name()));
+
+    // subdirs -> parent_id, Path
+    let mut subdirs = VecDeque::<(DbId, PathBuf)>::new();
+    subdirs.push_back((root_entry.id, src_path.to_owned()));
+
+    let mut filter_fn = |item: &Path, is_dir| filter.include(&item.to_string_lossy(), is_dir);
+    let mut added = Vec::<MoveCandidate>::new();
+    let mut vanished = Vec::<MoveCandidate>::new();
+
+    while let Some((dir_id, dir_path)) = subdirs.pop_front() {
+        let fs_items: Vec<FsDirEntry> = match fslayer::read::dir_entries(&dir_path, &mut filter_fn)
+        {
+            Ok(entries) => entries.collect(),
+            Err(e) => {
+                // keep the db contents of unreadable directories
+                eprintln!("Skipped '{}': {e}", dir_path.to_string_lossy());
+                continue;
+            }
+        };
+
+        let mut db_items: HashMap<String, DirEntry> = read_db
+            .enum_dir_entries(dir_id)?
+            .into_iter()
+            .map(|e| (e.fs_name.clone(), e))
+            .collect();
+
+        for fs_item in fs_items {
+            let item_path = dir_path.join(&fs_item.name);
+            if let Some(db_item) = db_items.remove(&fs_item.name) {
+                let mut cmp = compare_db_to_fsitem(&db_item, &fs_item);
+                let mut new_hash = None;
+                if cmp == DbFsCompareResult::Same && options.verify_content && !db_item.is_dir {
+                    new_hash = changed_content_hash(&read_db, &db_item, &item_path)?;
+                    if new_hash.is_some() {
+                        cmp = DbFsCompareResult::Content;
+                    }
+                }
+                on_change(
+                    &item_path,
+                    &SyncChange::Compared {
+                        db_item: &db_item,
+                        fs_item: &fs_item,
+                        result: cmp,
+                    },
+                );
+                match cmp {
+                    DbFsCompareResult::Same => {
+                        if db_item.is_dir {
+                            subdirs.push_back((db_item.id, item_path));
+                        } else if !options.dry
+                            && read_db.mime_type_of_dir_entry(db_item.id)?.is_none()
+                        {
+                            // synced before mime types were stored
+                            store_file_metadata(write_db, &db_item, &item_path)?;
+                        }
+                        continue;
+                    }
+                    DbFsCompareResult::DbItemBecameDir | DbFsCompareResult::DbItemBecameFile => {
+                        stats.deleted += 1;
+                        stats.deleted_nested += count_nested(&read_db, &db_item)?;
+                        if !options.dry {
+                            write_db.delete_dir_entry(db_item)?;
+                        }
+                    }
+                    DbFsCompareResult::ModTime
+                    | DbFsCompareResult::Size
+                    | DbFsCompareResult::Content => {
+                        let updated = updated_entry_from_fs(&db_item, &fs_item, sync_time);
+                        if !options.dry {
+                            write_db.update_dir_entry(&updated)?;
+                            store_file_metadata(write_db, &updated, &item_path)?;
+                            if let Some(hash_value) = new_hash {
+                                write_db.set_file_hash(&dblayer::FileHash {
+                                    dir_entry_id: updated.id,
+                                    hash_value,
+                                    fs_mod_time: updated.fs_mod_time,
+                                    fs_size: updated.fs_size,
+                                })?;
+                            }
+                        }
+                        stats.updated += 1;
+                        if updated.is_dir {
+                            subdirs.push_back((updated.id, item_path));
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            // in dry mode new directories get ids unknown to the db,
+            // so their db contents are empty and the whole subtree is reported
+            let new_entry = new_entry_from_fs(&fs_item, id_gen.gen_id(), sync_time);
+            if !options.dry {
+                write_db.create_dir_entry(&new_entry)?;
+                write_db.map_dir_entry_to_parent_dir(new_entry.id, dir_id)?;
+                store_file_metadata(write_db, &new_entry, &item_path)?;
+            }
+            if new_entry.is_dir {
+                subdirs.push_back((new_entry.id, item_path.clone()));
+            }
+            added.push(MoveCandidate {
+                path: item_path,
+                entry: new_entry,
+                parent_id: dir_id,
+            });
+        }
+
+        // not on the filesystem anymore, or excluded by the filter
+        for (name, db_item) in db_items {
+            vanished.push(MoveCandidate {
+                path: dir_path.join(name),
+                entry: db_item,
+                parent_id: dir_id,
+            });
+        }
+    }
+
+    apply_moves(
+        write_db, vanished, added, options, sync_time, &mut stats, on_change,
+    )?;
+
+    if let Some(threshold) = options.delete_threshold_percent {
+        let deleted = stats.deleted + stats.deleted_nested;
+        if exceeds_delete_threshold(deleted, total_entries, threshold) {
+            // dropping the transaction rolls back all the changes
+            return Err(SyncError::TooManyDeletions {
+                deleted,
+                total: total_entries,
+            }
+            .into());
+        }
+    }
+
+    tx.commit();
+    Ok(stats)
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::test_util::TmpDir;
     use std::time::SystemTime;
 
     use super::*;
+    use crate::coll_builder::new_collection;
+    use dblayer::write;
+
+    #[test]
+    fn sync_verify_content() {
+        use std::fs;
+        let tmp = TmpDir::new("sync-verify");
+        let tmp_dir = tmp.path();
+        fs::write(tmp_dir.join("a.txt"), "abc").unwrap();
+
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut db, "testcol", tmp_dir, 1, &Filter::new(), &|_| {}).unwrap();
+        crate::hashing::hash_collection(&conn, &col, false, &|_, _| {}).unwrap();
+
+        // same size and date, different content
+        let file_path = tmp_dir.join("a.txt");
+        let mod_time = fs::metadata(&file_path).unwrap().modified().unwrap();
+        fs::write(&file_path, "abd").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&file_path)
+            .unwrap()
+            .set_modified(mod_time)
+            .unwrap();
+
+        let quick = sync_collection(
+            &mut db,
+            &col,
+            &Filter::new(),
+            &SyncOptions {
+                dry: true,
+                ..Default::default()
+            },
+            &|_, _| {},
+        )
+        .unwrap();
+        let changes = std::cell::RefCell::new(Vec::new());
+        let verified = sync_collection(
+            &mut db,
+            &col,
+            &Filter::new(),
+            &SyncOptions {
+                verify_content: true,
+                ..Default::default()
+            },
+            &|_, change| {
+                if let SyncChange::Compared { result, .. } = change {
+                    changes.borrow_mut().push(*result);
+                }
+            },
+        )
+        .unwrap();
+        let new_hash = sha256_file(&file_path).unwrap();
+
+        assert_eq!(quick.updated, 0);
+        assert_eq!(verified.updated, 1);
+        assert!(changes.into_inner().contains(&DbFsCompareResult::Content));
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let entry = rdb
+            .dir_entry_by_path(col.root_id, "a.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            rdb.file_hash(entry.id).unwrap().unwrap().hash_value,
+            new_hash
+        );
+    }
+
+    #[test]
+    fn sync_moves() {
+        use std::fs;
+        let tmp = TmpDir::new("sync-moves");
+        let tmp_dir = tmp.path();
+        fs::create_dir_all(tmp_dir.join("2023/sub")).unwrap();
+        fs::write(tmp_dir.join("2023/a.txt"), "a").unwrap();
+        fs::write(tmp_dir.join("2023/sub/b.txt"), "bb").unwrap();
+        fs::write(tmp_dir.join("c.txt"), "ccc").unwrap();
+
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut db, "testcol", tmp_dir, 1, &Filter::new(), &|_| {}).unwrap();
+        crate::hashing::hash_collection(&conn, &col, false, &|_, _| {}).unwrap();
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let dir = rdb.dir_entry_by_path(col.root_id, "2023").unwrap().unwrap();
+        let b = rdb
+            .dir_entry_by_path(col.root_id, "2023/sub/b.txt")
+            .unwrap()
+            .unwrap();
+        let c = rdb
+            .dir_entry_by_path(col.root_id, "c.txt")
+            .unwrap()
+            .unwrap();
+        let tag = db.create_subject_tag("trip").unwrap();
+        db.assign_tag(tag.id, b.id).unwrap();
+
+        fs::rename(tmp_dir.join("2023"), tmp_dir.join("2023-trip")).unwrap();
+        fs::rename(tmp_dir.join("c.txt"), tmp_dir.join("2023-trip/sub/c.txt")).unwrap();
+
+        let dry_stats = sync_collection(
+            &mut db,
+            &col,
+            &Filter::new(),
+            &SyncOptions {
+                dry: true,
+                ..Default::default()
+            },
+            &|_, _| {},
+        )
+        .unwrap();
+        let moves = std::cell::RefCell::new(Vec::new());
+        let stats = sync_collection(
+            &mut db,
+            &col,
+            &Filter::new(),
+            &SyncOptions::default(),
+            &|path, change| {
+                if let SyncChange::Moved { from, .. } = change {
+                    moves
+                        .borrow_mut()
+                        .push((from.to_path_buf(), path.to_owned()));
+                }
+            },
+        )
+        .unwrap();
+
+        assert_eq!(dry_stats, stats);
+        assert_eq!(stats.added, 0);
+        assert_eq!(stats.deleted, 0);
+        assert_eq!(stats.moved, 2);
+        let root = Path::new(&col.fs_path);
+        let mut moves = moves.into_inner();
+        moves.sort();
+        assert_eq!(
+            moves,
+            vec![
+                (root.join("2023"), root.join("2023-trip")),
+                (root.join("c.txt"), root.join("2023-trip/sub/c.txt")),
+            ]
+        );
+        let moved_dir = rdb
+            .dir_entry_by_path(col.root_id, "2023-trip")
+            .unwrap()
+            .unwrap();
+        assert_eq!(moved_dir.id, dir.id);
+        let moved_b = rdb
+            .dir_entry_by_path(col.root_id, "2023-trip/sub/b.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(moved_b.id, b.id);
+        assert_eq!(rdb.entries_with_tag(tag.id).unwrap(), vec![b.id]);
+        let moved_c = rdb
+            .dir_entry_by_path(col.root_id, "2023-trip/sub/c.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(moved_c.id, c.id);
+        assert!(rdb.file_hash(c.id).unwrap().unwrap().is_valid_for(&moved_c));
+        // root, 2023-trip, sub, a, b, c
+        assert_eq!(rdb.subtree_entry_count(col.root_id).unwrap(), 5);
+    }
+
+    #[test]
+    fn sync_col() {
+        use std::fs;
+        let tmp = TmpDir::new("sync-col");
+        let tmp_dir = tmp.path();
+        fs::create_dir_all(tmp_dir.join("sub")).unwrap();
+        fs::write(tmp_dir.join("keep.txt"), "keep").unwrap();
+        fs::write(tmp_dir.join("change.txt"), "a").unwrap();
+        fs::write(tmp_dir.join("sub/gone.txt"), "gone").unwrap();
+
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut db, "testcol", tmp_dir, 1, &Filter::new(), &|_| {}).unwrap();
+
+        fs::write(tmp_dir.join("change.txt"), "abc").unwrap();
+        fs::remove_file(tmp_dir.join("sub/gone.txt")).unwrap();
+        fs::create_dir(tmp_dir.join("new")).unwrap();
+        fs::write(tmp_dir.join("new/new.txt"), "new").unwrap();
+
+        let changes = conn.total_change_count();
+        let dry_stats = sync_collection(
+            &mut db,
+            &col,
+            &Filter::new(),
+            &SyncOptions {
+                dry: true,
+                ..Default::default()
+            },
+            &|_, _| {},
+        )
+        .unwrap();
+        // a dry run doesn't write, not even app_version
+        assert_eq!(conn.total_change_count(), changes);
+        let stats = sync_collection(
+            &mut db,
+            &col,
+            &Filter::new(),
+            &SyncOptions::default(),
+            &|_, _| {},
+        )
+        .unwrap();
+        assert_eq!(dry_stats, stats);
+        assert_eq!(stats.deleted_nested, 0);
+
+        assert_eq!(stats.added, 2);
+        assert_eq!(stats.deleted, 1);
+        // change.txt, sub directory date, root directory date
+        assert!(stats.updated >= 1);
+
+        let mut read_db = dblayer::read::Db::new(&conn).unwrap();
+        let names: Vec<String> = read_db
+            .enum_dir_entries(col.root_id)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.fs_name)
+            .collect();
+        assert_eq!(names, vec!["change.txt", "keep.txt", "new", "sub"]);
+    }
+
+    #[test]
+    fn sync_mime_types() {
+        use std::fs;
+        let tmp = TmpDir::new("sync-mime");
+        let tmp_dir = tmp.path();
+        fs::write(tmp_dir.join("a.txt"), "text").unwrap();
+        fs::write(tmp_dir.join("b.txt"), "text").unwrap();
+
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut db, "testcol", tmp_dir, 1, &Filter::new(), &|_| {}).unwrap();
+        // as if synced by an older version
+        conn.execute("DELETE FROM mime_to_dir_entry").unwrap();
+        fs::copy("../sample-data/img1.jpg", tmp_dir.join("a.txt")).unwrap();
+        fs::copy("../sample-data/img1.webp", tmp_dir.join("c.bin")).unwrap();
+
+        sync_collection(
+            &mut db,
+            &col,
+            &Filter::new(),
+            &SyncOptions::default(),
+            &|_, _| {},
+        )
+        .unwrap();
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let mime_of = |name: &str| {
+            let entry = rdb.child_by_name(col.root_id, name).unwrap().unwrap();
+            rdb.mime_type_of_dir_entry(entry.id).unwrap().unwrap()
+        };
+        assert_eq!(mime_of("a.txt"), "image/jpeg");
+        assert_eq!(mime_of("b.txt"), "text/plain");
+        assert_eq!(mime_of("c.bin"), "image/webp");
+    }
+
+    #[test]
+    fn sync_mass_delete() {
+        use std::fs;
+        let tmp = TmpDir::new("sync-mass-delete");
+        let tmp_dir = tmp.path();
+        fs::create_dir_all(tmp_dir.join("dcim")).unwrap();
+        for name in ["a.jpg", "b.jpg", "c.jpg"] {
+            fs::write(tmp_dir.join("dcim").join(name), name).unwrap();
+        }
+
+        let conn = write::create_new(":memory:").unwrap();
+        let mut db = write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut db, "testcol", tmp_dir, 1, &Filter::new(), &|_| {}).unwrap();
+
+        fs::remove_dir_all(tmp_dir.join("dcim")).unwrap();
+        let options = SyncOptions {
+            delete_threshold_percent: Some(50),
+            ..Default::default()
+        };
+        let res = sync_collection(&mut db, &col, &Filter::new(), &options, &|_, _| {});
+        match res.unwrap_err().downcast_ref::<SyncError>() {
+            Some(SyncError::TooManyDeletions { deleted, total }) => {
+                assert_eq!(*deleted, 4);
+                assert_eq!(*total, 4);
+            }
+            _ => panic!("expected TooManyDeletions"),
+        }
+        let read_db = dblayer::read::Db::new(&conn).unwrap();
+        assert_eq!(read_db.subtree_entry_count(col.root_id).unwrap(), 4);
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+        let res = sync_collection(&mut db, &col, &Filter::new(), &options, &|_, _| {});
+        match res.unwrap_err().downcast_ref::<SyncError>() {
+            Some(SyncError::RootNotAvailable { .. }) => (),
+            _ => panic!("expected RootNotAvailable"),
+        }
+    }
+
+    #[test]
+    fn delete_threshold() {
+        assert!(!exceeds_delete_threshold(0, 0, 0));
+        assert!(!exceeds_delete_threshold(1, 4, 25));
+        assert!(exceeds_delete_threshold(2, 4, 25));
+        assert!(!exceeds_delete_threshold(4, 4, 100));
+    }
+
     #[test]
     fn compare_db_fs() {
         let time_now = SystemTime::now();
@@ -113,5 +881,14 @@ mod tests {
             compare_db_to_fsitem(&db_diritem_file, &fs_diritem_file_size),
             DbFsCompareResult::Size
         );
+        let fs_diritem_file_date = FsDirEntry::new_file(
+            "file".to_owned(),
+            32,
+            time_now + std::time::Duration::from_secs(1),
+        );
+        assert_eq!(
+            compare_db_to_fsitem(&db_diritem_file, &fs_diritem_file_date),
+            DbFsCompareResult::ModTime
+        );
     }
 }
@@ -0,0 +1,302 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::dblayer::{DbId, DbTime, DirEntry};
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/*
+Matching of vanished and new entries found by a sync:
+1) files by the stored hash of the vanished file, new files are hashed
+   only if some vanished hashed file has the same size
+2) remaining files by name + size + mod time, if unambiguous
+3) directories by votes: every matched file pair votes for its vanished and
+   new parent directories, and their parents up to the first not vanished
+   or not new one
+4) remaining directories by name + size + mod time, if unambiguous
+*/
+
+/// Entry vanished from the filesystem or new on it.
+#[derive(Debug, Clone)]
+pub struct MoveCandidate {
+    pub path: PathBuf,
+    pub entry: DirEntry,
+    pub parent_id: DbId,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MovePair {
+    /// index of the vanished candidate
+    pub old: usize,
+    /// index of the new candidate
+    pub new: usize,
+    /// hash of the new file if it was computed
+    pub hash: Option<String>,
+}
+
+/// Called with the path of a new file, None if it can't be hashed.
+pub type HashFile<'a> = dyn FnMut(&Path) -> Option<String> + 'a;
+
+type HeuristicKey<'a> = (bool, &'a str, i64, DbTime);
+
+fn heuristic_key(candidate: &MoveCandidate) -> HeuristicKey<'_> {
+    (
+        candidate.entry.is_dir,
+        &candidate.entry.fs_name,
+        candidate.entry.fs_size,
+        candidate.entry.fs_mod_time,
+    )
+}
+
+/// Pairs of vanished and new entries being the same file or directory.
+/// `old_hashes` are the valid stored hashes of the vanished files by id.
+pub fn match_moves(
+    vanished: &[MoveCandidate],
+    added: &[MoveCandidate],
+    old_hashes: &HashMap<DbId, String>,
+    hash_file: &mut HashFile,
+) -> Vec<MovePair> {
+    let mut pairs = Vec::new();
+    let mut used_old = HashSet::<usize>::new();
+    let mut used_new = HashSet::<usize>::new();
+
+    let mut by_hash = HashMap::<&str, Vec<usize>>::new();
+    for (i, old) in vanished.iter().enumerate() {
+        if let Some(hash_value) = old_hashes.get(&old.entry.id) {
+            by_hash.entry(hash_value).or_default().push(i);
+        }
+    }
+    let hashed_sizes: HashSet<i64> = by_hash
+        .values()
+        .flatten()
+        .map(|i| vanished[*i].entry.fs_size)
+        .collect();
+    for (i, new) in added.iter().enumerate() {
+        if new.entry.is_dir || !hashed_sizes.contains(&new.entry.fs_size) {
+            continue;
+        }
+        let Some(hash_value) = hash_file(&new.path) else {
+            continue;
+        };
+        let Some(candidates) = by_hash.get(hash_value.as_str()) else {
+            continue;
+        };
+        // prefer the same name among identical copies
+        let unused = || candidates.iter().filter(|c| !used_old.contains(c));
+        let found = unused()
+            .find(|c| vanished[**c].entry.fs_name == new.entry.fs_name)
+            .or_else(|| unused().next())
+            .copied();
+        if let Some(old) = found {
+            used_old.insert(old);
+            used_new.insert(i);
+            pairs.push(MovePair {
+                old,
+                new: i,
+                hash: Some(hash_value),
+            });
+        }
+    }
+
+    let unhashed = |i: &usize| !old_hashes.contains_key(&vanished[*i].entry.id);
+    match_by_heuristic(
+        vanished,
+        added,
+        false,
+        &unhashed,
+        &mut used_old,
+        &mut used_new,
+        &mut pairs,
+    );
+
+    let old_dirs: HashMap<&Path, usize> = vanished
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.entry.is_dir)
+        .map(|(i, c)| (c.path.as_path(), i))
+        .collect();
+    let new_dirs: HashMap<&Path, usize> = added
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.entry.is_dir)
+        .map(|(i, c)| (c.path.as_path(), i))
+        .collect();
+    let mut votes = HashMap::<(usize, usize), usize>::new();
+    for pair in &pairs {
+        let mut old_path = vanished[pair.old].path.parent();
+        let mut new_path = added[pair.new].path.parent();
+        while let (Some(op), Some(np)) = (old_path, new_path) {
+            match (old_dirs.get(op), new_dirs.get(np)) {
+                (Some(old), Some(new)) => *votes.entry((*old, *new)).or_default() += 1,
+                _ => break,
+            }
+            old_path = op.parent();
+            new_path = np.parent();
+        }
+    }
+    let mut votes: Vec<((usize, usize), usize)> = votes.into_iter().collect();
+    votes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    for ((old, new), _) in votes {
+        if !used_old.contains(&old) && !used_new.contains(&new) {
+            used_old.insert(old);
+            used_new.insert(new);
+            pairs.push(MovePair {
+                old,
+                new,
+                hash: None,
+            });
+        }
+    }
+
+    match_by_heuristic(
+        vanished,
+        added,
+        true,
+        &|_| true,
+        &mut used_old,
+        &mut used_new,
+        &mut pairs,
+    );
+    pairs
+}
+
+/// Pairs the unused candidates with the same name, size and mod time,
+/// if there is exactly one of both.
+fn match_by_heuristic(
+    vanished: &[MoveCandidate],
+    added: &[MoveCandidate],
+    is_dir: bool,
+    old_filter: &dyn Fn(&usize) -> bool,
+    used_old: &mut HashSet<usize>,
+    used_new: &mut HashSet<usize>,
+    pairs: &mut Vec<MovePair>,
+) {
+    let mut groups = HashMap::<HeuristicKey, (Vec<usize>, Vec<usize>)>::new();
+    for (i, old) in vanished.iter().enumerate() {
+        if old.entry.is_dir == is_dir && !used_old.contains(&i) && old_filter(&i) {
+            groups.entry(heuristic_key(old)).or_default().0.push(i);
+        }
+    }
+    for (i, new) in added.iter().enumerate() {
+        if new.entry.is_dir == is_dir && !used_new.contains(&i) {
+            if let Some(group) = groups.get_mut(&heuristic_key(new)) {
+                group.1.push(i);
+            }
+        }
+    }
+    let mut found: Vec<(usize, usize)> = groups
+        .into_values()
+        .filter_map(|(olds, news)| match (olds.as_slice(), news.as_slice()) {
+            ([old], [new]) => Some((*old, *new)),
+            _ => None,
+        })
+        .collect();
+    found.sort();
+    for (old, new) in found {
+        used_old.insert(old);
+        used_new.insert(new);
+        pairs.push(MovePair {
+            old,
+            new,
+            hash: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: DbId, path: &str, is_dir: bool, fs_size: i64) -> MoveCandidate {
+        let path = PathBuf::from(path);
+        MoveCandidate {
+            entry: DirEntry {
+                id,
+                fs_name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                fs_mod_time: 1000,
+                last_sync_time: 2000,
+                is_dir,
+                fs_size,
+            },
+            path,
+            parent_id: 1,
+        }
+    }
+
+    #[test]
+    fn match_renamed_dir() {
+        let vanished = vec![
+            candidate(10, "/c/2023", true, 0),
+            candidate(11, "/c/2023/a.jpg", false, 5),
+            candidate(12, "/c/2023/sub", true, 0),
+            candidate(13, "/c/2023/sub/b.jpg", false, 7),
+            candidate(14, "/c/gone.jpg", false, 9),
+            candidate(15, "/c/renamed.jpg", false, 3),
+        ];
+        let added = vec![
+            candidate(20, "/c/trip", true, 0),
+            candidate(21, "/c/trip/a.jpg", false, 5),
+            candidate(22, "/c/trip/sub", true, 0),
+            candidate(23, "/c/trip/sub/b-copy.jpg", false, 7),
+            candidate(24, "/c/new.jpg", false, 9),
+            candidate(25, "/c/new-name.jpg", false, 3),
+        ];
+        let old_hashes = HashMap::from([
+            (13, "b".to_owned()),
+            (14, "gone".to_owned()),
+            (15, "r".to_owned()),
+        ]);
+        let hashed = std::cell::RefCell::new(Vec::new());
+        let mut pairs = match_moves(&vanished, &added, &old_hashes, &mut |path| {
+            hashed.borrow_mut().push(path.to_owned());
+            match path.to_str().unwrap() {
+                "/c/trip/sub/b-copy.jpg" => Some("b".to_owned()),
+                "/c/new-name.jpg" => Some("r".to_owned()),
+                _ => Some("other".to_owned()),
+            }
+        });
+        // only the files of the same size as a hashed vanished file
+        assert_eq!(hashed.into_inner().len(), 3);
+        pairs.sort_by_key(|p| p.old);
+        let matched: Vec<(usize, usize)> = pairs.iter().map(|p| (p.old, p.new)).collect();
+        // gone.jpg has a different hash than new.jpg
+        assert_eq!(matched, vec![(0, 0), (1, 1), (2, 2), (3, 3), (5, 5)]);
+        assert_eq!(pairs[3].hash, Some("b".to_owned()));
+        assert_eq!(pairs[1].hash, None);
+    }
+
+    #[test]
+    fn ambiguous_heuristic() {
+        let vanished = vec![
+            candidate(10, "/c/a/x.jpg", false, 5),
+            candidate(11, "/c/b/x.jpg", false, 5),
+        ];
+        let added = vec![candidate(20, "/c/x.jpg", false, 5)];
+        let pairs = match_moves(&vanished, &added, &HashMap::new(), &mut |_| None);
+        assert!(pairs.is_empty());
+        let pairs = match_moves(&vanished[..1], &added, &HashMap::new(), &mut |_| None);
+        assert_eq!(
+            pairs,
+            vec![MovePair {
+                old: 0,
+                new: 0,
+                hash: None
+            }]
+        );
+    }
+}
@@ -0,0 +1,291 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::dblayer::Collection;
+use crate::fsdbtime::dbtime_from_sys;
+use crate::sync::{DbFsCompareResult, SyncChange};
+use crate::vpath;
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    /// one JSON object per line
+    JsonLines,
+    Toml,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(ReportFormat::JsonLines),
+            "toml" => Ok(ReportFormat::Toml),
+            _ => Err(format!(
+                "unknown report format '{s}', expected 'jsonl' or 'toml'"
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryChange {
+    Added,
+    Removed,
+    Same,
+    ModTime,
+    Size,
+    Content,
+    BecameDir,
+    BecameFile,
+    Moved,
+}
+
+impl From<DbFsCompareResult> for EntryChange {
+    fn from(result: DbFsCompareResult) -> Self {
+        match result {
+            DbFsCompareResult::Same => EntryChange::Same,
+            DbFsCompareResult::DbItemBecameDir => EntryChange::BecameDir,
+            DbFsCompareResult::DbItemBecameFile => EntryChange::BecameFile,
+            DbFsCompareResult::ModTime => EntryChange::ModTime,
+            DbFsCompareResult::Size => EntryChange::Size,
+            DbFsCompareResult::Content => EntryChange::Content,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct CollectionStatus {
+    pub collection: String,
+    pub fs_path: String,
+    /// false if the collection root directory is missing, e.g. not mounted
+    pub available: bool,
+}
+
+/// Times are in nanoseconds since unix epoch, as in the db.
+#[derive(Serialize, Debug)]
+pub struct EntryStatus {
+    pub v_path: String,
+    pub is_dir: bool,
+    pub change: EntryChange,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db_mod_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fs_mod_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db_size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fs_size: Option<i64>,
+    /// previous location of a moved entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_v_path: Option<String>,
+}
+
+impl EntryStatus {
+    pub fn from_change(col: &Collection, fs_path: &Path, change: &SyncChange) -> EntryStatus {
+        let v_path = vpath::from_fs_path(&col.coll_name, Path::new(&col.fs_path), fs_path);
+        match change {
+            SyncChange::Added(entry) => EntryStatus {
+                v_path,
+                is_dir: entry.is_dir,
+                change: EntryChange::Added,
+                db_mod_time: None,
+                fs_mod_time: Some(entry.fs_mod_time),
+                db_size: None,
+                fs_size: Some(entry.fs_size),
+                from_v_path: None,
+            },
+            SyncChange::Compared {
+                db_item,
+                fs_item,
+                result,
+            } => EntryStatus {
+                v_path,
+                is_dir: fs_item.is_dir,
+                change: (*result).into(),
+                db_mod_time: Some(db_item.fs_mod_time),
+                fs_mod_time: Some(dbtime_from_sys(fs_item.mod_time)),
+                db_size: Some(db_item.fs_size),
+                fs_size: Some(fs_item.size as i64),
+                from_v_path: None,
+            },
+            SyncChange::Deleted(entry) => EntryStatus {
+                v_path,
+                is_dir: entry.is_dir,
+                change: EntryChange::Removed,
+                db_mod_time: Some(entry.fs_mod_time),
+                fs_mod_time: None,
+                db_size: Some(entry.fs_size),
+                fs_size: None,
+                from_v_path: None,
+            },
+            SyncChange::Moved { entry, from } => EntryStatus {
+                v_path,
+                is_dir: entry.is_dir,
+                change: EntryChange::Moved,
+                db_mod_time: None,
+                fs_mod_time: Some(entry.fs_mod_time),
+                db_size: None,
+                fs_size: Some(entry.fs_size),
+                from_v_path: Some(vpath::from_fs_path(
+                    &col.coll_name,
+                    Path::new(&col.fs_path),
+                    from,
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum JsonRecord<'a> {
+    Collection(&'a CollectionStatus),
+    Entry(&'a EntryStatus),
+}
+
+/// Whole report as a single TOML document.
+#[derive(Serialize, Debug, Default)]
+pub struct SyncReport {
+    pub collections: Vec<CollectionStatus>,
+    pub entries: Vec<EntryStatus>,
+}
+
+/// Collects the records, nothing is printed until finish(), so a report
+/// of a failed (rolled back) sync can be dropped.
+pub struct ReportWriter {
+    format: ReportFormat,
+    include_same: bool,
+    report: RefCell<SyncReport>,
+}
+
+impl ReportWriter {
+    pub fn new(format: ReportFormat, include_same: bool) -> ReportWriter {
+        ReportWriter {
+            format,
+            include_same,
+            report: RefCell::new(SyncReport::default()),
+        }
+    }
+
+    pub fn collection(&self, status: CollectionStatus) {
+        self.report.borrow_mut().collections.push(status);
+    }
+
+    pub fn entry(&self, status: EntryStatus) {
+        if status.change == EntryChange::Same && !self.include_same {
+            return;
+        }
+        self.report.borrow_mut().entries.push(status);
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        let report = self.report.into_inner();
+        match self.format {
+            ReportFormat::JsonLines => {
+                // collections are listed before their entries
+                for col in &report.collections {
+                    println!("{}", json_line(&JsonRecord::Collection(col)));
+                }
+                for entry in &report.entries {
+                    println!("{}", json_line(&JsonRecord::Entry(entry)));
+                }
+            }
+            ReportFormat::Toml => print!("{}", toml::to_string(&report)?),
+        }
+        Ok(())
+    }
+}
+
+fn json_line(record: &JsonRecord) -> String {
+    serde_json::to_string(record).expect("Failed to serialize report record.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dblayer::DirEntry;
+
+    fn sample_col() -> Collection {
+        Collection {
+            id: 1,
+            coll_name: "card".to_owned(),
+            fs_path: "/mnt/card".to_owned(),
+            root_id: 1,
+            glob_filter_id: 1,
+        }
+    }
+
+    fn sample_entry() -> DirEntry {
+        DirEntry {
+            id: 2,
+            fs_name: "a.jpg".to_owned(),
+            fs_mod_time: 5,
+            last_sync_time: 6,
+            is_dir: false,
+            fs_size: 7,
+        }
+    }
+
+    #[test]
+    fn entry_json() {
+        let entry = sample_entry();
+        let status = EntryStatus::from_change(
+            &sample_col(),
+            Path::new("/mnt/card/dcim/a.jpg"),
+            &SyncChange::Deleted(&entry),
+        );
+        assert_eq!(
+            json_line(&JsonRecord::Entry(&status)),
+            r#"{"record":"entry","v_path":"card:/dcim/a.jpg","is_dir":false,"change":"removed","db_mod_time":5,"db_size":7}"#
+        );
+    }
+
+    #[test]
+    fn report_toml() {
+        let entry = sample_entry();
+        let report = SyncReport {
+            collections: vec![CollectionStatus {
+                collection: "card".to_owned(),
+                fs_path: "/mnt/card".to_owned(),
+                available: true,
+            }],
+            entries: vec![EntryStatus::from_change(
+                &sample_col(),
+                Path::new("/mnt/card/a.jpg"),
+                &SyncChange::Added(&entry),
+            )],
+        };
+        let text = toml::to_string(&report).unwrap();
+        assert!(text.contains("[[collections]]"));
+        assert!(text.contains("[[entries]]"));
+        assert!(text.contains(r#"v_path = "card:/a.jpg""#));
+        assert!(text.contains(r#"change = "added""#));
+    }
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!("jsonl".parse(), Ok(ReportFormat::JsonLines));
+        assert_eq!("toml".parse(), Ok(ReportFormat::Toml));
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+}
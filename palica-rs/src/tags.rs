@@ -0,0 +1,326 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::dblayer::{self, Collection, DbId, SubjectTag};
+use crate::vpath;
+use crate::xmp;
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub struct TagDiff {
+    pub v_path: String,
+    pub xmp_only: Vec<String>,
+    pub db_only: Vec<String>,
+}
+
+pub type OnTagDiff<'a> = dyn Fn(&TagDiff) + 'a;
+pub type OnSidecarWrite<'a> = dyn Fn(&Path) + 'a;
+
+/// Reads sidecars of the collection files, or the XMP embedded into the
+/// files without sidecars, and assigns tags found only there, unless `dry`.
+/// Reports files whose tags differ. Returns the number of assigned tags.
+pub fn read_sidecar_tags(
+    conn: &sqlite::Connection,
+    col: &Collection,
+    dry: bool,
+    on_diff: &OnTagDiff,
+) -> anyhow::Result<usize> {
+    let mut rdb = dblayer::read::Db::new(conn)?;
+    let wdb = dblayer::write::Db::new(conn)?;
+    let mut tx = dblayer::Transaction::new(conn);
+    let col_root = Path::new(&col.fs_path);
+    let mut assigned = 0;
+
+    for (rel_path, entry) in rdb.enum_subtree(col.root_id)? {
+        let fs_path = col_root.join(&rel_path);
+        if entry.is_dir || xmp::is_sidecar(&fs_path) {
+            continue;
+        }
+        let xmp_tags = match vienik_rs::find_sidecar(&fs_path) {
+            Some(sidecar) => match xmp::read_sidecar_tags(&sidecar) {
+                Ok(tags) => tags.tag_paths(),
+                Err(e) => {
+                    eprintln!("Skipped '{}': {e}", sidecar.to_string_lossy());
+                    continue;
+                }
+            },
+            // keywords embedded into the file itself
+            None => match vienik_rs::read_metadata(&fs_path) {
+                Ok(meta) => xmp::XmpTags::from(meta).tag_paths(),
+                Err(e) => {
+                    eprintln!("Skipped '{}': {e}", fs_path.to_string_lossy());
+                    continue;
+                }
+            },
+        };
+        let db_tags: BTreeSet<String> = rdb
+            .tags_of_dir_entry(entry.id)?
+            .into_iter()
+            .map(|t| t.tag_value)
+            .collect();
+        if xmp_tags == db_tags {
+            continue;
+        }
+
+        let diff = TagDiff {
+            v_path: vpath::from_fs_path(&col.coll_name, col_root, &fs_path),
+            xmp_only: xmp_tags.difference(&db_tags).cloned().collect(),
+            db_only: db_tags.difference(&xmp_tags).cloned().collect(),
+        };
+        if !dry {
+            for tag_value in &diff.xmp_only {
+                if !SubjectTag::is_valid_value(tag_value) {
+                    eprintln!("Skipped bad tag '{tag_value}' of '{}'.", diff.v_path);
+                    continue;
+                }
+                let tag = match rdb.subject_tag_by_value(tag_value)? {
+                    Some(tag) => tag,
+                    None => wdb.create_subject_tag(tag_value)?,
+                };
+                wdb.assign_tag(tag.id, entry.id)?;
+                assigned += 1;
+            }
+        }
+        on_diff(&diff);
+    }
+
+    tx.commit();
+    Ok(assigned)
+}
+
+pub fn update_xmp_enabled(rdb: &dblayer::read::Db) -> anyhow::Result<bool> {
+    Ok(rdb.setting_value(dblayer::UPDATE_XMP_KEY)?.as_deref() == Some("1"))
+}
+
+/// Writes db tags of the file to its sidecar, unless `dry`. Returns the
+/// sidecar path, None if the sidecar is up to date or not needed.
+fn write_entry_sidecar(
+    rdb: &dblayer::read::Db,
+    fs_path: &Path,
+    entry_id: DbId,
+    dry: bool,
+) -> anyhow::Result<Option<PathBuf>> {
+    if xmp::is_sidecar(fs_path) {
+        return Ok(None);
+    }
+    if !fs_path.is_file() {
+        eprintln!("Skipped '{}': not available.", fs_path.to_string_lossy());
+        return Ok(None);
+    }
+    let db_tags: BTreeSet<String> = rdb
+        .tags_of_dir_entry(entry_id)?
+        .into_iter()
+        .map(|t| t.tag_value)
+        .collect();
+    let sidecar = match vienik_rs::find_sidecar(fs_path) {
+        Some(sidecar) => {
+            if xmp::read_sidecar_tags(&sidecar)?.tag_paths() == db_tags {
+                return Ok(None);
+            }
+            sidecar
+        }
+        None if db_tags.is_empty() => return Ok(None),
+        None => xmp::sidecar_path(fs_path),
+    };
+    if !dry {
+        xmp::write_sidecar_tags(&sidecar, &xmp::XmpTags::from_tag_paths(&db_tags))?;
+    }
+    Ok(Some(sidecar))
+}
+
+/// Creates or updates sidecars of the collection files so that they contain
+/// the db tags, unless `dry`. Returns the number of written sidecars.
+pub fn write_sidecar_tags(
+    conn: &sqlite::Connection,
+    col: &Collection,
+    dry: bool,
+    on_write: &OnSidecarWrite,
+) -> anyhow::Result<usize> {
+    let mut rdb = dblayer::read::Db::new(conn)?;
+    let col_root = Path::new(&col.fs_path);
+    let mut written = 0;
+    for (rel_path, entry) in rdb.enum_subtree(col.root_id)? {
+        if entry.is_dir {
+            continue;
+        }
+        match write_entry_sidecar(&rdb, &col_root.join(&rel_path), entry.id, dry) {
+            Ok(Some(sidecar)) => {
+                on_write(&sidecar);
+                written += 1;
+            }
+            Ok(None) => (),
+            Err(e) => eprintln!("Skipped '{rel_path}': {e}"),
+        }
+    }
+    Ok(written)
+}
+
+/// Updates sidecars of the entries after tag edits.
+pub fn write_entries_sidecars(
+    conn: &sqlite::Connection,
+    entry_ids: &[DbId],
+    on_write: &OnSidecarWrite,
+) -> anyhow::Result<usize> {
+    let rdb = dblayer::read::Db::new(conn)?;
+    let mut written = 0;
+    for entry_id in entry_ids {
+        let Some((col, rel_path)) = rdb.entry_location(*entry_id)? else {
+            continue;
+        };
+        let fs_path = Path::new(&col.fs_path).join(rel_path);
+        if let Some(sidecar) = write_entry_sidecar(&rdb, &fs_path, *entry_id, false)? {
+            on_write(&sidecar);
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coll_builder::new_collection;
+    use crate::glob_filter::Filter;
+    use crate::test_util::TmpDir;
+    use std::cell::RefCell;
+
+    #[test]
+    fn read_tags() {
+        use std::fs;
+        let tmp = TmpDir::new("tags-read");
+        let tmp_dir = tmp.path();
+        fs::write(tmp_dir.join("a.jpg"), "a").unwrap();
+        fs::write(
+            tmp_dir.join("a.jpg.xmp"),
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:subject><rdf:Bag><rdf:li>city</rdf:li><rdf:li>nice</rdf:li></rdf:Bag></dc:subject>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )
+        .unwrap();
+        fs::write(tmp_dir.join("b.jpg"), "b").unwrap();
+        // no sidecar, an XMP APP1 segment
+        let xmp = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description xmlns:lr="http://ns.adobe.com/lightroom/1.0/">
+<lr:hierarchicalSubject><rdf:Bag><rdf:li>places|nice</rdf:li></rdf:Bag></lr:hierarchicalSubject>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        let id = b"http://ns.adobe.com/xap/1.0/\0";
+        jpeg.extend(((id.len() + xmp.len()) as u16 + 2).to_be_bytes());
+        jpeg.extend(id);
+        jpeg.extend(xmp);
+        jpeg.extend([0xFF, 0xD9]);
+        fs::write(tmp_dir.join("c.jpg"), jpeg).unwrap();
+
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut wdb, "col", tmp_dir, 1, &Filter::new(), &|_| {}).unwrap();
+        let col = Collection {
+            fs_path: tmp_dir.to_string_lossy().into_owned(),
+            ..col
+        };
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let b_entry = rdb
+            .dir_entry_by_path(col.root_id, "b.jpg")
+            .unwrap()
+            .unwrap();
+        let family = wdb.create_subject_tag("family").unwrap();
+        wdb.assign_tag(family.id, b_entry.id).unwrap();
+
+        let diffs = RefCell::new(Vec::new());
+        let assigned = read_sidecar_tags(&conn, &col, false, &|d| {
+            diffs.borrow_mut().push(d.v_path.clone());
+        })
+        .unwrap();
+
+        assert_eq!(assigned, 3);
+        assert_eq!(
+            diffs.into_inner(),
+            vec!["col:/a.jpg", "col:/b.jpg", "col:/c.jpg"]
+        );
+        let a_entry = rdb
+            .dir_entry_by_path(col.root_id, "a.jpg")
+            .unwrap()
+            .unwrap();
+        let a_tags: Vec<String> = rdb
+            .tags_of_dir_entry(a_entry.id)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.tag_value)
+            .collect();
+        assert_eq!(a_tags, vec!["city", "nice"]);
+        let c_entry = rdb
+            .dir_entry_by_path(col.root_id, "c.jpg")
+            .unwrap()
+            .unwrap();
+        let c_tags = rdb.tags_of_dir_entry(c_entry.id).unwrap();
+        assert_eq!(c_tags[0].tag_value, "places|nice");
+    }
+
+    #[test]
+    fn write_tags() {
+        use std::fs;
+        let tmp = TmpDir::new("tags-write");
+        let tmp_dir = tmp.path();
+        fs::write(tmp_dir.join("a.jpg"), "a").unwrap();
+        fs::write(tmp_dir.join("b.jpg"), "b").unwrap();
+        fs::write(tmp_dir.join("c.jpg"), "c").unwrap();
+
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let col = new_collection(&mut wdb, "col", tmp_dir, 1, &Filter::new(), &|_| {}).unwrap();
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let a_entry = rdb
+            .dir_entry_by_path(col.root_id, "a.jpg")
+            .unwrap()
+            .unwrap();
+        let b_entry = rdb
+            .dir_entry_by_path(col.root_id, "b.jpg")
+            .unwrap()
+            .unwrap();
+        let city = wdb.create_subject_tag("city").unwrap();
+        let nice = wdb.create_subject_tag("places|france|nice").unwrap();
+        wdb.assign_tag(city.id, a_entry.id).unwrap();
+        wdb.assign_tag(nice.id, a_entry.id).unwrap();
+        wdb.assign_tag(city.id, b_entry.id).unwrap();
+
+        let dry_count = write_sidecar_tags(&conn, &col, true, &|_| {}).unwrap();
+        let count = write_sidecar_tags(&conn, &col, false, &|_| {}).unwrap();
+        let a_tags = xmp::read_sidecar_tags(&tmp_dir.join("a.jpg.xmp")).unwrap();
+        let c_exists = tmp_dir.join("c.jpg.xmp").exists();
+
+        wdb.unassign_tag(city.id, b_entry.id).unwrap();
+        let updated = write_entries_sidecars(&conn, &[b_entry.id], &|_| {}).unwrap();
+        let b_tags = xmp::read_sidecar_tags(&tmp_dir.join("b.jpg.xmp")).unwrap();
+        let again = write_sidecar_tags(&conn, &col, false, &|_| {}).unwrap();
+
+        assert_eq!((dry_count, count), (2, 2));
+        assert_eq!(a_tags.subject, vec!["city", "france", "nice", "places"]);
+        assert_eq!(
+            a_tags.hierarchical_subject,
+            vec!["city", "places|france|nice"]
+        );
+        assert!(!c_exists);
+        assert_eq!(updated, 1);
+        assert_eq!(b_tags, xmp::XmpTags::default());
+        assert_eq!(again, 0);
+    }
+}
@@ -0,0 +1,930 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+//! Thumbnail store, a separate db described by sql/thumbnails1.sql.
+//! Blobs are shared by the sources with the same content hash.
+//! There can be several stores for different formats and sizes, registered
+//! as named profiles in the settings of the main db.
+use crate::dblayer::{
+    self, Collection, DbError, DbId, DbResult, Transaction, THUMBS_PROFILE_KEY_PREFIX,
+};
+use crate::hashing::sha256_file;
+use crate::vpath;
+use crate::xmp;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `produced_by` value for running `cmd_line` per file.
+pub const PRODUCED_BY_CMD_LINE: &str = "cmd_line";
+/// `produced_by` value for the in-process producer, see produce_with_image.
+pub const PRODUCED_BY_IMAGE: &str = "image";
+
+/// Files with these extensions get thumbnails, compared ignoring case.
+pub const IMAGE_EXTENSIONS: &[&str] = &[
+    "avif", "bmp", "gif", "heic", "jpeg", "jpg", "jxl", "png", "tif", "tiff", "webp",
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThumbError {
+    #[error("db error: {error}")]
+    DbError { error: DbError },
+    #[error("bad command line template '{template}': {reason}")]
+    BadCmdLine { template: String, reason: String },
+    #[error("command '{cmd}' failed: {reason}")]
+    CommandFailed { cmd: String, reason: String },
+    #[error("unsupported thumbnail setting {key} = '{value}'")]
+    Unsupported { key: String, value: String },
+    #[error("no such thumbnail profile: '{name}'")]
+    NoProfile { name: String },
+    #[error("thumbnail profile already exists: '{name}'")]
+    ProfileExists { name: String },
+    #[error("bad thumbnail profile name '{name}', use letters, digits, '-', '_' and '.'")]
+    BadProfileName { name: String },
+    #[error("failed to make a thumbnail of '{path}': {error}")]
+    ImageError {
+        path: String,
+        error: image::ImageError,
+    },
+}
+
+impl From<DbError> for ThumbError {
+    fn from(error: DbError) -> Self {
+        ThumbError::DbError { error }
+    }
+}
+
+impl From<sqlite::Error> for ThumbError {
+    fn from(error: sqlite::Error) -> Self {
+        ThumbError::DbError {
+            error: error.into(),
+        }
+    }
+}
+
+pub type ThumbResult<T> = Result<T, ThumbError>;
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| {
+            IMAGE_EXTENSIONS
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+        .unwrap_or(false)
+}
+
+/// Schema version of thumbnails1.sql, newer stores are refused.
+pub const THUMBS_DB_VERSION: i64 = 1;
+
+pub fn create_new(fname: &str) -> DbResult<sqlite::Connection> {
+    let existing = fname != ":memory:" && Path::new(fname).exists();
+    if existing {
+        return Err(DbError::AlreadyExists {
+            name: fname.to_owned(),
+        });
+    }
+    let conn = sqlite::Connection::open(fname)?;
+
+    let schema = "sql/thumbnails1.sql";
+    eprintln!("reading schema {}", schema);
+    let sql = std::fs::read_to_string(schema)?;
+    conn.execute(sql)?;
+    Ok(conn)
+}
+
+pub fn open_existing(fname: &str) -> DbResult<sqlite::Connection> {
+    if !Path::new(fname).exists() {
+        return Err(DbError::NoDbFile {
+            name: fname.to_owned(),
+        });
+    }
+    let conn = sqlite::Connection::open(fname)?;
+    match dblayer::db_version(&conn) {
+        Ok(Some(version)) if version <= THUMBS_DB_VERSION => Ok(conn),
+        _ => Err(DbError::WrongDbSchema {
+            name: fname.to_owned(),
+        }),
+    }
+}
+
+/// Settings from the app_info table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThumbsInfo {
+    /// of the blobs, e.g. 'image/jxl'
+    pub mime: String,
+    pub max_width: u32,
+    pub max_height: u32,
+    /// e.g. 'cjxl -j 0 {input} {output}.jxl', see expand_cmd_line
+    pub cmd_line: String,
+    pub hash_type: String,
+    /// PRODUCED_BY_CMD_LINE or PRODUCED_BY_IMAGE
+    pub produced_by: String,
+}
+
+/// Source file known to the store.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SrcItem {
+    pub id: DbId,
+    /// 'collection:/path'
+    pub v_path: String,
+    pub fs_mod_time: i64,
+}
+
+impl SrcItem {
+    pub fn from_row(row: &sqlite::Row) -> SrcItem {
+        SrcItem {
+            id: row.read::<i64, usize>(0),
+            v_path: row.read::<&str, usize>(1).to_owned(),
+            fs_mod_time: row.read::<i64, usize>(2),
+        }
+    }
+}
+
+pub struct Db<'a> {
+    pub conn: &'a sqlite::Connection,
+}
+
+impl Db<'_> {
+    pub fn new(conn: &sqlite::Connection) -> Db<'_> {
+        Db { conn }
+    }
+
+    pub fn app_info_value(&self, key: &str) -> DbResult<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT info_value FROM app_info WHERE info_key = ?1")?;
+        stmt.bind((1, key))?;
+        match stmt.iter().next() {
+            Some(row) => Ok(Some(row?.read::<&str, usize>(0).to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set_app_info_value(&self, key: &str, value: &str) -> DbResult<()> {
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO app_info(info_key, info_value) VALUES(?1, ?2)
+            ON CONFLICT(info_key) DO UPDATE SET info_value = ?2",
+        )?;
+        stmt.bind((1, key))?;
+        stmt.bind((2, value))?;
+        while let sqlite::State::Row = stmt.next()? {}
+        Ok(())
+    }
+
+    pub fn info(&self) -> ThumbResult<ThumbsInfo> {
+        let value = |key: &str| -> ThumbResult<String> {
+            self.app_info_value(key)?
+                .ok_or_else(|| ThumbError::Unsupported {
+                    key: key.to_owned(),
+                    value: String::new(),
+                })
+        };
+        let dimension = |key: &str| -> ThumbResult<u32> {
+            let text = value(key)?;
+            text.parse::<u32>().map_err(|_| ThumbError::Unsupported {
+                key: key.to_owned(),
+                value: text,
+            })
+        };
+        Ok(ThumbsInfo {
+            mime: value("mime")?,
+            max_width: dimension("max_width")?,
+            max_height: dimension("max_height")?,
+            cmd_line: value("cmd_line")?,
+            hash_type: value("hash_type")?,
+            produced_by: value("produced_by")?,
+        })
+    }
+
+    pub fn src_item_by_v_path(&self, v_path: &str) -> DbResult<Option<SrcItem>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, v_path, fs_mod_time FROM src_item WHERE v_path = ?1")?;
+        stmt.bind((1, v_path))?;
+        match stmt.iter().next() {
+            Some(row) => Ok(Some(SrcItem::from_row(&row?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn enum_src_items(&self) -> DbResult<Vec<SrcItem>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, v_path, fs_mod_time FROM src_item ORDER BY v_path")?;
+        let res = stmt.iter().map(|r| Ok(SrcItem::from_row(&r?))).collect();
+        res
+    }
+
+    /// true if the source has a thumbnail
+    pub fn has_thumbnail(&self, src_item_id: DbId) -> DbResult<bool> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT COUNT(*) FROM src_to_thumbnail WHERE src_item_id = ?1")?;
+        stmt.bind((1, src_item_id))?;
+        stmt.next()?;
+        Ok(stmt.read::<i64, _>(0)? > 0)
+    }
+
+    /// Blob of any source with the content hash.
+    pub fn blob_id_by_hash(&self, hash_value: &str) -> DbResult<Option<DbId>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t.thumbnail_blob_id FROM hash_values h
+            JOIN src_to_hash s ON s.hash_value_id = h.id
+            JOIN src_to_thumbnail t ON t.src_item_id = s.src_item_id
+            WHERE h.hash_value = ?1 LIMIT 1",
+        )?;
+        stmt.bind((1, hash_value))?;
+        match stmt.iter().next() {
+            Some(row) => Ok(Some(row?.read::<i64, usize>(0))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn thumbnail_by_v_path(&self, v_path: &str) -> DbResult<Option<Vec<u8>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT b.binary_data FROM src_item s
+            JOIN src_to_thumbnail t ON t.src_item_id = s.id
+            JOIN thumbnail_blob b ON b.id = t.thumbnail_blob_id
+            WHERE s.v_path = ?1",
+        )?;
+        stmt.bind((1, v_path))?;
+        match stmt.iter().next() {
+            Some(row) => Ok(Some(row?.read::<&[u8], usize>(0).to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn thumbnail_by_hash(&self, hash_value: &str) -> DbResult<Option<Vec<u8>>> {
+        let Some(blob_id) = self.blob_id_by_hash(hash_value)? else {
+            return Ok(None);
+        };
+        let mut stmt = self
+            .conn
+            .prepare("SELECT binary_data FROM thumbnail_blob WHERE id = ?1")?;
+        stmt.bind((1, blob_id))?;
+        match stmt.iter().next() {
+            Some(row) => Ok(Some(row?.read::<&[u8], usize>(0).to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    pub fn create_blob(&self, data: &[u8]) -> DbResult<DbId> {
+        let mut stmt = self
+            .conn
+            .prepare("INSERT INTO thumbnail_blob(binary_data) VALUES(?1) RETURNING id")?;
+        stmt.bind((1, data))?;
+        stmt.next()?;
+        Ok(stmt.read::<i64, _>(0)?)
+    }
+
+    fn hash_value_id(&self, hash_value: &str) -> DbResult<DbId> {
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO hash_values(hash_value) VALUES(?1)
+            ON CONFLICT(hash_value) DO UPDATE SET hash_value = hash_value RETURNING id",
+        )?;
+        stmt.bind((1, hash_value))?;
+        stmt.next()?;
+        Ok(stmt.read::<i64, _>(0)?)
+    }
+
+    /// Creates or updates the source and links it to the hash and the blob.
+    pub fn set_thumbnail(
+        &self,
+        v_path: &str,
+        fs_mod_time: i64,
+        hash_value: &str,
+        blob_id: DbId,
+    ) -> DbResult<()> {
+        let mut tx = Transaction::new(self.conn);
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO src_item(v_path, fs_mod_time) VALUES(?1, ?2)
+            ON CONFLICT(v_path) DO UPDATE SET fs_mod_time = ?2 RETURNING id",
+        )?;
+        stmt.bind((1, v_path))?;
+        stmt.bind((2, fs_mod_time))?;
+        stmt.next()?;
+        let src_id = stmt.read::<i64, _>(0)?;
+        drop(stmt);
+        let hash_id = self.hash_value_id(hash_value)?;
+        for (sql, target_id) in [
+            (
+                "INSERT INTO src_to_hash(src_item_id, hash_value_id) VALUES(?1, ?2)
+                ON CONFLICT(src_item_id) DO UPDATE SET hash_value_id = ?2",
+                hash_id,
+            ),
+            (
+                "INSERT INTO src_to_thumbnail(src_item_id, thumbnail_blob_id) VALUES(?1, ?2)
+                ON CONFLICT(src_item_id) DO UPDATE SET thumbnail_blob_id = ?2",
+                blob_id,
+            ),
+        ] {
+            let mut stmt = self.conn.prepare(sql)?;
+            stmt.bind((1, src_id))?;
+            stmt.bind((2, target_id))?;
+            while let sqlite::State::Row = stmt.next()? {}
+        }
+        tx.commit();
+        Ok(())
+    }
+
+    pub fn delete_src_item(&self, src_item_id: DbId) -> DbResult<()> {
+        let mut tx = Transaction::new(self.conn);
+        for sql in [
+            "DELETE FROM src_item WHERE id = ?1",
+            "DELETE FROM src_to_hash WHERE src_item_id = ?1",
+            "DELETE FROM src_to_thumbnail WHERE src_item_id = ?1",
+        ] {
+            let mut stmt = self.conn.prepare(sql)?;
+            stmt.bind((1, src_item_id))?;
+            while let sqlite::State::Row = stmt.next()? {}
+        }
+        tx.commit();
+        Ok(())
+    }
+
+    /// Deletes hashes and blobs no source refers to, returns the number of
+    /// deleted blobs.
+    pub fn delete_orphans(&self) -> DbResult<usize> {
+        self.conn.execute(
+            "DELETE FROM hash_values
+            WHERE id NOT IN (SELECT hash_value_id FROM src_to_hash)",
+        )?;
+        self.conn.execute(
+            "DELETE FROM thumbnail_blob
+            WHERE id NOT IN (SELECT thumbnail_blob_id FROM src_to_thumbnail)",
+        )?;
+        Ok(self.conn.change_count())
+    }
+}
+
+/// Arguments of the command line with `{input}`, `{output}`, `{max_width}`
+/// and `{max_height}` replaced, `{{` and `}}` are literal braces.
+/// Arguments are separated by whitespace, substituted values are never split.
+pub fn expand_cmd_line(
+    template: &str,
+    input: &Path,
+    output: &Path,
+    info: &ThumbsInfo,
+) -> ThumbResult<Vec<String>> {
+    let bad = |reason: &str| ThumbError::BadCmdLine {
+        template: template.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let mut args = Vec::new();
+    let (mut has_input, mut has_output) = (false, false);
+    for word in template.split_whitespace() {
+        let mut arg = String::new();
+        let mut rest = word;
+        while let Some(pos) = rest.find(['{', '}']) {
+            arg.push_str(&rest[..pos]);
+            rest = &rest[pos..];
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                arg.push_str(&rest[..1]);
+                rest = &rest[2..];
+                continue;
+            }
+            let end = rest.find('}').ok_or_else(|| bad("unclosed '{'"))?;
+            match &rest[..=end] {
+                "{input}" => {
+                    has_input = true;
+                    arg.push_str(&input.to_string_lossy());
+                }
+                "{output}" => {
+                    has_output = true;
+                    arg.push_str(&output.to_string_lossy());
+                }
+                "{max_width}" => arg.push_str(&info.max_width.to_string()),
+                "{max_height}" => arg.push_str(&info.max_height.to_string()),
+                _ => return Err(bad(&format!("unknown placeholder '{}'", &rest[..=end]))),
+            }
+            rest = &rest[end + 1..];
+        }
+        arg.push_str(rest);
+        args.push(arg);
+    }
+    if !has_input || !has_output {
+        return Err(bad("both {input} and {output} are required"));
+    }
+    Ok(args)
+}
+
+/// Index of the argument the thumbnail is written to.
+fn output_arg_index(template: &str) -> Option<usize> {
+    template
+        .split_whitespace()
+        .position(|word| word.replace("{{", "").contains("{output}"))
+}
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Thumbnail of the file produced by running the configured command line.
+pub fn run_cmd_line(info: &ThumbsInfo, input: &Path) -> ThumbResult<Vec<u8>> {
+    let output_base: PathBuf = std::env::temp_dir().join(format!(
+        "palica-thumb-{}-{}",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let args = expand_cmd_line(&info.cmd_line, input, &output_base, info)?;
+    let output_path = PathBuf::from(&args[output_arg_index(&info.cmd_line).unwrap_or(0)]);
+    let failed = |reason: String| ThumbError::CommandFailed {
+        cmd: args.join(" "),
+        reason,
+    };
+    let status = std::process::Command::new(&args[0])
+        .args(&args[1..])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_err(|e| failed(e.to_string()))?;
+    let data = std::fs::read(&output_path);
+    let _ = std::fs::remove_file(&output_path);
+    if !status.success() {
+        return Err(failed(status.to_string()));
+    }
+    data.map_err(|e| {
+        failed(format!(
+            "no output '{}': {e}",
+            output_path.to_string_lossy()
+        ))
+    })
+}
+
+/// Output format of the in-process producer by `mime`.
+fn image_output_format(info: &ThumbsInfo) -> ThumbResult<image::ImageFormat> {
+    match image::ImageFormat::from_mime_type(&info.mime) {
+        Some(format) if format.writing_enabled() => Ok(format),
+        _ => Err(ThumbError::Unsupported {
+            key: "mime".to_owned(),
+            value: info.mime.clone(),
+        }),
+    }
+}
+
+/// Thumbnail made in-process: decodes JPEG, PNG or WebP, scales it down to
+/// fit `max_width`x`max_height` and encodes it to the format of `mime`.
+pub fn produce_with_image(info: &ThumbsInfo, input: &Path) -> ThumbResult<Vec<u8>> {
+    let failed = |error| ThumbError::ImageError {
+        path: input.to_string_lossy().into_owned(),
+        error,
+    };
+    let format = image_output_format(info)?;
+    let mut img = image::ImageReader::open(input)
+        .map_err(|e| failed(e.into()))?
+        .with_guessed_format()
+        .map_err(|e| failed(e.into()))?
+        .decode()
+        .map_err(failed)?;
+    if img.width() > info.max_width || img.height() > info.max_height {
+        img = img.thumbnail(info.max_width, info.max_height);
+    }
+    // no alpha in JPEG
+    if format == image::ImageFormat::Jpeg {
+        img = image::DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    let mut data = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut data, format).map_err(failed)?;
+    Ok(data.into_inner())
+}
+
+/// true if the configured producer can read the file
+fn can_produce(info: &ThumbsInfo, path: &Path) -> bool {
+    if info.produced_by == PRODUCED_BY_IMAGE {
+        image::ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled())
+    } else {
+        is_image(path)
+    }
+}
+
+/// Thumbnail of the file by the configured producer.
+pub fn produce(info: &ThumbsInfo, input: &Path) -> ThumbResult<Vec<u8>> {
+    if info.produced_by == PRODUCED_BY_IMAGE {
+        produce_with_image(info, input)
+    } else {
+        run_cmd_line(info, input)
+    }
+}
+
+fn check_producer(info: &ThumbsInfo) -> ThumbResult<()> {
+    if info.hash_type != "sha256" {
+        return Err(ThumbError::Unsupported {
+            key: "hash_type".to_owned(),
+            value: info.hash_type.clone(),
+        });
+    }
+    match info.produced_by.as_str() {
+        PRODUCED_BY_CMD_LINE => Ok(()),
+        PRODUCED_BY_IMAGE => image_output_format(info).map(|_| ()),
+        _ => Err(ThumbError::Unsupported {
+            key: "produced_by".to_owned(),
+            value: info.produced_by.clone(),
+        }),
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ThumbStats {
+    pub built: usize,
+    /// blob of another source with the same content
+    pub reused: usize,
+    /// unchanged since the last build
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Called with the filesystem path of every new thumbnail source.
+pub type OnThumbnail<'a> = dyn Fn(&Path) + 'a;
+
+/// Creates thumbnails of the collection images changed since the last build.
+pub fn build_collection(
+    conn: &sqlite::Connection,
+    thumbs_conn: &sqlite::Connection,
+    col: &Collection,
+    on_thumb: &OnThumbnail,
+) -> ThumbResult<ThumbStats> {
+    let mut rdb = dblayer::read::Db::new(conn)?;
+    let tdb = Db::new(thumbs_conn);
+    let info = tdb.info()?;
+    check_producer(&info)?;
+    tdb.set_app_info_value("app_version", crate::PALICA_VERSION)?;
+
+    let mut stats = ThumbStats::default();
+    let col_root = Path::new(&col.fs_path);
+    for (rel_path, entry) in rdb.enum_subtree(col.root_id)? {
+        let fs_path = col_root.join(&rel_path);
+        if entry.is_dir || xmp::is_sidecar(&fs_path) || !can_produce(&info, &fs_path) {
+            continue;
+        }
+        let v_path = vpath::from_rel_path(&col.coll_name, &rel_path);
+        if let Some(item) = tdb.src_item_by_v_path(&v_path)? {
+            if item.fs_mod_time == entry.fs_mod_time && tdb.has_thumbnail(item.id)? {
+                stats.skipped += 1;
+                continue;
+            }
+        }
+        let hash_value = match rdb.file_hash(entry.id)? {
+            Some(hash) if hash.is_valid_for(&entry) => hash.hash_value,
+            _ => match sha256_file(&fs_path) {
+                Ok(hash_value) => hash_value,
+                Err(e) => {
+                    eprintln!("Failed to hash '{}': {e}", fs_path.to_string_lossy());
+                    stats.failed += 1;
+                    continue;
+                }
+            },
+        };
+        let blob_id = match tdb.blob_id_by_hash(&hash_value)? {
+            Some(blob_id) => {
+                stats.reused += 1;
+                blob_id
+            }
+            None => match produce(&info, &fs_path) {
+                Ok(data) => {
+                    stats.built += 1;
+                    tdb.create_blob(&data)?
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    stats.failed += 1;
+                    continue;
+                }
+            },
+        };
+        tdb.set_thumbnail(&v_path, entry.fs_mod_time, &hash_value, blob_id)?;
+        on_thumb(&fs_path);
+    }
+    Ok(stats)
+}
+
+/// Deletes thumbnails of the sources missing from the metadata db, and
+/// blobs no source refers to. Returns the number of deleted sources.
+pub fn prune(conn: &sqlite::Connection, thumbs_conn: &sqlite::Connection) -> ThumbResult<usize> {
+    let rdb = dblayer::read::Db::new(conn)?;
+    let tdb = Db::new(thumbs_conn);
+    let mut tx = Transaction::new(thumbs_conn);
+    let mut deleted = 0;
+    for item in tdb.enum_src_items()? {
+        let exists = match vpath::parse(&item.v_path) {
+            Some((col_name, rel_path)) => match rdb.collection_by_name(col_name)? {
+                Some(col) => rdb.dir_entry_by_path(col.root_id, rel_path)?.is_some(),
+                None => false,
+            },
+            None => false,
+        };
+        if !exists {
+            tdb.delete_src_item(item.id)?;
+            deleted += 1;
+        }
+    }
+    tdb.delete_orphans()?;
+    tdb.set_app_info_value("app_version", crate::PALICA_VERSION)?;
+    tx.commit();
+    Ok(deleted)
+}
+
+/// Thumbnail db registered in the main db settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    /// e.g. 'small-256-jxl'
+    pub name: String,
+    /// absolute path of the thumbnail db
+    pub fs_path: String,
+}
+
+pub fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+pub fn enum_profiles(rdb: &dblayer::read::Db) -> DbResult<Vec<Profile>> {
+    Ok(rdb
+        .settings_with_prefix(THUMBS_PROFILE_KEY_PREFIX)?
+        .into_iter()
+        .map(|setting| Profile {
+            name: setting.key[THUMBS_PROFILE_KEY_PREFIX.len()..].to_owned(),
+            fs_path: setting.value,
+        })
+        .collect())
+}
+
+pub fn profile_by_name(rdb: &dblayer::read::Db, name: &str) -> ThumbResult<Profile> {
+    match rdb.setting_value(&format!("{THUMBS_PROFILE_KEY_PREFIX}{name}"))? {
+        Some(fs_path) => Ok(Profile {
+            name: name.to_owned(),
+            fs_path,
+        }),
+        None => Err(ThumbError::NoProfile {
+            name: name.to_owned(),
+        }),
+    }
+}
+
+pub fn open_profile(profile: &Profile) -> DbResult<sqlite::Connection> {
+    open_existing(&profile.fs_path)
+}
+
+/// Creates the thumbnail db with the settings of `info` and registers it.
+pub fn create_profile(
+    wdb: &dblayer::write::Db,
+    name: &str,
+    fs_path: &Path,
+    info: &ThumbsInfo,
+) -> ThumbResult<Profile> {
+    if !is_valid_profile_name(name) {
+        return Err(ThumbError::BadProfileName {
+            name: name.to_owned(),
+        });
+    }
+    let rdb = dblayer::read::Db::new(wdb.conn)?;
+    if profile_by_name(&rdb, name).is_ok() {
+        return Err(ThumbError::ProfileExists {
+            name: name.to_owned(),
+        });
+    }
+    check_producer(info)?;
+    let fs_path = std::path::absolute(fs_path)
+        .map_err(DbError::from)?
+        .to_string_lossy()
+        .into_owned();
+    let thumbs_conn = create_new(&fs_path)?;
+    let tdb = Db::new(&thumbs_conn);
+    for (key, value) in [
+        ("mime", info.mime.clone()),
+        ("max_width", info.max_width.to_string()),
+        ("max_height", info.max_height.to_string()),
+        ("cmd_line", info.cmd_line.clone()),
+        ("produced_by", info.produced_by.clone()),
+        ("app_version", crate::PALICA_VERSION.to_owned()),
+    ] {
+        tdb.set_app_info_value(key, &value)?;
+    }
+    wdb.set_setting_value(&format!("{THUMBS_PROFILE_KEY_PREFIX}{name}"), &fs_path)?;
+    Ok(Profile {
+        name: name.to_owned(),
+        fs_path,
+    })
+}
+
+/// Unregisters the profile, its thumbnail db file is kept.
+pub fn remove_profile(wdb: &dblayer::write::Db, name: &str) -> ThumbResult<Profile> {
+    let profile = profile_by_name(&dblayer::read::Db::new(wdb.conn)?, name)?;
+    wdb.delete_setting(&format!("{THUMBS_PROFILE_KEY_PREFIX}{name}"))?;
+    Ok(profile)
+}
+
+/// Prunes the thumbnail dbs of all profiles, missing db files are skipped.
+/// Returns the number of deleted sources.
+pub fn prune_profiles(conn: &sqlite::Connection) -> ThumbResult<usize> {
+    let rdb = dblayer::read::Db::new(conn)?;
+    let mut deleted = 0;
+    for profile in enum_profiles(&rdb)? {
+        if !Path::new(&profile.fs_path).exists() {
+            eprintln!(
+                "Skipped thumbnail profile '{}': no file '{}'.",
+                profile.name, profile.fs_path
+            );
+            continue;
+        }
+        deleted += prune(conn, &open_profile(&profile)?)?;
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coll_builder::new_collection;
+    use crate::glob_filter::Filter;
+    use crate::test_util::TmpDir;
+
+    fn test_info(cmd_line: &str) -> ThumbsInfo {
+        ThumbsInfo {
+            mime: "image/jxl".to_owned(),
+            max_width: 256,
+            max_height: 128,
+            cmd_line: cmd_line.to_owned(),
+            hash_type: "sha256".to_owned(),
+            produced_by: "cmd_line".to_owned(),
+        }
+    }
+
+    #[test]
+    fn cmd_line() {
+        let info = test_info("");
+        let input = Path::new("/photos/my img.jpg");
+        let output = Path::new("/tmp/out");
+        assert_eq!(
+            expand_cmd_line("cjxl -j 0 {input} {output}.jxl", input, output, &info).unwrap(),
+            vec!["cjxl", "-j", "0", "/photos/my img.jpg", "/tmp/out.jxl"]
+        );
+        assert_eq!(
+            expand_cmd_line(
+                "convert {input} -resize {max_width}x{max_height} x{{y}} {output}",
+                input,
+                output,
+                &info
+            )
+            .unwrap(),
+            vec![
+                "convert",
+                "/photos/my img.jpg",
+                "-resize",
+                "256x128",
+                "x{y}",
+                "/tmp/out"
+            ]
+        );
+        assert!(expand_cmd_line("cp {input} {out}", input, output, &info).is_err());
+        assert!(expand_cmd_line("cp {input}", input, output, &info).is_err());
+        assert!(expand_cmd_line("cp {input} {output", input, output, &info).is_err());
+        assert_eq!(output_arg_index("cjxl -j 0 {input} {output}.jxl"), Some(4));
+    }
+
+    #[test]
+    fn image_producer() {
+        let mut info = test_info("");
+        info.produced_by = PRODUCED_BY_IMAGE.to_owned();
+        for (mime, format) in [
+            ("image/png", image::ImageFormat::Png),
+            ("image/jpeg", image::ImageFormat::Jpeg),
+            ("image/webp", image::ImageFormat::WebP),
+        ] {
+            info.mime = mime.to_owned();
+            for src in ["img1.jpg", "img1.webp", "lossless/img1.png"] {
+                let data = produce(&info, &Path::new("../sample-data").join(src)).unwrap();
+                assert_eq!(image::guess_format(&data).unwrap(), format);
+                let thumb = image::load_from_memory(&data).unwrap();
+                assert!(thumb.width() <= 256 && thumb.height() <= 128);
+                assert!(thumb.width() == 256 || thumb.height() == 128);
+            }
+        }
+        info.mime = "image/jxl".to_owned();
+        assert!(produce(&info, Path::new("../sample-data/img1.jpg")).is_err());
+        assert!(can_produce(&info, Path::new("a.PNG")));
+        assert!(!can_produce(&info, Path::new("a.jxl")));
+    }
+
+    #[test]
+    fn profiles() {
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let col = new_collection(
+            &mut wdb,
+            "sample",
+            Path::new("../sample-data"),
+            1,
+            &Filter::new(),
+            &|_| {},
+        )
+        .unwrap();
+        let tmp = TmpDir::new("thumbs-profiles");
+        let tmp_dir = tmp.path();
+        let mut info = test_info("");
+        info.produced_by = PRODUCED_BY_IMAGE.to_owned();
+        info.mime = "image/png".to_owned();
+        let small = create_profile(&wdb, "small-64-png", &tmp_dir.join("small.db"), &{
+            let mut small = info.clone();
+            small.max_width = 64;
+            small.max_height = 64;
+            small
+        })
+        .unwrap();
+        create_profile(&wdb, "preview", &tmp_dir.join("preview.db"), &info).unwrap();
+        assert!(create_profile(&wdb, "preview", &tmp_dir.join("other.db"), &info).is_err());
+        assert!(create_profile(&wdb, "bad name", &tmp_dir.join("other.db"), &info).is_err());
+        info.mime = "image/jxl".to_owned();
+        assert!(create_profile(&wdb, "jxl", &tmp_dir.join("jxl.db"), &info).is_err());
+
+        let rdb = dblayer::read::Db::new(&conn).unwrap();
+        let names: Vec<String> = enum_profiles(&rdb)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, vec!["preview", "small-64-png"]);
+        let small_conn = open_profile(&profile_by_name(&rdb, "small-64-png").unwrap()).unwrap();
+        assert_eq!(Db::new(&small_conn).info().unwrap().max_width, 64);
+        let stats = build_collection(&conn, &small_conn, &col, &|_| {}).unwrap();
+        assert_eq!(stats.failed, 0);
+        assert!(stats.built >= 3);
+        let thumb = Db::new(&small_conn)
+            .thumbnail_by_v_path("sample:/img1.jpg")
+            .unwrap()
+            .unwrap();
+        assert!(image::load_from_memory(&thumb).unwrap().width() <= 64);
+
+        wdb.delete_collection(col).unwrap();
+        assert_eq!(prune_profiles(&conn).unwrap(), stats.built);
+        assert_eq!(remove_profile(&wdb, "small-64-png").unwrap(), small);
+        assert!(profile_by_name(&rdb, "small-64-png").is_err());
+    }
+
+    #[test]
+    fn build_thumbs() {
+        let conn = dblayer::write::create_new(":memory:").unwrap();
+        let mut wdb = dblayer::write::Db::new(&conn).unwrap();
+        let mut cols = Vec::new();
+        for name in ["sample", "copy"] {
+            cols.push(
+                new_collection(
+                    &mut wdb,
+                    name,
+                    Path::new("../sample-data"),
+                    1,
+                    &Filter::new(),
+                    &|_| {},
+                )
+                .unwrap(),
+            );
+        }
+        let thumbs_conn = create_new(":memory:").unwrap();
+        let tdb = Db::new(&thumbs_conn);
+        tdb.set_app_info_value("cmd_line", "cp {input} {output}.bin")
+            .unwrap();
+
+        let first = build_collection(&conn, &thumbs_conn, &cols[0], &|_| {}).unwrap();
+        assert!(first.built > 3);
+        assert_eq!(first.failed, 0);
+        assert_eq!(
+            tdb.thumbnail_by_v_path("sample:/img1.jpg")
+                .unwrap()
+                .unwrap(),
+            std::fs::read("../sample-data/img1.jpg").unwrap()
+        );
+        assert!(tdb
+            .thumbnail_by_v_path("sample:/img1.xcf.xz")
+            .unwrap()
+            .is_none());
+        let again = build_collection(&conn, &thumbs_conn, &cols[0], &|_| {}).unwrap();
+        assert_eq!(again.skipped, first.built);
+        assert_eq!(again.built, 0);
+        let copy = build_collection(&conn, &thumbs_conn, &cols[1], &|_| {}).unwrap();
+        assert_eq!(copy.reused, first.built);
+        assert_eq!(copy.built, 0);
+
+        wdb.delete_collection(cols.remove(0)).unwrap();
+        assert_eq!(prune(&conn, &thumbs_conn).unwrap(), first.built);
+        assert!(tdb
+            .thumbnail_by_v_path("sample:/img1.jpg")
+            .unwrap()
+            .is_none());
+        assert!(tdb.thumbnail_by_v_path("copy:/img1.jpg").unwrap().is_some());
+        assert_eq!(prune(&conn, &thumbs_conn).unwrap(), 0);
+    }
+}
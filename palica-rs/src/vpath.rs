@@ -0,0 +1,70 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use std::path::Path;
+
+/// Separates collection name and the path inside the collection.
+pub const SEPARATOR: &str = ":/";
+
+/// Virtual paths address entries independently of the mount point, e.g.
+/// 'collection:/dir/file.jpg' for '/mnt/card/dir/file.jpg'.
+pub fn from_fs_path(col_name: &str, col_root: &Path, fs_path: &Path) -> String {
+    let relative = fs_path.strip_prefix(col_root).unwrap_or(fs_path);
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    from_rel_path(col_name, &parts.join("/"))
+}
+
+/// `rel_path` is '/' separated, relative to the collection root.
+pub fn from_rel_path(col_name: &str, rel_path: &str) -> String {
+    format!("{col_name}{SEPARATOR}{rel_path}")
+}
+
+/// Splits 'collection:/dir/file.jpg' into collection name and relative path.
+pub fn parse(v_path: &str) -> Option<(&str, &str)> {
+    let (col_name, rel_path) = v_path.split_once(SEPARATOR)?;
+    if col_name.is_empty() {
+        None
+    } else {
+        Some((col_name, rel_path.trim_end_matches('/')))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn from_fs() {
+        let root = Path::new("/mnt/card");
+        assert_eq!(from_fs_path("card", root, root), "card:/");
+        assert_eq!(
+            from_fs_path("card", root, &root.join("dcim/a.jpg")),
+            "card:/dcim/a.jpg"
+        );
+    }
+
+    #[test]
+    fn parse_vpath() {
+        assert_eq!(parse("card:/dcim/a.jpg"), Some(("card", "dcim/a.jpg")));
+        assert_eq!(parse("card:/"), Some(("card", "")));
+        assert_eq!(parse("card:/dcim/"), Some(("card", "dcim")));
+        assert_eq!(parse(":/dcim"), None);
+        assert_eq!(parse("/mnt/card"), None);
+    }
+}
@@ -0,0 +1,432 @@
+/*
+    palica media catalogue program
+    Copyright (C) 2023 Yury Benesh
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::name::{QName, ResolveResult};
+use quick_xml::NsReader;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub const DC_NS: &[u8] = b"http://purl.org/dc/elements/1.1/";
+pub const LR_NS: &[u8] = b"http://ns.adobe.com/lightroom/1.0/";
+pub const RDF_NS: &[u8] = b"http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// Separates levels in lr:hierarchicalSubject values, e.g. 'places|france|nice'.
+pub const HIERARCHY_SEPARATOR: char = '|';
+
+#[derive(Error, Debug)]
+pub enum XmpError {
+    #[error("xml error: {error}")]
+    XmlError { error: quick_xml::Error },
+    #[error("io error: {error}")]
+    IoError { error: std::io::Error },
+}
+
+impl From<quick_xml::Error> for XmpError {
+    fn from(error: quick_xml::Error) -> Self {
+        XmpError::XmlError { error }
+    }
+}
+
+impl From<std::io::Error> for XmpError {
+    fn from(error: std::io::Error) -> Self {
+        XmpError::IoError { error }
+    }
+}
+
+pub type XmpResult<T> = Result<T, XmpError>;
+
+/// Skeleton for new sidecars.
+const EMPTY_XMP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="palica">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:lr="http://ns.adobe.com/lightroom/1.0/">
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+/// Subject tags as darktable/digikam store them, see design.adoc.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct XmpTags {
+    /// dc:subject bag
+    pub subject: Vec<String>,
+    /// lr:hierarchicalSubject bag, e.g. 'places|france|nice'
+    pub hierarchical_subject: Vec<String>,
+}
+
+impl XmpTags {
+    /// Hierarchical subjects and the subjects which are not a level of any
+    /// of them, darktable puts every level into dc:subject too.
+    pub fn tag_paths(&self) -> BTreeSet<String> {
+        let levels: HashSet<&str> = self
+            .hierarchical_subject
+            .iter()
+            .flat_map(|v| v.split(HIERARCHY_SEPARATOR))
+            .collect();
+        self.hierarchical_subject
+            .iter()
+            .chain(self.subject.iter().filter(|v| !levels.contains(v.as_str())))
+            .cloned()
+            .collect()
+    }
+
+    /// Bags for tag paths: all paths in lr:hierarchicalSubject and all their
+    /// levels in dc:subject.
+    pub fn from_tag_paths<'a>(paths: impl IntoIterator<Item = &'a String>) -> XmpTags {
+        let hierarchical_subject: BTreeSet<String> = paths.into_iter().cloned().collect();
+        let subject: BTreeSet<&str> = hierarchical_subject
+            .iter()
+            .flat_map(|v| v.split(HIERARCHY_SEPARATOR))
+            .collect();
+        XmpTags {
+            subject: subject.into_iter().map(str::to_owned).collect(),
+            hierarchical_subject: hierarchical_subject.into_iter().collect(),
+        }
+    }
+}
+
+/// Bags as read by vienik_rs::parse_xmp, from a sidecar or embedded XMP.
+impl From<vienik_rs::Metadata> for XmpTags {
+    fn from(meta: vienik_rs::Metadata) -> XmpTags {
+        XmpTags {
+            subject: meta.keywords.into_iter().collect(),
+            hierarchical_subject: meta.hierarchical_keywords.into_iter().collect(),
+        }
+    }
+}
+
+fn is_tag_bag(ns: &ResolveResult, local_name: &[u8]) -> bool {
+    match ns {
+        ResolveResult::Bound(ns) if ns.as_ref() == DC_NS => local_name == b"subject",
+        ResolveResult::Bound(ns) if ns.as_ref() == LR_NS => local_name == b"hierarchicalSubject",
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ElementKind {
+    TagBag,
+    Description,
+    Rdf,
+    Other,
+}
+
+fn element_kind(ns: &ResolveResult, local_name: &[u8]) -> ElementKind {
+    if is_tag_bag(ns, local_name) {
+        return ElementKind::TagBag;
+    }
+    match (ns, local_name) {
+        (ResolveResult::Bound(ns), b"Description") if ns.as_ref() == RDF_NS => {
+            ElementKind::Description
+        }
+        (ResolveResult::Bound(ns), b"RDF") if ns.as_ref() == RDF_NS => ElementKind::Rdf,
+        _ => ElementKind::Other,
+    }
+}
+
+/// Qualified name for the element and the namespace declaration if the
+/// conventional prefix is not bound to the namespace in the current scope.
+fn qualified_name(
+    reader: &NsReader<&[u8]>,
+    prefix: &str,
+    local_name: &str,
+    ns: &[u8],
+) -> (String, String) {
+    let name = format!("{prefix}:{local_name}");
+    let decl = match reader.resolve_element(QName(name.as_bytes())).0 {
+        ResolveResult::Bound(bound) if bound.as_ref() == ns => String::new(),
+        _ => format!(r#" xmlns:{prefix}="{}""#, String::from_utf8_lossy(ns)),
+    };
+    (name, decl)
+}
+
+/// Both bags as XML text, every line is prefixed with a new line and `indent`.
+fn tag_bags_xml(reader: &NsReader<&[u8]>, tags: &XmpTags, indent: &str) -> String {
+    let (bag_name, bag_decl) = qualified_name(reader, "rdf", "Bag", RDF_NS);
+    let (li_name, _) = qualified_name(reader, "rdf", "li", RDF_NS);
+    let li_name = if bag_decl.is_empty() {
+        li_name
+    } else {
+        "rdf:li".to_owned()
+    };
+    let mut res = String::new();
+    for (values, prefix, local_name, ns) in [
+        (&tags.subject, "dc", "subject", DC_NS),
+        (
+            &tags.hierarchical_subject,
+            "lr",
+            "hierarchicalSubject",
+            LR_NS,
+        ),
+    ] {
+        if values.is_empty() {
+            continue;
+        }
+        let (name, decl) = qualified_name(reader, prefix, local_name, ns);
+        res.push_str(&format!(
+            "\n{indent}<{name}{decl}>\n{indent} <{bag_name}{bag_decl}>"
+        ));
+        for value in values {
+            res.push_str(&format!(
+                "\n{indent}  <{li_name}>{}</{li_name}>",
+                escape(value)
+            ));
+        }
+        res.push_str(&format!("\n{indent} </{bag_name}>\n{indent}</{name}>"));
+    }
+    res
+}
+
+/// Replaces dc:subject and lr:hierarchicalSubject bags in XMP text, the rest
+/// of the text is kept as is. The bags are put into the first rdf:Description,
+/// empty bags are removed.
+pub fn update_tags(xml: &str, tags: &XmpTags) -> XmpResult<String> {
+    let mut reader = NsReader::from_str(xml);
+    let mut res = String::with_capacity(xml.len());
+    // xml[copied..] is not yet in res
+    let mut copied = 0;
+    let mut depth = 0usize;
+    // start of the whitespace right before the current event
+    let mut ws_start: Option<usize> = None;
+    // start of the bag being removed and its depth
+    let mut removing: Option<(usize, usize)> = None;
+    let mut written = false;
+
+    loop {
+        let start = reader.buffer_position();
+        let (ns, event) = reader.read_resolved_event()?;
+        let kind = match &event {
+            Event::Start(e) | Event::Empty(e) => element_kind(&ns, e.local_name().as_ref()),
+            Event::End(e) => element_kind(&ns, e.local_name().as_ref()),
+            _ => ElementKind::Other,
+        };
+        let end = reader.buffer_position();
+        let prev_ws_start = ws_start.take();
+        match event {
+            Event::Start(_) => {
+                depth += 1;
+                if removing.is_none() && kind == ElementKind::TagBag {
+                    removing = Some((prev_ws_start.unwrap_or(start), depth));
+                }
+            }
+            Event::Empty(e) if removing.is_none() => {
+                if kind == ElementKind::TagBag {
+                    res.push_str(&xml[copied..prev_ws_start.unwrap_or(start)]);
+                    copied = end;
+                } else if !written && kind == ElementKind::Description {
+                    let start_tag = xml[start..end].trim_end_matches("/>").trim_end();
+                    let indent = " ".repeat(depth);
+                    res.push_str(&xml[copied..start]);
+                    res.push_str(start_tag);
+                    res.push('>');
+                    res.push_str(&tag_bags_xml(&reader, tags, &" ".repeat(depth + 1)));
+                    res.push_str(&format!(
+                        "\n{indent}</{}>",
+                        String::from_utf8_lossy(e.name().as_ref())
+                    ));
+                    copied = end;
+                    written = true;
+                }
+            }
+            Event::End(_) => {
+                if let Some((remove_start, remove_depth)) = removing {
+                    if remove_depth == depth {
+                        res.push_str(&xml[copied..remove_start]);
+                        copied = end;
+                        removing = None;
+                    }
+                } else if !written && (kind == ElementKind::Description || kind == ElementKind::Rdf)
+                {
+                    let insert_at = prev_ws_start.unwrap_or(start);
+                    res.push_str(&xml[copied..insert_at]);
+                    if kind == ElementKind::Description {
+                        res.push_str(&tag_bags_xml(&reader, tags, &" ".repeat(depth)));
+                    } else {
+                        // no rdf:Description at all
+                        let indent = " ".repeat(depth);
+                        let (name, decl) = qualified_name(&reader, "rdf", "Description", RDF_NS);
+                        res.push_str(&format!("\n{indent}<{name}{decl} rdf:about=\"\">"));
+                        res.push_str(&tag_bags_xml(&reader, tags, &" ".repeat(depth + 1)));
+                        res.push_str(&format!("\n{indent}</{name}>"));
+                    }
+                    copied = insert_at;
+                    written = true;
+                }
+                depth = depth.saturating_sub(1);
+            }
+            Event::Text(e) if e.iter().all(|b| b.is_ascii_whitespace()) => {
+                ws_start = Some(prev_ws_start.unwrap_or(start));
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+
+    if !written {
+        // nothing to keep without rdf:RDF
+        return update_tags(EMPTY_XMP, tags);
+    }
+    res.push_str(&xml[copied..]);
+    Ok(res)
+}
+
+/// Creates the sidecar or updates tags in the existing one.
+pub fn write_sidecar_tags(path: &Path, tags: &XmpTags) -> XmpResult<()> {
+    let xml = if path.exists() {
+        std::fs::read_to_string(path)?
+    } else {
+        EMPTY_XMP.to_owned()
+    };
+    std::fs::write(path, update_tags(&xml, tags)?)?;
+    Ok(())
+}
+
+pub fn read_sidecar_tags(path: &Path) -> XmpResult<XmpTags> {
+    Ok(vienik_rs::parse_xmp(&std::fs::read(path)?)?.into())
+}
+
+/// darktable and digikam name sidecars as '<file name>.xmp', e.g. 'a.jpg.xmp'.
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(".xmp");
+    PathBuf::from(name)
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("xmp"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_tags(xml: &str) -> XmpResult<XmpTags> {
+        Ok(vienik_rs::parse_xmp(xml.as_bytes())?.into())
+    }
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="XMP Core 4.4.0-Exiv2">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:lr="http://ns.adobe.com/lightroom/1.0/"
+    xmlns:darktable="http://darktable.sf.net/">
+   <darktable:history>
+    <rdf:Seq>
+     <rdf:li darktable:operation="exposure"/>
+    </rdf:Seq>
+   </darktable:history>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>city</rdf:li>
+     <rdf:li>nice</rdf:li>
+     <rdf:li>rock &amp; roll</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <lr:hierarchicalSubject>
+    <rdf:Bag>
+     <rdf:li>city</rdf:li>
+     <rdf:li>places|france|nice</rdf:li>
+    </rdf:Bag>
+   </lr:hierarchicalSubject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    #[test]
+    fn read_sample() {
+        let tags = read_tags(SAMPLE).unwrap();
+        assert_eq!(tags.subject, vec!["city", "nice", "rock & roll"]);
+        assert_eq!(
+            tags.hierarchical_subject,
+            vec!["city", "places|france|nice"]
+        );
+        assert_eq!(
+            tags.tag_paths().into_iter().collect::<Vec<_>>(),
+            vec!["city", "places|france|nice", "rock & roll"]
+        );
+    }
+
+    #[test]
+    fn tag_paths_round_trip() {
+        let paths = vec!["city".to_owned(), "places|france|nice".to_owned()];
+        let tags = XmpTags::from_tag_paths(&paths);
+        assert_eq!(tags.subject, vec!["city", "france", "nice", "places"]);
+        assert_eq!(tags.hierarchical_subject, paths);
+        let read_back = read_tags(&update_tags(EMPTY_XMP, &tags).unwrap()).unwrap();
+        assert_eq!(read_back.tag_paths().into_iter().collect::<Vec<_>>(), paths);
+    }
+
+    #[test]
+    fn read_no_tags() {
+        let tags = read_tags(r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"/>"#).unwrap();
+        assert_eq!(tags, XmpTags::default());
+    }
+
+    #[test]
+    fn update_sample() {
+        let tags = XmpTags {
+            subject: vec!["sea".to_owned()],
+            hierarchical_subject: vec!["places|france|nice".to_owned()],
+        };
+        let updated = update_tags(SAMPLE, &tags).unwrap();
+        assert_eq!(read_tags(&updated).unwrap(), tags);
+        assert!(updated.contains(r#"<rdf:li darktable:operation="exposure"/>"#));
+        assert!(!updated.contains("rock &amp; roll"));
+        assert_eq!(updated.matches("<dc:subject>").count(), 1);
+
+        let cleared = update_tags(&updated, &XmpTags::default()).unwrap();
+        assert_eq!(read_tags(&cleared).unwrap(), XmpTags::default());
+        assert!(cleared.contains("darktable:history"));
+        assert!(!cleared.contains("hierarchicalSubject"));
+    }
+
+    #[test]
+    fn update_minimal() {
+        let tags = XmpTags {
+            subject: vec!["a < b".to_owned()],
+            hierarchical_subject: vec![],
+        };
+        for xml in [
+            EMPTY_XMP,
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="3"/></rdf:RDF></x:xmpmeta>"#,
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"></rdf:RDF></x:xmpmeta>"#,
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"/>"#,
+        ] {
+            let updated = update_tags(xml, &tags).unwrap();
+            assert_eq!(read_tags(&updated).unwrap(), tags, "{updated}");
+        }
+    }
+
+    #[test]
+    fn sidecar_names() {
+        assert_eq!(
+            sidecar_path(Path::new("/a/img.jpg")),
+            PathBuf::from("/a/img.jpg.xmp")
+        );
+        assert!(is_sidecar(Path::new("/a/img.jpg.XMP")));
+        assert!(!is_sidecar(Path::new("/a/img.jpg")));
+    }
+}
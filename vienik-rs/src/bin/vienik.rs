@@ -36,6 +36,8 @@ enum Command {
     Compare(CompareCommand),
     #[command(about = "Merges tags for files matching suffix groups.")]
     MergeTagsInGroups(MergeTagsInGroupsCommand),
+    #[command(about = "Prints EXIF and XMP metadata of the file and its sidecar as TOML.")]
+    Metadata(MetadataCommand),
 }
 
 #[derive(clap::Args, Debug)]
@@ -163,6 +165,16 @@ struct MergeTagsInGroupsCommand {
     pub toml_output: Option<String>,
 }
 
+#[derive(clap::Args, Debug)]
+struct MetadataCommand {
+    #[arg(help = "Photo or video file.")]
+    pub path: String,
+    #[arg(short = 's', help = "Ignore the XMP sidecar.", default_value_t = false)]
+    pub skip_sidecar: bool,
+    #[arg(short = 'o', help = "Toml output file name.")]
+    pub toml_output: Option<String>,
+}
+
 fn example(kind: ExampleKind) {
     match kind {
         ExampleKind::Filter => example_filter(),
@@ -200,6 +212,7 @@ fn main() -> anyhow::Result<()> {
         Command::Compare(_cmd) => eprintln!("not implemented"),
         // TODO
         Command::MergeTagsInGroups(_cmd) => eprintln!("not implemented"),
+        Command::Metadata(cmd) => metadata_command(cmd.path, cmd.skip_sidecar, cmd.toml_output)?,
     }
 
     Ok(())
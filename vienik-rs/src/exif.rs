@@ -0,0 +1,579 @@
+//! EXIF of JPEG and TIFF based raw files and `mvhd` of MP4/QuickTime
+//! files, read without external tools.
+use crate::metadata::{invalid_data, GpsPosition, Metadata};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const TAG_IMAGE_WIDTH: u16 = 0x0100;
+const TAG_IMAGE_LENGTH: u16 = 0x0101;
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_XMP: u16 = 0x02BC;
+const TAG_EXPOSURE_TIME: u16 = 0x829A;
+const TAG_F_NUMBER: u16 = 0x829D;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_ISO: u16 = 0x8827;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_FOCAL_LENGTH: u16 = 0x920A;
+const TAG_PIXEL_X_DIMENSION: u16 = 0xA002;
+const TAG_PIXEL_Y_DIMENSION: u16 = 0xA003;
+const TAG_LENS_MODEL: u16 = 0xA434;
+
+const TAG_GPS_LATITUDE_REF: u16 = 1;
+const TAG_GPS_LATITUDE: u16 = 2;
+const TAG_GPS_LONGITUDE_REF: u16 = 3;
+const TAG_GPS_LONGITUDE: u16 = 4;
+const TAG_GPS_ALTITUDE_REF: u16 = 5;
+const TAG_GPS_ALTITUDE: u16 = 6;
+
+const TYPE_BYTE: u16 = 1;
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+const TYPE_UNDEFINED: u16 = 7;
+const TYPE_SRATIONAL: u16 = 10;
+
+/// Protects from garbage offsets.
+const MAX_IFD_ENTRIES: u16 = 1024;
+const MAX_ASCII_LEN: u32 = 1024;
+const MAX_BLOCK_LEN: u32 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct IfdEntry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u32,
+    /// the value itself if it fits into 4 bytes, else the offset
+    pub value: [u8; 4],
+}
+
+/// TIFF structure, the EXIF block of JPEG or a whole TIFF based raw file.
+pub struct TiffReader<R> {
+    reader: R,
+    /// offset of the TIFF header in the stream
+    base: u64,
+    big_endian: bool,
+    first_ifd: u32,
+}
+
+impl<R: Read + Seek> TiffReader<R> {
+    /// None if there is no TIFF header at `base`.
+    pub fn new(mut reader: R, base: u64) -> std::io::Result<Option<TiffReader<R>>> {
+        let mut header = [0u8; 8];
+        reader.seek(SeekFrom::Start(base))?;
+        reader.read_exact(&mut header)?;
+        let big_endian = match &header[0..2] {
+            b"II" => false,
+            b"MM" => true,
+            _ => return Ok(None),
+        };
+        let magic = if big_endian {
+            u16::from_be_bytes([header[2], header[3]])
+        } else {
+            u16::from_le_bytes([header[2], header[3]])
+        };
+        // 42, Olympus ORF 'RO' and 'SR', Panasonic RW2 0x55
+        if !matches!(magic, 42 | 0x4F52 | 0x5352 | 0x55) {
+            return Ok(None);
+        }
+        let first = [header[4], header[5], header[6], header[7]];
+        Ok(Some(TiffReader {
+            reader,
+            base,
+            big_endian,
+            first_ifd: if big_endian {
+                u32::from_be_bytes(first)
+            } else {
+                u32::from_le_bytes(first)
+            },
+        }))
+    }
+
+    pub fn u16_from(&self, bytes: [u8; 2]) -> u16 {
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    pub fn u32_from(&self, bytes: [u8; 4]) -> u32 {
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    pub fn first_ifd(&self) -> u32 {
+        self.first_ifd
+    }
+
+    /// Entries of the IFD at `offset` from the TIFF header.
+    pub fn read_ifd(&mut self, offset: u32) -> std::io::Result<Vec<IfdEntry>> {
+        self.reader
+            .seek(SeekFrom::Start(self.base + u64::from(offset)))?;
+        let mut buf = [0u8; 12];
+        self.reader.read_exact(&mut buf[..2])?;
+        let count = self.u16_from([buf[0], buf[1]]).min(MAX_IFD_ENTRIES);
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            self.reader.read_exact(&mut buf)?;
+            entries.push(IfdEntry {
+                tag: self.u16_from([buf[0], buf[1]]),
+                field_type: self.u16_from([buf[2], buf[3]]),
+                count: self.u32_from([buf[4], buf[5], buf[6], buf[7]]),
+                value: [buf[8], buf[9], buf[10], buf[11]],
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Raw bytes of the value, `size` is the size of one item.
+    pub fn value_bytes(&mut self, entry: &IfdEntry, size: u32) -> std::io::Result<Vec<u8>> {
+        self.read_value(entry, entry.count.min(MAX_ASCII_LEN) * size)
+    }
+
+    fn read_value(&mut self, entry: &IfdEntry, len: u32) -> std::io::Result<Vec<u8>> {
+        if len <= 4 {
+            return Ok(entry.value[..len as usize].to_vec());
+        }
+        let offset = self.u32_from(entry.value);
+        self.reader
+            .seek(SeekFrom::Start(self.base + u64::from(offset)))?;
+        let mut data = vec![0u8; len as usize];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    pub fn ascii(&mut self, entry: &IfdEntry) -> std::io::Result<Option<String>> {
+        if entry.field_type != TYPE_ASCII {
+            return Ok(None);
+        }
+        let data = self.value_bytes(entry, 1)?;
+        let text = String::from_utf8_lossy(&data);
+        let text = text.trim_end_matches('\0').trim();
+        Ok(if text.is_empty() {
+            None
+        } else {
+            Some(text.to_owned())
+        })
+    }
+
+    /// SHORT or LONG value
+    pub fn uint(&self, entry: &IfdEntry) -> Option<u32> {
+        match entry.field_type {
+            TYPE_BYTE => Some(u32::from(entry.value[0])),
+            TYPE_SHORT => Some(u32::from(self.u16_from([entry.value[0], entry.value[1]]))),
+            TYPE_LONG => Some(self.u32_from(entry.value)),
+            _ => None,
+        }
+    }
+
+    /// RATIONAL or SRATIONAL values, empty for other types.
+    pub fn rationals(&mut self, entry: &IfdEntry) -> std::io::Result<Vec<f64>> {
+        let signed = match entry.field_type {
+            TYPE_RATIONAL => false,
+            TYPE_SRATIONAL => true,
+            _ => return Ok(Vec::new()),
+        };
+        let data = self.value_bytes(entry, 8)?;
+        Ok(data
+            .chunks_exact(8)
+            .filter_map(|pair| {
+                let numerator = self.u32_from([pair[0], pair[1], pair[2], pair[3]]);
+                let denominator = self.u32_from([pair[4], pair[5], pair[6], pair[7]]);
+                if denominator == 0 {
+                    None
+                } else if signed {
+                    Some(f64::from(numerator as i32) / f64::from(denominator as i32))
+                } else {
+                    Some(f64::from(numerator) / f64::from(denominator))
+                }
+            })
+            .collect())
+    }
+
+    /// BYTE or UNDEFINED block, e.g. the XMP packet.
+    pub fn block(&mut self, entry: &IfdEntry) -> std::io::Result<Option<Vec<u8>>> {
+        if !matches!(entry.field_type, TYPE_BYTE | TYPE_UNDEFINED) || entry.count > MAX_BLOCK_LEN {
+            return Ok(None);
+        }
+        self.read_value(entry, entry.count).map(Some)
+    }
+
+    fn ascii_tag(&mut self, entries: &[IfdEntry], tag: u16) -> std::io::Result<Option<String>> {
+        match find_entry(entries, tag) {
+            Some(entry) => self.ascii(entry),
+            None => Ok(None),
+        }
+    }
+
+    fn uint_tag(&self, entries: &[IfdEntry], tag: u16) -> Option<u32> {
+        find_entry(entries, tag).and_then(|e| self.uint(e))
+    }
+
+    /// The first rational of the tag.
+    fn rational_tag(&mut self, entries: &[IfdEntry], tag: u16) -> std::io::Result<Option<f64>> {
+        match find_entry(entries, tag) {
+            Some(entry) => Ok(self.rationals(entry)?.first().copied()),
+            None => Ok(None),
+        }
+    }
+}
+
+pub fn find_entry(entries: &[IfdEntry], tag: u16) -> Option<&IfdEntry> {
+    entries.iter().find(|e| e.tag == tag)
+}
+
+/// 'YYYY:MM:DD HH:MM:SS'
+pub fn parse_exif_date_time(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, "%Y:%m:%d %H:%M:%S").ok()
+}
+
+/// '+02:00' -> seconds east of UTC, None beyond the real offsets of
+/// up to 14 hours.
+pub fn parse_exif_offset(text: &str) -> Option<i64> {
+    let sign = match text.get(0..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = text[1..].split_once(':')?;
+    let hours = hours.parse::<u32>().ok()?;
+    let minutes = minutes.parse::<u32>().ok()?;
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    Some(sign * (i64::from(hours) * 3600 + i64::from(minutes) * 60))
+}
+
+/// 'N'/'E' positive, 'S'/'W' negative.
+pub(crate) fn gps_coordinate(dms: &[f64], reference: Option<&str>) -> Option<f64> {
+    let degrees =
+        dms.first()? + dms.get(1).unwrap_or(&0.0) / 60.0 + dms.get(2).unwrap_or(&0.0) / 3600.0;
+    match reference {
+        Some("S") | Some("W") => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+fn gps_position<R: Read + Seek>(
+    tiff: &mut TiffReader<R>,
+    gps: &[IfdEntry],
+) -> std::io::Result<Option<GpsPosition>> {
+    let mut coordinate = |value_tag: u16, ref_tag: u16| -> std::io::Result<Option<f64>> {
+        let Some(entry) = find_entry(gps, value_tag) else {
+            return Ok(None);
+        };
+        let dms = tiff.rationals(entry)?;
+        let reference = tiff.ascii_tag(gps, ref_tag)?;
+        Ok(gps_coordinate(&dms, reference.as_deref()))
+    };
+    let latitude = coordinate(TAG_GPS_LATITUDE, TAG_GPS_LATITUDE_REF)?;
+    let longitude = coordinate(TAG_GPS_LONGITUDE, TAG_GPS_LONGITUDE_REF)?;
+    let (Some(latitude), Some(longitude)) = (latitude, longitude) else {
+        return Ok(None);
+    };
+    let altitude = tiff.rational_tag(gps, TAG_GPS_ALTITUDE)?.map(|altitude| {
+        // 1 is below the sea level
+        match tiff.uint_tag(gps, TAG_GPS_ALTITUDE_REF) {
+            Some(1) => -altitude,
+            _ => altitude,
+        }
+    });
+    Ok(Some(GpsPosition {
+        latitude,
+        longitude,
+        altitude,
+    }))
+}
+
+/// Metadata of IFD0 and its EXIF and GPS IFDs, and the XMP packet of
+/// TIFF files if any.
+pub(crate) fn tiff_metadata<R: Read + Seek>(
+    tiff: &mut TiffReader<R>,
+) -> std::io::Result<(Metadata, Option<Vec<u8>>)> {
+    let ifd0 = tiff.read_ifd(tiff.first_ifd())?;
+    let date_time = tiff
+        .ascii_tag(&ifd0, TAG_DATE_TIME)?
+        .as_deref()
+        .and_then(parse_exif_date_time);
+    let mut meta = Metadata {
+        camera_make: tiff.ascii_tag(&ifd0, TAG_MAKE)?,
+        camera_model: tiff.ascii_tag(&ifd0, TAG_MODEL)?,
+        // of the main image in TIFF files, often of a preview in raw ones
+        width: tiff.uint_tag(&ifd0, TAG_IMAGE_WIDTH),
+        height: tiff.uint_tag(&ifd0, TAG_IMAGE_LENGTH),
+        orientation: tiff
+            .uint_tag(&ifd0, TAG_ORIENTATION)
+            .and_then(|o| u16::try_from(o).ok()),
+        date_taken: date_time,
+        date_modified: date_time,
+        ..Default::default()
+    };
+    let xmp = match find_entry(&ifd0, TAG_XMP) {
+        Some(entry) => tiff.block(entry)?,
+        None => None,
+    };
+
+    if let Some(exif_offset) = find_entry(&ifd0, TAG_EXIF_IFD).and_then(|e| tiff.uint(e)) {
+        let exif = tiff.read_ifd(exif_offset)?;
+        let original = tiff
+            .ascii_tag(&exif, TAG_DATE_TIME_ORIGINAL)?
+            .as_deref()
+            .and_then(parse_exif_date_time);
+        if let Some(original) = original {
+            let offset = tiff
+                .ascii_tag(&exif, TAG_OFFSET_TIME_ORIGINAL)?
+                .as_deref()
+                .and_then(parse_exif_offset);
+            // the camera clock as is if out of range
+            meta.date_taken = Some(
+                original
+                    .checked_sub_signed(Duration::seconds(offset.unwrap_or(0)))
+                    .unwrap_or(original),
+            );
+        }
+        meta.lens = tiff.ascii_tag(&exif, TAG_LENS_MODEL)?;
+        meta.focal_length = tiff.rational_tag(&exif, TAG_FOCAL_LENGTH)?;
+        meta.exposure_time = tiff.rational_tag(&exif, TAG_EXPOSURE_TIME)?;
+        meta.f_number = tiff.rational_tag(&exif, TAG_F_NUMBER)?;
+        meta.iso = tiff.uint_tag(&exif, TAG_ISO);
+        if let (Some(width), Some(height)) = (
+            tiff.uint_tag(&exif, TAG_PIXEL_X_DIMENSION),
+            tiff.uint_tag(&exif, TAG_PIXEL_Y_DIMENSION),
+        ) {
+            meta.width = Some(width);
+            meta.height = Some(height);
+        }
+    }
+    if let Some(gps_offset) = find_entry(&ifd0, TAG_GPS_IFD).and_then(|e| tiff.uint(e)) {
+        let gps = tiff.read_ifd(gps_offset)?;
+        meta.gps = gps_position(tiff, &gps)?;
+    }
+    Ok((meta, xmp))
+}
+
+/// Size of the box content and its type, None at the end.
+pub(crate) fn read_box_header<R: Read + Seek>(
+    reader: &mut R,
+) -> std::io::Result<Option<(u64, [u8; 4])>> {
+    let mut header = [0u8; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let box_type = [header[4], header[5], header[6], header[7]];
+    let size = u64::from(u32::from_be_bytes([
+        header[0], header[1], header[2], header[3],
+    ]));
+    let content_size = match size {
+        // up to the end of the file
+        0 => {
+            let pos = reader.stream_position()?;
+            reader.seek(SeekFrom::End(0))? - pos
+        }
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            u64::from_be_bytes(large).saturating_sub(16)
+        }
+        _ => size.saturating_sub(8),
+    };
+    Ok(Some((content_size, box_type)))
+}
+
+/// Seeks over `size` bytes of a box, which must end before `end`, so
+/// that garbage sizes can't move backwards.
+pub(crate) fn skip_box<R: Seek>(reader: &mut R, size: u64, end: u64) -> std::io::Result<()> {
+    let pos = reader.stream_position()?;
+    let offset = match i64::try_from(size) {
+        Ok(offset) if pos.checked_add(size).is_some_and(|box_end| box_end <= end) => offset,
+        _ => return Err(invalid_data(format!("box of {size} bytes at {pos}"))),
+    };
+    reader.seek(SeekFrom::Current(offset))?;
+    Ok(())
+}
+
+/// Skips boxes until `box_type` and returns its content size.
+pub(crate) fn find_box<R: Read + Seek>(
+    reader: &mut R,
+    box_type: &[u8; 4],
+    end: u64,
+) -> std::io::Result<Option<u64>> {
+    while reader.stream_position()? < end {
+        let Some((size, found)) = read_box_header(reader)? else {
+            return Ok(None);
+        };
+        if &found == box_type {
+            return Ok(Some(size));
+        }
+        skip_box(reader, size, end)?;
+    }
+    Ok(None)
+}
+
+/// Seconds from 1904-01-01 UTC
+fn quicktime_time(seconds: u64) -> Option<NaiveDateTime> {
+    if seconds == 0 {
+        return None;
+    }
+    NaiveDate::from_ymd_opt(1904, 1, 1)?
+        .and_hms_opt(0, 0, 0)?
+        .checked_add_signed(Duration::seconds(i64::try_from(seconds).ok()?))
+}
+
+/// Creation time of the movie header, UTC.
+pub(crate) fn bmff_capture_time<R: Read + Seek>(
+    reader: &mut R,
+) -> std::io::Result<Option<NaiveDateTime>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let Some(moov_size) = find_box(reader, b"moov", end)? else {
+        return Ok(None);
+    };
+    let moov_end = reader.stream_position()?.saturating_add(moov_size);
+    if find_box(reader, b"mvhd", moov_end)?.is_none() {
+        return Ok(None);
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let seconds = if version[0] == 1 {
+        let mut time = [0u8; 8];
+        reader.read_exact(&mut time)?;
+        u64::from_be_bytes(time)
+    } else {
+        let mut time = [0u8; 4];
+        reader.read_exact(&mut time)?;
+        u64::from(u32::from_be_bytes(time))
+    };
+    Ok(quicktime_time(seconds))
+}
+
+/// EXIF DateTimeOriginal (or DateTime) converted to UTC by
+/// OffsetTimeOriginal if present, else the camera clock as is;
+/// or the creation time of MP4/QuickTime movies, UTC.
+/// XMP dates are used if there is no EXIF, see read_metadata_from.
+pub fn capture_time_from<R: Read + Seek>(reader: &mut R) -> std::io::Result<Option<NaiveDateTime>> {
+    Ok(crate::read_metadata_from(reader)?.date_taken)
+}
+
+pub fn capture_time(path: &Path) -> std::io::Result<Option<NaiveDateTime>> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    capture_time_from(&mut file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn date_time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    /// little endian TIFF with IFD0 DateTime and an EXIF IFD
+    fn tiff(date_time: &str, original: &str, offset: &str) -> Vec<u8> {
+        let entry = |tag: u16, field_type: u16, count: u32, value: u32| -> Vec<u8> {
+            let mut e = tag.to_le_bytes().to_vec();
+            e.extend(field_type.to_le_bytes());
+            e.extend(count.to_le_bytes());
+            e.extend(value.to_le_bytes());
+            e
+        };
+        let mut data = b"II\x2a\0\x08\0\0\0".to_vec();
+        // IFD0 at 8 and EXIF IFD at 38, 2 entries each, values from 68
+        data.extend(2u16.to_le_bytes());
+        data.extend(entry(TAG_DATE_TIME, TYPE_ASCII, 20, 68));
+        data.extend(entry(TAG_EXIF_IFD, TYPE_LONG, 1, 38));
+        data.extend(0u32.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+        data.extend(entry(TAG_DATE_TIME_ORIGINAL, TYPE_ASCII, 20, 88));
+        let offset = format!("{offset:<6}\0");
+        data.extend(entry(
+            TAG_OFFSET_TIME_ORIGINAL,
+            TYPE_ASCII,
+            offset.len() as u32,
+            108,
+        ));
+        data.extend(0u32.to_le_bytes());
+        assert_eq!(data.len(), 68);
+        for text in [date_time, original] {
+            data.extend(format!("{text:<19}\0").as_bytes());
+        }
+        data.extend(offset.as_bytes());
+        data
+    }
+
+    #[test]
+    fn jpeg_exif() {
+        let capture = capture_time(Path::new("../sample-data/exif/img2.jpg")).unwrap();
+        assert_eq!(capture, Some(date_time("2023-05-03 12:30:00")));
+        assert_eq!(
+            capture_time(Path::new("../sample-data/img1.jpg")).unwrap(),
+            None
+        );
+        assert_eq!(
+            capture_time(Path::new("../sample-data/img1.webp")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn tiff_exif() {
+        let data = tiff("2023:01:02 10:00:00", "2023:01:01 10:00:00", "-01:30");
+        let capture = capture_time_from(&mut Cursor::new(data)).unwrap();
+        assert_eq!(capture, Some(date_time("2023-01-01 11:30:00")));
+        let data = tiff("2023:01:02 10:00:00", "0000:00:00 00:00:00", "+00:");
+        let capture = capture_time_from(&mut Cursor::new(data)).unwrap();
+        assert_eq!(capture, Some(date_time("2023-01-02 10:00:00")));
+        assert_eq!(parse_exif_offset("-01:30"), Some(-5400));
+        assert_eq!(parse_exif_offset("01:30"), None);
+        // not a real offset, used to overflow the date
+        let data = tiff(
+            "2023:01:02 10:00:00",
+            "2023:01:01 10:00:00",
+            "+9999999999:00",
+        );
+        let capture = capture_time_from(&mut Cursor::new(data)).unwrap();
+        assert_eq!(capture, Some(date_time("2023-01-01 10:00:00")));
+        assert_eq!(parse_exif_offset("+14:00"), Some(50400));
+        assert_eq!(parse_exif_offset("+15:00"), None);
+        assert_eq!(parse_exif_offset("-01:60"), None);
+    }
+
+    #[test]
+    fn movie_header() {
+        let mut data = b"\0\0\0\x10ftypqt  \0\0\0\0".to_vec();
+        data.extend(b"\0\0\0\x10free\0\0\0\0\0\0\0\0");
+        data.extend(b"\0\0\0\x1cmoov\0\0\0\x14mvhd\0\0\0\0");
+        // 2023-01-01 00:00:00 UTC
+        data.extend(3755376000u32.to_be_bytes());
+        data.extend(3755376000u32.to_be_bytes());
+        let capture = capture_time_from(&mut Cursor::new(data)).unwrap();
+        assert_eq!(capture, Some(date_time("2023-01-01 00:00:00")));
+        let no_moov = b"\0\0\0\x10ftypisom\0\0\0\0".to_vec();
+        assert_eq!(capture_time_from(&mut Cursor::new(no_moov)).unwrap(), None);
+        // a largesize that doesn't fit into i64 used to seek backwards forever
+        let mut data = b"\0\0\0\x10ftypqt  \0\0\0\0".to_vec();
+        data.extend(b"\0\0\0\x01free");
+        data.extend((u64::MAX - 7).to_be_bytes());
+        data.extend(b"\0\0\0\x08moov");
+        let error = capture_time_from(&mut Cursor::new(data)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        // past the end of the file
+        let mut data = b"\0\0\0\x10ftypqt  \0\0\0\0".to_vec();
+        data.extend(b"\0\0\0\x20free\0\0\0\0");
+        let error = capture_time_from(&mut Cursor::new(data)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
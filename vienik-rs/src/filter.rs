@@ -133,6 +133,7 @@ mod tests {
             path: String::from("abc"),
             tags: Option::None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -153,6 +154,7 @@ mod tests {
             path: String::from("abc"),
             tags: Option::None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -173,6 +175,7 @@ mod tests {
             path: String::from("abc"),
             tags: Option::None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -193,6 +196,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -213,6 +217,7 @@ mod tests {
             path: String::from("abc"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -233,6 +238,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -255,6 +261,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -277,6 +284,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -299,6 +307,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: Some(chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap()),
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -321,6 +330,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: Some(chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap()),
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -343,6 +353,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: Some(chrono::NaiveDate::from_ymd_opt(1990, 1, 1).unwrap()),
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -365,6 +376,7 @@ mod tests {
             path: String::from("abc"),
             tags: Some(HashSet::from(["tag1".to_owned(), "other".to_owned()])),
             mod_date: Some(chrono::NaiveDate::from_ymd_opt(1991, 1, 1).unwrap()),
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -386,6 +398,7 @@ mod tests {
             path: String::from("/mntabc"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -407,6 +420,7 @@ mod tests {
             path: String::from("/mntabc"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -428,6 +442,7 @@ mod tests {
             path: String::from("/mntabc"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -449,6 +464,7 @@ mod tests {
             path: String::from("fullstring"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -470,6 +486,7 @@ mod tests {
             path: String::from("fullstring"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -480,6 +497,7 @@ mod tests {
             path: String::from("abc"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -490,6 +508,7 @@ mod tests {
             path: String::from("zxf"),
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         };
         assert_eq!(
@@ -1,16 +1,22 @@
 mod example;
+mod exif;
 mod filter;
+mod metadata;
 mod schema;
 mod sorting;
+mod xmp;
 use std::collections::{HashMap, HashSet};
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 
 use chrono::Local;
 pub use example::*;
+pub use exif::*;
 pub use filter::*;
+pub use metadata::*;
 pub use schema::*;
 pub use sorting::*;
+pub use xmp::*;
 
 pub fn ls_command(path: Option<String>) -> anyhow::Result<()> {
     use std::process::Command;
@@ -84,6 +90,14 @@ fn file_mod_date(path: &str) -> Option<chrono::NaiveDate> {
     }
 }
 
+/// Capture date of photos and videos, UTC or the camera clock, see capture_time.
+fn file_taken_date(path: &str) -> Option<chrono::NaiveDate> {
+    match capture_time(std::path::Path::new(path)) {
+        Ok(taken) => taken.map(|t| t.date()),
+        _ => None,
+    }
+}
+
 fn file_size(path: &str) -> Option<u64> {
     let meta = std::path::Path::new(path).metadata();
     match meta {
@@ -103,12 +117,14 @@ pub fn plain_to_rich_command(
         .into_iter()
         .map(|line| {
             let date = file_mod_date(&line);
+            let taken_date = file_taken_date(&line);
             let size = file_size(&line);
 
             FileListItem {
                 path: line,
                 tags: None,
                 mod_date: date,
+                taken_date,
                 size: size,
             }
         })
@@ -305,6 +321,22 @@ pub fn merge_command(
     Ok(())
 }
 
+pub fn metadata_command(
+    path: String,
+    skip_sidecar: bool,
+    toml_output: Option<String>,
+) -> anyhow::Result<()> {
+    let path = PathBuf::from(&path);
+    let meta = if skip_sidecar {
+        read_metadata(&path)?
+    } else {
+        read_metadata_with_sidecar(&path)?
+    };
+    let serialized = toml::to_string(&meta)?;
+    string_to_file_or_stdout(&serialized, toml_output)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,418 @@
+//! Typed metadata of photos and videos: EXIF of JPEG and TIFF based raw
+//! files, XMP embedded into JPEG, TIFF, WebP and JPEG XL files, and XMP
+//! sidecars.
+use crate::exif::{bmff_capture_time, read_box_header, skip_box, tiff_metadata, TiffReader};
+use crate::xmp::xmp_metadata;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const JPEG_EXIF_ID: &[u8] = b"Exif\0\0";
+const JPEG_XMP_ID: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const JXL_CONTAINER: &[u8] = &[
+    0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A,
+];
+
+/// Protects from garbage sizes of WebP chunks and JPEG XL boxes.
+const MAX_BLOCK_LEN: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GpsPosition {
+    /// degrees, negative to the south
+    pub latitude: f64,
+    /// degrees, negative to the west
+    pub longitude: f64,
+    /// meters, negative below the sea level
+    pub altitude: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Metadata {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    /// millimeters
+    pub focal_length: Option<f64>,
+    /// seconds
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// pixels, as stored, see orientation
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// UTC if the time zone is known, else the camera clock as is
+    pub date_taken: Option<NaiveDateTime>,
+    pub date_modified: Option<NaiveDateTime>,
+    /// EXIF orientation, 1 is upright
+    pub orientation: Option<u16>,
+    /// dc:subject
+    pub keywords: BTreeSet<String>,
+    /// lr:hierarchicalSubject, e.g. 'places|france|nice'
+    pub hierarchical_keywords: BTreeSet<String>,
+    pub gps: Option<GpsPosition>,
+}
+
+fn replace_known<T>(value: &mut Option<T>, other: Option<T>) {
+    if other.is_some() {
+        *value = other;
+    }
+}
+
+impl Metadata {
+    /// Known values of `other` replace these ones, keywords are united.
+    pub fn merge(&mut self, other: Metadata) {
+        replace_known(&mut self.camera_make, other.camera_make);
+        replace_known(&mut self.camera_model, other.camera_model);
+        replace_known(&mut self.lens, other.lens);
+        replace_known(&mut self.focal_length, other.focal_length);
+        replace_known(&mut self.exposure_time, other.exposure_time);
+        replace_known(&mut self.f_number, other.f_number);
+        replace_known(&mut self.iso, other.iso);
+        replace_known(&mut self.width, other.width);
+        replace_known(&mut self.height, other.height);
+        replace_known(&mut self.date_taken, other.date_taken);
+        replace_known(&mut self.date_modified, other.date_modified);
+        replace_known(&mut self.orientation, other.orientation);
+        replace_known(&mut self.gps, other.gps);
+        self.keywords.extend(other.keywords);
+        self.hierarchical_keywords
+            .extend(other.hierarchical_keywords);
+    }
+}
+
+pub(crate) fn invalid_data<E>(error: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+fn read_block<R: Read>(reader: &mut R, size: u64) -> std::io::Result<Vec<u8>> {
+    if size > MAX_BLOCK_LEN {
+        return Err(invalid_data(format!("metadata block of {size} bytes")));
+    }
+    let mut data = vec![0u8; size as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Parses an XMP packet, embedded or a whole sidecar file.
+pub fn parse_xmp(data: &[u8]) -> std::io::Result<Metadata> {
+    let text = String::from_utf8_lossy(data);
+    xmp_metadata(text.trim_end_matches('\0')).map_err(invalid_data)
+}
+
+/// EXIF of the TIFF structure at `base` and its XMP packet.
+fn exif_metadata<R: Read + Seek>(
+    reader: &mut R,
+    base: u64,
+) -> std::io::Result<(Metadata, Option<Vec<u8>>)> {
+    match TiffReader::new(reader, base)? {
+        Some(mut tiff) => tiff_metadata(&mut tiff),
+        None => Ok((Metadata::default(), None)),
+    }
+}
+
+/// EXIF and XMP of the APP1 segments.
+fn jpeg_metadata<R: Read + Seek>(reader: &mut R) -> std::io::Result<(Metadata, Option<Vec<u8>>)> {
+    let mut exif_offset = None;
+    let mut xmp = None;
+    reader.seek(SeekFrom::Start(2))?;
+    let mut marker = [0u8; 2];
+    let mut len = [0u8; 2];
+    loop {
+        reader.read_exact(&mut marker)?;
+        // start of scan, no more metadata
+        if marker[0] != 0xFF || marker[1] == 0xDA || marker[1] == 0xD9 {
+            break;
+        }
+        reader.read_exact(&mut len)?;
+        let len = u64::from(u16::from_be_bytes(len));
+        if len < 2 {
+            break;
+        }
+        if marker[1] != 0xE1 {
+            reader.seek(SeekFrom::Current(len as i64 - 2))?;
+            continue;
+        }
+        let start = reader.stream_position()?;
+        let segment = read_block(reader, len - 2)?;
+        if segment.starts_with(JPEG_EXIF_ID) && exif_offset.is_none() {
+            exif_offset = Some(start + JPEG_EXIF_ID.len() as u64);
+        } else if segment.starts_with(JPEG_XMP_ID) && xmp.is_none() {
+            xmp = Some(segment[JPEG_XMP_ID.len()..].to_vec());
+        }
+    }
+    let (meta, tiff_xmp) = match exif_offset {
+        Some(offset) => exif_metadata(reader, offset)?,
+        None => (Metadata::default(), None),
+    };
+    Ok((meta, xmp.or(tiff_xmp)))
+}
+
+/// EXIF data of WebP and JPEG XL, with or without the JPEG 'Exif' id.
+fn exif_block_metadata(data: Vec<u8>) -> std::io::Result<(Metadata, Option<Vec<u8>>)> {
+    let base = if data.starts_with(JPEG_EXIF_ID) {
+        JPEG_EXIF_ID.len() as u64
+    } else {
+        0
+    };
+    exif_metadata(&mut Cursor::new(data), base)
+}
+
+/// 'EXIF' and 'XMP ' chunks of the RIFF container.
+fn webp_metadata<R: Read + Seek>(reader: &mut R) -> std::io::Result<(Metadata, Option<Vec<u8>>)> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(12))?;
+    let mut meta = Metadata::default();
+    let mut xmp = None;
+    let mut header = [0u8; 8];
+    while reader.stream_position()? + 8 <= end {
+        reader.read_exact(&mut header)?;
+        let size = u64::from(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ]));
+        match &header[0..4] {
+            b"EXIF" => meta = exif_block_metadata(read_block(reader, size)?)?.0,
+            b"XMP " => xmp = Some(read_block(reader, size)?),
+            _ => skip_box(reader, size, end)?,
+        }
+        // chunks are padded to even sizes
+        if size % 2 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+    }
+    Ok((meta, xmp))
+}
+
+/// 'Exif' and 'xml ' boxes of the JPEG XL container, brotli compressed
+/// 'brob' boxes are skipped.
+fn jxl_metadata<R: Read + Seek>(reader: &mut R) -> std::io::Result<(Metadata, Option<Vec<u8>>)> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(JXL_CONTAINER.len() as u64))?;
+    let mut meta = Metadata::default();
+    let mut xmp = None;
+    while reader.stream_position()? < end {
+        let Some((size, box_type)) = read_box_header(reader)? else {
+            break;
+        };
+        match &box_type {
+            b"Exif" => {
+                let data = read_block(reader, size)?;
+                // offset of the TIFF header after the offset field itself
+                if data.len() >= 4 {
+                    let offset = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                    let tiff = data.get(4 + offset..).unwrap_or_default().to_vec();
+                    meta = exif_block_metadata(tiff)?.0;
+                }
+            }
+            b"xml " => xmp = Some(read_block(reader, size)?),
+            _ => skip_box(reader, size, end)?,
+        }
+    }
+    Ok((meta, xmp))
+}
+
+/// Metadata of the file itself, EXIF values win over the embedded XMP.
+/// Unknown formats have no metadata.
+pub fn read_metadata_from<R: Read + Seek>(reader: &mut R) -> std::io::Result<Metadata> {
+    let mut magic = [0u8; 12];
+    reader.seek(SeekFrom::Start(0))?;
+    let read = reader.read(&mut magic)?;
+    let magic = &magic[..read];
+    let (exif, xmp) = if magic.starts_with(&[0xFF, 0xD8]) {
+        jpeg_metadata(reader)?
+    } else if magic.starts_with(b"II") || magic.starts_with(b"MM") {
+        exif_metadata(reader, 0)?
+    } else if magic.starts_with(b"RIFF") && magic.get(8..12) == Some(b"WEBP") {
+        webp_metadata(reader)?
+    } else if magic == JXL_CONTAINER {
+        jxl_metadata(reader)?
+    } else if magic.get(4..8) == Some(b"ftyp") {
+        let meta = Metadata {
+            date_taken: bmff_capture_time(reader)?,
+            ..Default::default()
+        };
+        (meta, None)
+    } else {
+        (Metadata::default(), None)
+    };
+    let mut meta = match xmp {
+        Some(xmp) => parse_xmp(&xmp)?,
+        None => Metadata::default(),
+    };
+    meta.merge(exif);
+    Ok(meta)
+}
+
+pub fn read_metadata(path: &Path) -> std::io::Result<Metadata> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    read_metadata_from(&mut file)
+}
+
+/// Existing sidecar, either 'a.jpg.xmp' or 'a.xmp'.
+pub fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let mut full_name = path.as_os_str().to_owned();
+    full_name.push(".xmp");
+    let full_name = PathBuf::from(full_name);
+    if full_name.is_file() {
+        return Some(full_name);
+    }
+    let stem_name = path.with_extension("xmp");
+    if stem_name != path && stem_name.is_file() {
+        return Some(stem_name);
+    }
+    None
+}
+
+/// Metadata of the file merged with its sidecar, the sidecar values win
+/// as editors write them there.
+pub fn read_metadata_with_sidecar(path: &Path) -> std::io::Result<Metadata> {
+    let mut meta = read_metadata(path)?;
+    if let Some(sidecar) = find_sidecar(path) {
+        meta.merge(parse_xmp(&std::fs::read(sidecar)?)?);
+    }
+    Ok(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:lr="http://ns.adobe.com/lightroom/1.0/"
+    xmlns:exifEX="http://cipa.jp/exif/1.0/"
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    exifEX:LensModel="Sidecar Lens"
+    exif:DateTimeOriginal="2023-05-03T14:31:00+02:00">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>nice</rdf:li>
+     <rdf:li>sea</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <lr:hierarchicalSubject>
+    <rdf:Bag>
+     <rdf:li>places|france|nice</rdf:li>
+    </rdf:Bag>
+   </lr:hierarchicalSubject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    fn date_time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn jpeg_metadata() {
+        let meta = read_metadata(Path::new("../sample-data/exif/img2.jpg")).unwrap();
+        assert_eq!(meta.camera_make.as_deref(), Some("OLYMPUS CORPORATION"));
+        assert_eq!(meta.camera_model.as_deref(), Some("E-M5MarkII"));
+        assert_eq!(meta.lens.as_deref(), Some("OLYMPUS M.12-40mm F2.8"));
+        assert_eq!(meta.orientation, Some(6));
+        assert_eq!(meta.focal_length, Some(25.0));
+        assert_eq!(meta.exposure_time, Some(0.004));
+        assert_eq!(meta.f_number, Some(5.6));
+        assert_eq!(meta.iso, Some(200));
+        assert_eq!((meta.width, meta.height), (Some(64), Some(48)));
+        assert_eq!(meta.date_taken, Some(date_time("2023-05-03 12:30:00")));
+        assert_eq!(meta.date_modified, Some(date_time("2023-05-04 09:00:00")));
+        let gps = meta.gps.unwrap();
+        assert!((gps.latitude - 43.705).abs() < 1e-9);
+        assert!((gps.longitude - 7.26).abs() < 1e-9);
+        assert_eq!(gps.altitude, None);
+
+        let plain = read_metadata(Path::new("../sample-data/img1.jpg")).unwrap();
+        assert_eq!(plain, Metadata::default());
+    }
+
+    #[test]
+    fn embedded_xmp() {
+        // JPEG with an XMP APP1 segment only
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        let len = 2 + JPEG_XMP_ID.len() + XMP.len();
+        jpeg.extend((len as u16).to_be_bytes());
+        jpeg.extend(JPEG_XMP_ID);
+        jpeg.extend(XMP.as_bytes());
+        jpeg.extend([0xFF, 0xDA, 0, 2]);
+        let meta = read_metadata_from(&mut Cursor::new(jpeg)).unwrap();
+        assert_eq!(meta.lens.as_deref(), Some("Sidecar Lens"));
+        assert_eq!(meta.date_taken, Some(date_time("2023-05-03 12:31:00")));
+        assert_eq!(
+            meta.keywords,
+            BTreeSet::from(["nice".to_owned(), "sea".to_owned()])
+        );
+
+        // WebP with an odd sized XMP chunk
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(b"VP8X");
+        webp.extend(10u32.to_le_bytes());
+        webp.extend([0u8; 10]);
+        let xmp = format!("{XMP} ");
+        webp.extend(b"XMP ");
+        webp.extend((xmp.len() as u32).to_le_bytes());
+        webp.extend(xmp.as_bytes());
+        if xmp.len() % 2 == 1 {
+            webp.push(0);
+        }
+        let meta = read_metadata_from(&mut Cursor::new(webp)).unwrap();
+        assert_eq!(
+            meta.hierarchical_keywords,
+            BTreeSet::from(["places|france|nice".to_owned()])
+        );
+
+        // JPEG XL container with the EXIF of img2.jpg and XMP
+        let img2 = std::fs::read("../sample-data/exif/img2.jpg").unwrap();
+        let exif_start = img2.windows(6).position(|w| w == JPEG_EXIF_ID).unwrap() + 6;
+        let exif_len =
+            u16::from_be_bytes([img2[exif_start - 8], img2[exif_start - 7]]) as usize - 8;
+        let mut jxl = JXL_CONTAINER.to_vec();
+        jxl.extend((8 + 4 + exif_len as u32).to_be_bytes());
+        jxl.extend(b"Exif");
+        jxl.extend(0u32.to_be_bytes());
+        jxl.extend(&img2[exif_start..exif_start + exif_len]);
+        jxl.extend((8 + XMP.len() as u32).to_be_bytes());
+        jxl.extend(b"xml ");
+        jxl.extend(XMP.as_bytes());
+        jxl.extend(b"\0\0\0\x0Ajxlc\xFF\x0A");
+        let meta = read_metadata_from(&mut Cursor::new(jxl)).unwrap();
+        // EXIF wins over the embedded XMP
+        assert_eq!(meta.lens.as_deref(), Some("OLYMPUS M.12-40mm F2.8"));
+        assert_eq!(meta.date_taken, Some(date_time("2023-05-03 12:30:00")));
+        assert_eq!(meta.keywords.len(), 2);
+
+        // a garbage largesize must not seek backwards forever
+        let mut jxl = JXL_CONTAINER.to_vec();
+        jxl.extend(b"\0\0\0\x01free");
+        jxl.extend((u64::MAX - 7).to_be_bytes());
+        jxl.extend(b"\0\0\0\x0Ajxlc\xFF\x0A");
+        let error = read_metadata_from(&mut Cursor::new(jxl)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sidecar_wins() {
+        let dir = std::env::temp_dir().join(format!("vienik-metadata-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let photo = dir.join("img2.jpg");
+        std::fs::copy("../sample-data/exif/img2.jpg", &photo).unwrap();
+        assert_eq!(find_sidecar(&photo), None);
+        std::fs::write(dir.join("img2.xmp"), XMP).unwrap();
+        assert_eq!(find_sidecar(&photo), Some(dir.join("img2.xmp")));
+
+        let meta = read_metadata_with_sidecar(&photo).unwrap();
+        assert_eq!(meta.lens.as_deref(), Some("Sidecar Lens"));
+        assert_eq!(meta.date_taken, Some(date_time("2023-05-03 12:31:00")));
+        assert_eq!(meta.camera_model.as_deref(), Some("E-M5MarkII"));
+        assert_eq!(meta.keywords.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -23,6 +23,9 @@ pub struct FileListItem {
     /// Turns out that modification date is the only attribute preserved when
     /// copying files.
     pub mod_date: Option<chrono::NaiveDate>,
+    /// EXIF or movie header capture date, wrong dates of copies and edits
+    /// are the reason to prefer it over `mod_date` when present.
+    pub taken_date: Option<chrono::NaiveDate>,
     pub size: Option<u64>,
 }
 
@@ -32,6 +35,7 @@ impl FileListItem {
             path,
             tags: None,
             mod_date: None,
+            taken_date: None,
             size: None,
         }
     }
@@ -41,10 +45,16 @@ impl FileListItem {
             path,
             tags: Some(tags.into_iter().collect::<HashSet<String>>()),
             mod_date: None,
+            taken_date: None,
             size: None,
         }
     }
 
+    /// Capture date, or the modification date if unknown.
+    pub fn taken_or_mod_date(&self) -> Option<chrono::NaiveDate> {
+        self.taken_date.or(self.mod_date)
+    }
+
     pub fn tags_count(&self) -> usize {
         match &self.tags {
             Some(tags) => tags.len(),
@@ -214,6 +224,9 @@ impl SortingCommand {
     pub fn compare(&self, a: &FileListItem, b: &FileListItem) -> Ordering {
         match self.criteria {
             SortingCriteria::Date => Self::compare_dates(&a.mod_date, &b.mod_date),
+            SortingCriteria::TakenDate => {
+                Self::compare_dates(&a.taken_or_mod_date(), &b.taken_or_mod_date())
+            }
             SortingCriteria::PathName => a.path.cmp(&b.path),
             SortingCriteria::Size => Self::compare_size(&a.size, &b.size),
             SortingCriteria::TagsCount => a.tags_count().cmp(&b.tags_count()),
@@ -225,6 +238,8 @@ impl SortingCommand {
 pub enum SortingCriteria {
     PathName,
     Date,
+    /// capture date, falls back to the modification date
+    TakenDate,
     Size,
     TagsCount,
 }
@@ -0,0 +1,321 @@
+//! Properties of XMP packets, embedded into files or sidecars.
+use crate::exif::gps_coordinate;
+use crate::metadata::{GpsPosition, Metadata};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::name::ResolveResult;
+use quick_xml::NsReader;
+use std::collections::{BTreeSet, HashMap};
+
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+const LR_NS: &str = "http://ns.adobe.com/lightroom/1.0/";
+const XMP_NS: &str = "http://ns.adobe.com/xap/1.0/";
+const TIFF_NS: &str = "http://ns.adobe.com/tiff/1.0/";
+const EXIF_NS: &str = "http://ns.adobe.com/exif/1.0/";
+const EXIF_EX_NS: &str = "http://cipa.jp/exif/1.0/";
+const AUX_NS: &str = "http://ns.adobe.com/exif/1.0/aux/";
+const PHOTOSHOP_NS: &str = "http://ns.adobe.com/photoshop/1.0/";
+
+/// Expanded name -> values, a simple property has one value, a bag or a
+/// sequence has one per rdf:li.
+type Properties = HashMap<String, Vec<String>>;
+
+/// Namespace and local name as one string, as RDF names properties.
+fn expanded_name(ns: &ResolveResult, local_name: &[u8]) -> Option<String> {
+    match ns {
+        ResolveResult::Bound(ns) => Some(format!(
+            "{}{}",
+            String::from_utf8_lossy(ns.as_ref()),
+            String::from_utf8_lossy(local_name)
+        )),
+        _ => None,
+    }
+}
+
+fn is_rdf(name: &Option<String>, local_name: &str) -> bool {
+    name.as_deref()
+        .and_then(|name| name.strip_prefix(RDF_NS))
+        .map(|name| name == local_name)
+        .unwrap_or(false)
+}
+
+/// Simple properties written as rdf:Description attributes.
+fn read_attributes(
+    reader: &NsReader<&[u8]>,
+    element: &BytesStart,
+    properties: &mut Properties,
+) -> Result<(), quick_xml::Error> {
+    for attr in element.attributes() {
+        let attr = attr?;
+        let (ns, local_name) = reader.resolve_attribute(attr.key);
+        if let Some(name) = expanded_name(&ns, local_name.as_ref()) {
+            let value = attr.unescape_value()?.trim().to_owned();
+            if !value.is_empty() {
+                properties.entry(name).or_default().push(value);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Top level properties of all rdf:Description elements; structures are
+/// skipped.
+fn read_properties(xml: &str) -> Result<Properties, quick_xml::Error> {
+    let mut reader = NsReader::from_str(xml);
+    let mut properties = Properties::new();
+    // expanded names of the open elements
+    let mut open: Vec<Option<String>> = Vec::new();
+    let mut text = String::new();
+
+    loop {
+        match reader.read_resolved_event()? {
+            (ns, Event::Start(e)) => {
+                let name = expanded_name(&ns, e.local_name().as_ref());
+                if is_rdf(&name, "Description") {
+                    read_attributes(&reader, &e, &mut properties)?;
+                }
+                open.push(name);
+                text.clear();
+            }
+            (ns, Event::Empty(e)) => {
+                let name = expanded_name(&ns, e.local_name().as_ref());
+                if is_rdf(&name, "Description") {
+                    read_attributes(&reader, &e, &mut properties)?;
+                }
+                text.clear();
+            }
+            (_, Event::Text(e)) => text.push_str(&e.unescape()?),
+            (_, Event::End(_)) => {
+                let name = open.pop().flatten();
+                let value = text.trim();
+                let property = if is_rdf(&name, "li") {
+                    // the nearest non-RDF element, e.g. dc:subject of its rdf:Bag
+                    open.iter()
+                        .rev()
+                        .flatten()
+                        .find(|name| !name.starts_with(RDF_NS))
+                        .cloned()
+                } else if open
+                    .last()
+                    .is_some_and(|parent| is_rdf(parent, "Description"))
+                    && !name.as_deref().unwrap_or(RDF_NS).starts_with(RDF_NS)
+                {
+                    name
+                } else {
+                    None
+                };
+                if let (Some(property), false) = (property, value.is_empty()) {
+                    properties
+                        .entry(property)
+                        .or_default()
+                        .push(value.to_owned());
+                }
+                text.clear();
+            }
+            (_, Event::Eof) => break,
+            _ => (),
+        }
+    }
+    Ok(properties)
+}
+
+/// ISO 8601 as XMP writes it, with or without seconds or the time zone,
+/// or a date only. Converted to UTC if the time zone is known.
+pub fn parse_xmp_date(text: &str) -> Option<NaiveDateTime> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(text) {
+        return Some(date_time.naive_utc());
+    }
+    if let Ok(date_time) = DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M%:z") {
+        return Some(date_time.naive_utc());
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })
+}
+
+/// 'DDD,MM.mmk' or 'DDD,MM,SSk', k is one of N, S, E, W.
+pub fn parse_xmp_coordinate(text: &str) -> Option<f64> {
+    let reference = text.get(text.len().checked_sub(1)?..)?;
+    let dms = text[..text.len() - 1]
+        .split(',')
+        .map(|part| part.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    gps_coordinate(&dms, Some(reference))
+}
+
+/// '12345/10'
+fn parse_rational(text: &str) -> Option<f64> {
+    match text.split_once('/') {
+        Some((numerator, denominator)) => {
+            let denominator: f64 = denominator.trim().parse().ok()?;
+            if denominator == 0.0 {
+                None
+            } else {
+                Some(numerator.trim().parse::<f64>().ok()? / denominator)
+            }
+        }
+        None => text.trim().parse().ok(),
+    }
+}
+
+pub(crate) fn xmp_metadata(xml: &str) -> Result<Metadata, quick_xml::Error> {
+    let properties = read_properties(xml)?;
+    let first = |ns: &str, local_name: &str| {
+        properties
+            .get(&format!("{ns}{local_name}"))
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    };
+    let all = |ns: &str, local_name: &str| -> BTreeSet<String> {
+        properties
+            .get(&format!("{ns}{local_name}"))
+            .map(|values| values.iter().cloned().collect())
+            .unwrap_or_default()
+    };
+
+    let latitude = first(EXIF_NS, "GPSLatitude").and_then(parse_xmp_coordinate);
+    let longitude = first(EXIF_NS, "GPSLongitude").and_then(parse_xmp_coordinate);
+    let gps = match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude: first(EXIF_NS, "GPSAltitude")
+                .and_then(parse_rational)
+                .map(|altitude| match first(EXIF_NS, "GPSAltitudeRef") {
+                    // below the sea level
+                    Some("1") => -altitude,
+                    _ => altitude,
+                }),
+        }),
+        _ => None,
+    };
+
+    let uint = |value: &str| value.trim().parse::<u32>().ok();
+
+    Ok(Metadata {
+        camera_make: first(TIFF_NS, "Make").map(str::to_owned),
+        camera_model: first(TIFF_NS, "Model").map(str::to_owned),
+        lens: first(EXIF_EX_NS, "LensModel")
+            .or_else(|| first(AUX_NS, "Lens"))
+            .map(str::to_owned),
+        focal_length: first(EXIF_NS, "FocalLength").and_then(parse_rational),
+        exposure_time: first(EXIF_NS, "ExposureTime").and_then(parse_rational),
+        f_number: first(EXIF_NS, "FNumber").and_then(parse_rational),
+        iso: first(EXIF_EX_NS, "PhotographicSensitivity")
+            .or_else(|| first(EXIF_NS, "ISOSpeedRatings"))
+            .and_then(uint),
+        width: first(EXIF_NS, "PixelXDimension")
+            .or_else(|| first(TIFF_NS, "ImageWidth"))
+            .and_then(uint),
+        height: first(EXIF_NS, "PixelYDimension")
+            .or_else(|| first(TIFF_NS, "ImageLength"))
+            .and_then(uint),
+        date_taken: [
+            (EXIF_NS, "DateTimeOriginal"),
+            (PHOTOSHOP_NS, "DateCreated"),
+            (XMP_NS, "CreateDate"),
+        ]
+        .iter()
+        .find_map(|(ns, local_name)| first(ns, local_name).and_then(parse_xmp_date)),
+        date_modified: first(XMP_NS, "ModifyDate").and_then(parse_xmp_date),
+        orientation: first(TIFF_NS, "Orientation").and_then(|o| o.parse().ok()),
+        keywords: all(DC_NS, "subject"),
+        hierarchical_keywords: all(LR_NS, "hierarchicalSubject"),
+        gps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn xmp_properties() {
+        let xml = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:tiff="http://ns.adobe.com/tiff/1.0/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    xmlns:aux="http://ns.adobe.com/exif/1.0/aux/"
+    tiff:Make="OLYMPUS CORPORATION"
+    xmp:CreateDate="2023-05-03T14:30:00"
+    xmp:ModifyDate="2023-06-01T10:00:00.25Z"
+    exif:GPSLatitude="43,42.3N"
+    exif:GPSLongitude="7,15,36W"
+    exif:GPSAltitude="105/10"
+    exif:GPSAltitudeRef="1"
+    exif:ExposureTime="1/250"
+    exif:FNumber="28/10"
+    exif:PixelXDimension="4608"
+    tiff:ImageLength="3456">
+   <tiff:Model>E-M5MarkII</tiff:Model>
+   <tiff:Orientation>8</tiff:Orientation>
+   <aux:Lens>OLYMPUS M.12-40mm F2.8</aux:Lens>
+   <exif:FocalLength>12/1</exif:FocalLength>
+   <exif:ISOSpeedRatings>
+    <rdf:Seq><rdf:li>400</rdf:li></rdf:Seq>
+   </exif:ISOSpeedRatings>
+   <exif:Flash rdf:parseType="Resource">
+    <exif:Fired>False</exif:Fired>
+   </exif:Flash>
+   <dc:subject xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <rdf:Bag><rdf:li>sea &amp; sun</rdf:li><rdf:li>nice</rdf:li></rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+        let meta = xmp_metadata(xml).unwrap();
+        assert_eq!(meta.camera_make.as_deref(), Some("OLYMPUS CORPORATION"));
+        assert_eq!(meta.camera_model.as_deref(), Some("E-M5MarkII"));
+        assert_eq!(meta.lens.as_deref(), Some("OLYMPUS M.12-40mm F2.8"));
+        assert_eq!(meta.orientation, Some(8));
+        assert_eq!(meta.focal_length, Some(12.0));
+        assert_eq!(meta.exposure_time, Some(0.004));
+        assert_eq!(meta.f_number, Some(2.8));
+        assert_eq!(meta.iso, Some(400));
+        assert_eq!((meta.width, meta.height), (Some(4608), Some(3456)));
+        assert_eq!(meta.date_taken, Some(date_time("2023-05-03 14:30:00")));
+        assert_eq!(
+            meta.date_modified
+                .map(|d| d.and_utc().timestamp_millis() % 1000),
+            Some(250)
+        );
+        assert_eq!(
+            meta.keywords,
+            BTreeSet::from(["nice".to_owned(), "sea & sun".to_owned()])
+        );
+        assert!(meta.hierarchical_keywords.is_empty());
+        let gps = meta.gps.unwrap();
+        assert!((gps.latitude - 43.705).abs() < 1e-9);
+        assert!((gps.longitude + 7.26).abs() < 1e-9);
+        assert_eq!(gps.altitude, Some(-10.5));
+        assert!(read_properties("<a></b>").is_err());
+    }
+
+    #[test]
+    fn xmp_dates() {
+        let expected = Some(date_time("2023-05-03 12:30:00"));
+        assert_eq!(parse_xmp_date("2023-05-03T14:30:00+02:00"), expected);
+        assert_eq!(parse_xmp_date("2023-05-03T14:30+02:00"), expected);
+        assert_eq!(parse_xmp_date("2023-05-03T12:30:00Z"), expected);
+        assert_eq!(parse_xmp_date("2023-05-03T12:30"), expected);
+        assert_eq!(
+            parse_xmp_date("2023-05-03"),
+            Some(date_time("2023-05-03 00:00:00"))
+        );
+        assert_eq!(parse_xmp_date("yesterday"), None);
+        assert!((parse_xmp_coordinate("43,42,18S").unwrap() + 43.705).abs() < 1e-9);
+        assert_eq!(parse_xmp_coordinate("N"), None);
+    }
+}